@@ -0,0 +1,110 @@
+//! Times the closure expansion of `rcomp!` against the `loops` modifier
+//! and hand-written loops, at a few nesting depths. This needs no extra
+//! dependencies; run it with `cargo bench --bench nesting`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rustcomp::rcomp;
+
+const ROUNDS: u32 = 50;
+
+/// Runs `f` a few times to warm up, then returns the fastest of `ROUNDS`
+/// runs, which is the least noisy number on a busy machine.
+fn time<T>(mut f: impl FnMut() -> T) -> Duration {
+    for _ in 0..3 {
+        black_box(f());
+    }
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn report(name: &str, closures: Duration, loops: Duration, by_hand: Duration) {
+    println!(
+        "{name:<8} closures {:>10.3?}   loops {:>10.3?}   by hand {:>10.3?}   closures/loops {:.2}x",
+        closures,
+        loops,
+        by_hand,
+        closures.as_secs_f64() / loops.as_secs_f64(),
+    );
+}
+
+fn main() {
+    let n = black_box(200_u64);
+    let rows = (0..n)
+        .map(|i| (0..n).map(|j| i * n + j).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    report(
+        "depth 2",
+        time(|| rcomp![Vec<_>; for row in &rows, x in row => x * 3, if x % 7 != 0]),
+        time(|| rcomp![loops; Vec<_>; for row in &rows, x in row => x * 3, if x % 7 != 0]),
+        time(|| {
+            let mut v = Vec::new();
+            for row in &rows {
+                for x in row {
+                    if x % 7 != 0 {
+                        v.push(x * 3);
+                    }
+                }
+            }
+            v
+        }),
+    );
+
+    let m = black_box(40_u64);
+    report(
+        "depth 3",
+        time(
+            || rcomp![move; Vec<_>; for a in 0..m, b in 0..m, c in 0..m => a * b + c, if (a + b + c) % 3 == 0],
+        ),
+        time(
+            || rcomp![loops; Vec<_>; for a in 0..m, b in 0..m, c in 0..m => a * b + c, if (a + b + c) % 3 == 0],
+        ),
+        time(|| {
+            let mut v = Vec::new();
+            for a in 0..m {
+                for b in 0..m {
+                    for c in 0..m {
+                        if (a + b + c) % 3 == 0 {
+                            v.push(a * b + c);
+                        }
+                    }
+                }
+            }
+            v
+        }),
+    );
+
+    let k = black_box(14_u64);
+    report(
+        "depth 4",
+        time(
+            || rcomp![move; Vec<_>; for a in 0..k, b in 0..k, c in 0..k, d in 0..k => a ^ b ^ c ^ d, if a + d != b + c],
+        ),
+        time(
+            || rcomp![loops; Vec<_>; for a in 0..k, b in 0..k, c in 0..k, d in 0..k => a ^ b ^ c ^ d, if a + d != b + c],
+        ),
+        time(|| {
+            let mut v = Vec::new();
+            for a in 0..k {
+                for b in 0..k {
+                    for c in 0..k {
+                        for d in 0..k {
+                            if a + d != b + c {
+                                v.push(a ^ b ^ c ^ d);
+                            }
+                        }
+                    }
+                }
+            }
+            v
+        }),
+    );
+}
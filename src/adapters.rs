@@ -0,0 +1,1498 @@
+/*!
+Iterator adapters used by the trailing clauses of the [`rcomp!`](crate::rcomp)
+macro, e.g. `rcomp![for x in v => x, unique by |x| x.id]`.
+
+Each clause has a function here that takes the comprehension's iterator
+and returns the adapted one. Like the [`terminals`](crate::terminals),
+they work on any iterator, not just comprehensions.
+*/
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+
+/// Adapters that are defined outside of this crate. A value that
+/// implements this trait can be used as a clause with `via <adapter>`,
+/// which hands it the comprehension's iterator and carries on with the
+/// iterator it returns, so adapters from other crates don't need a method
+/// chain around the macro.
+///
+/// It's implemented for closures that take the iterator and return
+/// another one, for one-off adapters.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::adapters::CompAdapter;
+///
+/// /// Keeps every `n`th item.
+/// struct Every(usize);
+///
+/// impl<I: Iterator> CompAdapter<I> for Every {
+///     type Output = std::iter::StepBy<I>;
+///
+///     fn adapt(self, iter: I) -> Self::Output {
+///         iter.step_by(self.0)
+///     }
+/// }
+///
+/// let v = rcomp![Vec<_>; for x in 0..10 => x, via Every(3), via |it| it.skip(1)];
+/// assert_eq!(v, [3, 6, 9]);
+/// ```
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't adapt a comprehension's `{I}`",
+    label = "this isn't an adapter for `{I}`",
+    note = "an adapter has to implement `CompAdapter<{I}>`"
+)]
+pub trait CompAdapter<I: Iterator> {
+    /// The adapted iterator.
+    type Output: Iterator;
+
+    /// Adapts the comprehension's iterator.
+    fn adapt(self, iter: I) -> Self::Output;
+}
+
+impl<I, F, J> CompAdapter<I> for F
+where
+    I: Iterator,
+    F: FnOnce(I) -> J,
+    J: Iterator,
+{
+    type Output = J;
+
+    fn adapt(self, iter: I) -> J {
+        self(iter)
+    }
+}
+
+/// Adapts the iterator with a closure. This is what `via <closure>`
+/// expands to, since a closure that's only known to be a [`CompAdapter`]
+/// doesn't know what its argument is.
+pub fn adapt_with<I, J, F>(iter: I, f: F) -> J
+where
+    I: IntoIterator,
+    F: FnOnce(I::IntoIter) -> J,
+    J: Iterator,
+{
+    f(iter.into_iter())
+}
+
+/// An iterator that only yields the first item for each key. See
+/// [`unique_by`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct UniqueBy<I, F, K> {
+    iter: I,
+    key_fn: F,
+    seen: HashSet<K>,
+}
+
+impl<I, F, K> Iterator for UniqueBy<I, F, K>
+where
+    I: Iterator,
+    F: FnMut(&I::Item) -> K,
+    K: Hash + Eq,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Self { iter, key_fn, seen } = self;
+        iter.find(|item| seen.insert(key_fn(item)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        // the first item is always unique, but there's no telling after that
+        (usize::from(lower > 0), upper)
+    }
+}
+
+/// Keeps only the first item for each key returned by `key_fn`, which is
+/// given a reference to the item. Every key seen so far is kept in a
+/// `HashSet`, so unlike `Vec::dedup` this removes duplicates that _aren't_
+/// next to each other.
+///
+/// This is what the `unique by <key_fn>` clause expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// struct Event {
+///     id: u32,
+///     name: &'static str,
+/// }
+///
+/// let events = vec![
+///     Event { id: 1, name: "first" },
+///     Event { id: 2, name: "second" },
+///     Event { id: 1, name: "first again" },
+/// ];
+/// let v = rcomp![Vec<_>; for e in &events => e, unique by |e| e.id];
+/// assert_eq!(v.iter().map(|e| e.name).collect::<Vec<_>>(), ["first", "second"]);
+/// ```
+pub fn unique_by<I, F, K>(iter: I, key_fn: F) -> UniqueBy<I::IntoIter, F, K>
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item) -> K,
+    K: Hash + Eq,
+{
+    UniqueBy {
+        iter: iter.into_iter(),
+        key_fn,
+        seen: HashSet::new(),
+    }
+}
+
+/// An iterator that yields at most `n` items per key. See [`limit_per`]
+/// for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct LimitPer<I, F, K> {
+    iter: I,
+    key_fn: F,
+    n: usize,
+    counts: HashMap<K, usize>,
+}
+
+impl<I, F, K> Iterator for LimitPer<I, F, K>
+where
+    I: Iterator,
+    F: FnMut(&I::Item) -> K,
+    K: Hash + Eq,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Self {
+            iter,
+            key_fn,
+            n,
+            counts,
+        } = self;
+        iter.find(|item| {
+            let count = counts.entry(key_fn(item)).or_insert(0);
+            *count += 1;
+            *count <= *n
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (_, upper) = self.iter.size_hint();
+        (0, upper)
+    }
+}
+
+/// Keeps only the first `n` items for each key returned by `key_fn`, which
+/// is given a reference to the item. `unique by` is the same as a limit of
+/// one per key.
+///
+/// This is what the `limit <n> per <key_fn>` clause expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let events = [("ann", 1), ("bo", 2), ("ann", 3), ("ann", 4), ("bo", 5)];
+/// let v = rcomp![Vec<_>; for e in events => e, limit 2 per |(user, _)| *user];
+/// assert_eq!(v, [("ann", 1), ("bo", 2), ("ann", 3), ("bo", 5)]);
+/// ```
+pub fn limit_per<I, F, K>(iter: I, n: usize, key_fn: F) -> LimitPer<I::IntoIter, F, K>
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item) -> K,
+    K: Hash + Eq,
+{
+    LimitPer {
+        iter: iter.into_iter(),
+        key_fn,
+        n,
+        counts: HashMap::new(),
+    }
+}
+
+/// An iterator that caches the results of its mapping function. See
+/// [`memo_map`] for more information.
+#[derive(Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct MemoMap<I: Iterator, F, V> {
+    iter: I,
+    f: F,
+    cache: HashMap<I::Item, V>,
+}
+
+impl<I, F, V> std::fmt::Debug for MemoMap<I, F, V>
+where
+    I: Iterator + std::fmt::Debug,
+    I::Item: std::fmt::Debug,
+    V: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoMap")
+            .field("iter", &self.iter)
+            .field("cache", &self.cache)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<I, F, V> Iterator for MemoMap<I, F, V>
+where
+    I: Iterator,
+    I::Item: Hash + Eq + Clone,
+    F: FnMut(I::Item) -> V,
+    V: Clone,
+{
+    type Item = V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Self { iter, f, cache } = self;
+        let item = iter.next()?;
+        Some(
+            cache
+                .entry(item)
+                .or_insert_with_key(|k| f(k.clone()))
+                .clone(),
+        )
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+/// Maps each item with `f`, but only calls `f` the first time an item is
+/// seen. The result is cached and cloned for every repeat of that item, so
+/// `f` should be a pure function of the item.
+///
+/// This is what `=> memo <mapper>` expands to, with the guard folded into
+/// `f`. In a nested comprehension, the innermost `for-in` clause gets a
+/// fresh cache for every outer item.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// # use std::cell::Cell;
+/// let calls = Cell::new(0);
+/// let expensive = |x: u64| {
+///     calls.set(calls.get() + 1);
+///     x.pow(3)
+/// };
+/// let v = rcomp![Vec<_>; for x in [2, 3, 2, 2, 3] => memo expensive(x)];
+/// assert_eq!(v, vec![8, 27, 8, 8, 27]);
+/// assert_eq!(calls.get(), 2);
+/// ```
+pub fn memo_map<I, F, V>(iter: I, f: F) -> MemoMap<I::IntoIter, F, V>
+where
+    I: IntoIterator,
+    I::Item: Hash + Eq + Clone,
+    F: FnMut(I::Item) -> V,
+    V: Clone,
+{
+    MemoMap {
+        iter: iter.into_iter(),
+        f,
+        cache: HashMap::new(),
+    }
+}
+
+/// Maps every item of a slice with `f`, eight at a time. The work is done
+/// up front, as a plain loop over fixed-size chunks with no guard and no
+/// closures in between, which gives the optimizer a much better chance of
+/// vectorizing a numeric mapper than the usual chain of iterator adapters.
+///
+/// This is what `rcomp![simd; ...]` expands to. `f` is given a reference to
+/// each item, as if iterating over `&slice`.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let xs = (0..20).map(f64::from).collect::<Vec<_>>();
+/// let v = rcomp![simd; Vec<_>; for x in &xs => x * 0.5 + 1.0];
+/// assert_eq!(v.len(), 20);
+/// assert_eq!(v[19], 10.5);
+/// ```
+pub fn simd_map<S, T, U, F>(slice: &S, mut f: F) -> std::vec::IntoIter<U>
+where
+    S: AsRef<[T]> + ?Sized,
+    F: FnMut(&T) -> U,
+{
+    const LANES: usize = 8;
+
+    let slice = slice.as_ref();
+    let mut out = Vec::with_capacity(slice.len());
+    let chunks = slice.chunks_exact(LANES);
+    let rest = chunks.remainder();
+    for chunk in chunks {
+        out.extend(std::array::from_fn::<U, LANES, _>(|i| f(&chunk[i])));
+    }
+    out.extend(rest.iter().map(f));
+    out.into_iter()
+}
+
+/// The items a block mapper yields for one input. A block mapper can call
+/// `emit!(value)` any number of times before it finishes, and the value of
+/// the block comes last, unless it ends with `skip!()`. This is created by
+/// the macro and shouldn't usually be used directly.
+///
+/// The first item is kept inline, so a block that yields one item, which is
+/// the usual case, doesn't allocate.
+#[derive(Debug, Clone)]
+pub struct Emits<T> {
+    first: Option<T>,
+    rest: Vec<T>,
+}
+
+impl<T> Emits<T> {
+    /// Creates an empty buffer.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            first: None,
+            rest: Vec::new(),
+        }
+    }
+
+    /// Adds an item after the ones already emitted.
+    pub fn push(&mut self, item: T) {
+        if self.first.is_none() && self.rest.is_empty() {
+            self.first = Some(item);
+        } else {
+            self.rest.push(item);
+        }
+    }
+}
+
+impl<T> Default for Emits<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> IntoIterator for Emits<T> {
+    type Item = T;
+    type IntoIter = std::iter::Chain<std::option::IntoIter<T>, std::vec::IntoIter<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.first.into_iter().chain(self.rest)
+    }
+}
+
+/// What to do with the errors in an iterator of `Result`s. See
+/// [`on_error`] for more information.
+#[derive(Debug)]
+pub enum OnError<'a, E> {
+    /// Drop the errors and carry on.
+    Skip,
+    /// Push the errors into the given `Vec` and carry on.
+    Collect(&'a mut Vec<E>),
+    /// Stop at the first error, storing it in the given `Option`.
+    Abort(&'a mut Option<E>),
+}
+
+/// An iterator that unwraps `Ok` items and handles `Err` items according to
+/// an [`OnError`] policy. See [`on_error`] for more information.
+#[derive(Debug)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct HandleErrors<'a, I, E> {
+    iter: I,
+    policy: OnError<'a, E>,
+    done: bool,
+}
+
+impl<I, T, E> Iterator for HandleErrors<'_, I, E>
+where
+    I: Iterator<Item = Result<T, E>>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match self.iter.next()? {
+                Ok(item) => return Some(item),
+                Err(e) => match &mut self.policy {
+                    OnError::Skip => {}
+                    OnError::Collect(errors) => errors.push(e),
+                    OnError::Abort(error) => {
+                        **error = Some(e);
+                        self.done = true;
+                    }
+                },
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.done {
+            (0, Some(0))
+        } else {
+            (0, self.iter.size_hint().1)
+        }
+    }
+}
+
+/// Unwraps the `Ok` items of an iterator of `Result`s, handling the errors
+/// according to `policy`:
+///
+/// - [`OnError::Skip`] drops them.
+/// - [`OnError::Collect`] pushes them into a `Vec` for later.
+/// - [`OnError::Abort`] stops at the first one and keeps it in an `Option`.
+///
+/// This is mainly used by the fallible sources, like
+/// [`lines`](crate::sources::lines), and by the `on error` clause, which
+/// handles the errors of a mapper that returns `Result`s.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::adapters::{on_error, OnError};
+///
+/// let inputs = ["1", "two", "3", "four"];
+/// let mut errors = Vec::new();
+/// let v = rcomp![Vec<_>; for n in on_error(inputs.map(str::parse::<u32>), OnError::Collect(&mut errors)) => n];
+/// assert_eq!(v, vec![1, 3]);
+/// assert_eq!(errors.len(), 2);
+///
+/// let mut error = None;
+/// let v = rcomp![Vec<_>; for n in on_error(inputs.map(str::parse::<u32>), OnError::Abort(&mut error)) => n];
+/// assert_eq!(v, vec![1]);
+/// assert!(error.is_some());
+/// ```
+pub fn on_error<I, T, E>(iter: I, policy: OnError<'_, E>) -> HandleErrors<'_, I::IntoIter, E>
+where
+    I: IntoIterator<Item = Result<T, E>>,
+{
+    HandleErrors {
+        iter: iter.into_iter(),
+        policy,
+        done: false,
+    }
+}
+
+/// An iterator that yields a separator between the items of another. See
+/// [`intersperse`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Intersperse<I: Iterator> {
+    iter: std::iter::Peekable<I>,
+    sep: I::Item,
+    needs_sep: bool,
+}
+
+impl<I> Iterator for Intersperse<I>
+where
+    I: Iterator,
+    I::Item: Clone,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.needs_sep && self.iter.peek().is_some() {
+            self.needs_sep = false;
+            Some(self.sep.clone())
+        } else {
+            self.needs_sep = true;
+            self.iter.next()
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        // every item but the first has a separator in front of it
+        let with_seps = |n: usize| {
+            let seps = if self.needs_sep {
+                n
+            } else {
+                n.saturating_sub(1)
+            };
+            n.checked_add(seps)
+        };
+        (
+            with_seps(lower).unwrap_or(usize::MAX),
+            upper.and_then(with_seps),
+        )
+    }
+}
+
+/// Yields a clone of `sep` between every two items, like the unstable
+/// `Iterator::intersperse`. Nothing is added before the first item or after
+/// the last one.
+///
+/// This is what the `intersperse <sep>` clause expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![String; for x in 1..=3 => x.to_string(), intersperse ", ".to_owned()];
+/// assert_eq!(v, "1, 2, 3");
+/// ```
+pub fn intersperse<I>(iter: I, sep: I::Item) -> Intersperse<I::IntoIter>
+where
+    I: IntoIterator,
+    I::Item: Clone,
+{
+    Intersperse {
+        iter: iter.into_iter().peekable(),
+        sep,
+        needs_sep: false,
+    }
+}
+
+/// An iterator that merges adjacent items. See [`coalesce`] for more
+/// information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Coalesce<I: Iterator, F> {
+    iter: I,
+    f: F,
+    last: Option<I::Item>,
+}
+
+impl<I, F> Iterator for Coalesce<I, F>
+where
+    I: Iterator,
+    F: FnMut(I::Item, I::Item) -> Result<I::Item, (I::Item, I::Item)>,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Self { iter, f, last } = self;
+        let mut acc = last.take().or_else(|| iter.next())?;
+        for item in iter.by_ref() {
+            match f(acc, item) {
+                Ok(merged) => acc = merged,
+                Err((done, next)) => {
+                    *last = Some(next);
+                    return Some(done);
+                }
+            }
+        }
+        Some(acc)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        let pending = usize::from(self.last.is_some());
+        // everything could be merged into one item
+        (
+            usize::from(lower > 0 || pending > 0),
+            upper.and_then(|n| n.checked_add(pending)),
+        )
+    }
+}
+
+/// Merges adjacent items with `f`. Each item is given to `f` along with the
+/// one after it; returning `Ok(merged)` replaces both with `merged`, which
+/// is then merged with the next item, and returning `Err((a, b))` yields `a`
+/// and carries on from `b`. This is the same as `coalesce` in `itertools`.
+///
+/// This is what the `coalesce <f>` clause expands to.
+///
+/// # Example
+///
+/// Run-length encoding:
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![Vec<_>; for c in "aaabccdd".chars() => (c, 1),
+///     coalesce |(a, n), (b, m)| if a == b { Ok((a, n + m)) } else { Err(((a, n), (b, m))) }
+/// ];
+/// assert_eq!(v, vec![('a', 3), ('b', 1), ('c', 2), ('d', 2)]);
+/// ```
+pub fn coalesce<I, F>(iter: I, f: F) -> Coalesce<I::IntoIter, F>
+where
+    I: IntoIterator,
+    F: FnMut(I::Item, I::Item) -> Result<I::Item, (I::Item, I::Item)>,
+{
+    Coalesce {
+        iter: iter.into_iter(),
+        f,
+        last: None,
+    }
+}
+
+/// An iterator over the runs of adjacent items with the same key. See
+/// [`chunk_by`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct ChunkBy<I: Iterator, F, K> {
+    iter: I,
+    key_fn: F,
+    next: Option<(K, I::Item)>,
+}
+
+impl<I, F, K> Iterator for ChunkBy<I, F, K>
+where
+    I: Iterator,
+    F: FnMut(&I::Item) -> K,
+    K: PartialEq,
+{
+    type Item = (K, Vec<I::Item>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Self { iter, key_fn, next } = self;
+        let (key, first) = next.take().or_else(|| {
+            let item = iter.next()?;
+            Some((key_fn(&item), item))
+        })?;
+        let mut run = vec![first];
+        for item in iter.by_ref() {
+            let k = key_fn(&item);
+            if k != key {
+                *next = Some((k, item));
+                break;
+            }
+            run.push(item);
+        }
+        Some((key, run))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        let pending = usize::from(self.next.is_some());
+        // everything could have the same key
+        (
+            usize::from(lower > 0 || pending > 0),
+            upper.and_then(|n| n.checked_add(pending)),
+        )
+    }
+}
+
+/// Splits the stream into runs of adjacent items that have the same key,
+/// yielding each key along with its run. Unlike the `group by` terminal,
+/// the items aren't gathered into a map, so a key comes up again if its
+/// items aren't next to each other. That's the point for time-ordered
+/// data, like the days in a log. `key_fn` is given a reference to the item.
+///
+/// This is what the `chunk by <key_fn>` clause expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let log = [(1, "boot"), (1, "login"), (2, "login"), (1, "clock skew")];
+/// let days = rcomp![Vec<_>; for entry in log => entry, chunk by |(day, _)| *day];
+/// assert_eq!(days, [
+///     (1, vec![(1, "boot"), (1, "login")]),
+///     (2, vec![(2, "login")]),
+///     (1, vec![(1, "clock skew")]),
+/// ]);
+/// ```
+pub fn chunk_by<I, F, K>(iter: I, key_fn: F) -> ChunkBy<I::IntoIter, F, K>
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item) -> K,
+    K: PartialEq,
+{
+    ChunkBy {
+        iter: iter.into_iter(),
+        key_fn,
+        next: None,
+    }
+}
+
+/// An iterator over the segments between delimiter items. See
+/// [`split_when`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct SplitWhen<I, F> {
+    iter: I,
+    pred: F,
+}
+
+impl<I, F> Iterator for SplitWhen<I, F>
+where
+    I: Iterator,
+    F: FnMut(&I::Item) -> bool,
+{
+    type Item = Vec<I::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Self { iter, pred } = self;
+        let mut segment = Vec::new();
+        for item in iter.by_ref() {
+            if !pred(&item) {
+                segment.push(item);
+            } else if !segment.is_empty() {
+                return Some(segment);
+            }
+        }
+        (!segment.is_empty()).then_some(segment)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // every other item could be a delimiter
+        let (_, upper) = self.iter.size_hint();
+        (0, upper.map(|n| n.div_ceil(2)))
+    }
+}
+
+/// Splits the stream into segments wherever `pred` returns `true`, yielding
+/// each segment as a `Vec`. The items that `pred` matches are delimiters:
+/// they aren't part of any segment, and since there's nothing between
+/// delimiters that are next to each other (or at either end), no empty
+/// segments are yielded. `pred` is given a reference to the item.
+///
+/// This is what the `split when <pred>` clause expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let packets = [0xff, 1, 2, 0xff, 0xff, 3, 0xff];
+/// let v = rcomp![Vec<_>; for b in packets => b, split when |b| *b == 0xff];
+/// assert_eq!(v, [vec![1, 2], vec![3]]);
+/// ```
+pub fn split_when<I, F>(iter: I, pred: F) -> SplitWhen<I::IntoIter, F>
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item) -> bool,
+{
+    SplitWhen {
+        iter: iter.into_iter(),
+        pred,
+    }
+}
+
+/// An iterator that's padded to a minimum length. See [`pad_to`] for more
+/// information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct PadTo<I, F> {
+    iter: I,
+    fill: F,
+    remaining: usize,
+}
+
+impl<I, F> Iterator for PadTo<I, F>
+where
+    I: Iterator,
+    F: FnMut() -> I::Item,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = match self.iter.next() {
+            Some(item) => item,
+            None if self.remaining > 0 => (self.fill)(),
+            None => return None,
+        };
+        self.remaining = self.remaining.saturating_sub(1);
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        (
+            lower.max(self.remaining),
+            upper.map(|n| n.max(self.remaining)),
+        )
+    }
+}
+
+/// Yields every item, then calls `fill` for more until at least `n` items
+/// have been yielded. Iterators that are already long enough are left
+/// alone.
+///
+/// This is what the `pad to <n> [with <fill>]` clause expands to, where
+/// `fill` evaluates the fill expression (or `Default::default`) again for
+/// every padded item.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let row = rcomp![Vec<_>; for s in ["a", "b"] => s.to_owned(), pad to 4 with "-".to_owned()];
+/// assert_eq!(row, ["a", "b", "-", "-"]);
+/// let frame = rcomp![Vec<f32>; for x in [0.5, 0.25] => x, pad to 3];
+/// assert_eq!(frame, [0.5, 0.25, 0.0]);
+/// ```
+pub fn pad_to<I, F>(iter: I, n: usize, fill: F) -> PadTo<I::IntoIter, F>
+where
+    I: IntoIterator,
+    F: FnMut() -> I::Item,
+{
+    PadTo {
+        iter: iter.into_iter(),
+        fill,
+        remaining: n,
+    }
+}
+
+/// An iterator over an aggregate of each sliding window of another. See
+/// [`rolling`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Rolling<I: Iterator, F> {
+    iter: I,
+    f: F,
+    n: usize,
+    buf: Vec<I::Item>,
+}
+
+impl<I, F, U> Iterator for Rolling<I, F>
+where
+    I: Iterator,
+    F: FnMut(&[I::Item]) -> U,
+{
+    type Item = U;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Self { iter, f, n, buf } = self;
+        loop {
+            // the buffer holds up to two windows' worth of items, so the
+            // window is always contiguous and the old items only have to
+            // be shifted out once every `n` items
+            if buf.len() == *n * 2 {
+                buf.drain(..=*n);
+            }
+            buf.push(iter.next()?);
+            if buf.len() >= *n {
+                return Some(f(&buf[buf.len() - *n..]));
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        let missing = (self.n - 1).saturating_sub(self.buf.len());
+        (
+            lower.saturating_sub(missing),
+            upper.map(|n| n.saturating_sub(missing)),
+        )
+    }
+}
+
+/// Calls `f` on every window of `n` consecutive items, like
+/// [`slice::windows`], and yields the results. The windows overlap, so the
+/// aggregate of each new item and the `n - 1` before it comes out. If there
+/// are fewer than `n` items, nothing is yielded.
+///
+/// The windows share one buffer, so there's no allocation per window. For
+/// sums and means, [`rolling_sum`] and [`rolling_mean`] are faster still.
+///
+/// This is what the `rolling <n>, <f>` clause expands to.
+///
+/// # Panics
+///
+/// Panics if `n` is zero.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let prices = [3, 7, 2, 9, 4];
+/// let highs = rcomp![Vec<_>; for p in prices => p, rolling 3, |w| *w.iter().max().unwrap()];
+/// assert_eq!(highs, vec![7, 9, 9]);
+/// ```
+pub fn rolling<I, F, U>(iter: I, n: usize, f: F) -> Rolling<I::IntoIter, F>
+where
+    I: IntoIterator,
+    F: FnMut(&[I::Item]) -> U,
+{
+    assert!(n > 0, "a rolling window needs at least one item");
+    Rolling {
+        iter: iter.into_iter(),
+        f,
+        n,
+        buf: Vec::new(),
+    }
+}
+
+/// Numbers that [`rolling_sum`] can keep a running sum of. This is
+/// implemented for all of the primitive integers and floats.
+///
+/// Integers wrap around while the window slides, so each window's sum comes
+/// out exact as long as it fits in the type, even when a partial sum on the
+/// way there wouldn't.
+pub trait WindowSum: Copy {
+    /// The sum of no items.
+    const ZERO: Self;
+
+    /// Adds `rhs`, wrapping around on overflow for integers.
+    #[must_use]
+    fn window_add(self, rhs: Self) -> Self;
+
+    /// Subtracts `rhs`, wrapping around on overflow for integers.
+    #[must_use]
+    fn window_sub(self, rhs: Self) -> Self;
+}
+
+/// Numbers that [`rolling_mean`] can average. This is implemented for all
+/// of the primitive integers and floats, including the ones too wide for
+/// `Into<f64>`, which lose precision the same way an `as` cast does.
+pub trait ToF64: Copy {
+    /// Converts the number to the nearest `f64`.
+    fn to_f64(self) -> f64;
+}
+
+macro_rules! impl_window_int {
+    ($($t:ty),+) => {$(
+        impl WindowSum for $t {
+            const ZERO: Self = 0;
+
+            fn window_add(self, rhs: Self) -> Self {
+                self.wrapping_add(rhs)
+            }
+
+            fn window_sub(self, rhs: Self) -> Self {
+                self.wrapping_sub(rhs)
+            }
+        }
+
+        impl ToF64 for $t {
+            #[allow(clippy::cast_precision_loss, clippy::cast_lossless)]
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+        }
+    )+};
+}
+
+impl_window_int!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+macro_rules! impl_window_float {
+    ($($t:ty),+) => {$(
+        impl WindowSum for $t {
+            const ZERO: Self = 0.0;
+
+            fn window_add(self, rhs: Self) -> Self {
+                self + rhs
+            }
+
+            fn window_sub(self, rhs: Self) -> Self {
+                self - rhs
+            }
+        }
+
+        impl ToF64 for $t {
+            fn to_f64(self) -> f64 {
+                f64::from(self)
+            }
+        }
+    )+};
+}
+
+impl_window_float!(f32, f64);
+
+/// An iterator over the sum of each sliding window of another. See
+/// [`rolling_sum`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct RollingSum<I: Iterator> {
+    iter: I,
+    n: usize,
+    window: VecDeque<I::Item>,
+    sum: Option<I::Item>,
+}
+
+impl<I, T> Iterator for RollingSum<I>
+where
+    I: Iterator<Item = T>,
+    T: WindowSum,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let item = self.iter.next()?;
+            self.window.push_back(item);
+            match self.window.len().cmp(&self.n) {
+                std::cmp::Ordering::Less => continue,
+                std::cmp::Ordering::Equal => {
+                    let sum = self
+                        .window
+                        .iter()
+                        .fold(T::ZERO, |sum, x| sum.window_add(*x));
+                    self.sum = Some(sum);
+                }
+                std::cmp::Ordering::Greater => {
+                    let old = self.window.pop_front()?;
+                    self.sum = self.sum.map(|sum| sum.window_sub(old).window_add(item));
+                }
+            }
+            return self.sum;
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.iter.size_hint();
+        let missing = (self.n - 1).saturating_sub(self.window.len());
+        (
+            lower.saturating_sub(missing),
+            upper.map(|n| n.saturating_sub(missing)),
+        )
+    }
+}
+
+/// Yields the sum of every window of `n` consecutive items. Only the first
+/// window is summed in full; after that, each new item is added and the
+/// oldest one subtracted, so every window costs the same no matter how big
+/// `n` is. With floats, this means rounding errors can build up over very
+/// long iterators.
+///
+/// This is what the `rolling sum <n>` clause expands to.
+///
+/// # Panics
+///
+/// Panics if `n` is zero.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![Vec<_>; for x in 1..=5 => x, rolling sum 2];
+/// assert_eq!(v, vec![3, 5, 7, 9]);
+/// ```
+pub fn rolling_sum<I>(iter: I, n: usize) -> RollingSum<I::IntoIter>
+where
+    I: IntoIterator,
+{
+    assert!(n > 0, "a rolling window needs at least one item");
+    RollingSum {
+        iter: iter.into_iter(),
+        n,
+        window: VecDeque::with_capacity(n + 1),
+        sum: None,
+    }
+}
+
+/// Yields the mean of every window of `n` consecutive items as an `f64`,
+/// using [`rolling_sum`].
+///
+/// This is what the `rolling mean <n>` clause expands to.
+///
+/// # Panics
+///
+/// Panics if `n` is zero.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let temps: [i16; 4] = [10, 14, 12, 20];
+/// let v = rcomp![Vec<_>; for t in temps => t, rolling mean 2];
+/// assert_eq!(v, vec![12.0, 13.0, 16.0]);
+/// ```
+pub fn rolling_mean<I>(iter: I, n: usize) -> impl Iterator<Item = f64>
+where
+    I: IntoIterator,
+    I::Item: ToF64,
+{
+    // windows big enough to lose precision here won't fit in memory anyway
+    #[allow(clippy::cast_precision_loss)]
+    let len = n as f64;
+    rolling_sum(iter.into_iter().map(ToF64::to_f64), n).map(move |sum: f64| sum / len)
+}
+
+/// Collects the iterator into a `Vec` and sorts it with `compare`. The sort
+/// is stable, so equal items keep their order.
+///
+/// This is what the `sorted` and `sorted by <fields>` clauses expand to,
+/// with `compare` built from the fields and their directions.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// struct Point {
+///     x: i32,
+///     y: i32,
+/// }
+///
+/// let points = [Point { x: 1, y: 2 }, Point { x: 0, y: 5 }, Point { x: 1, y: 1 }];
+/// let v = rcomp![Vec<_>; for p in &points => p, sorted by (x desc, y)];
+/// assert_eq!(v.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>(), [(1, 1), (1, 2), (0, 5)]);
+/// ```
+pub fn sorted_by<I, F>(iter: I, compare: F) -> std::vec::IntoIter<I::Item>
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item, &I::Item) -> std::cmp::Ordering,
+{
+    let mut v = iter.into_iter().collect::<Vec<_>>();
+    v.sort_by(compare);
+    v.into_iter()
+}
+
+/// Like [`sorted_by`], but uses an unstable sort, so equal items may be
+/// reordered. This is usually faster and doesn't allocate.
+///
+/// This is what `sorted unstable [by <fields>]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![Vec<_>; for x in [5, 3, 9, 1] => x * 2, sorted unstable desc];
+/// assert_eq!(v, vec![18, 10, 6, 2]);
+/// ```
+pub fn sorted_unstable_by<I, F>(iter: I, compare: F) -> std::vec::IntoIter<I::Item>
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item, &I::Item) -> std::cmp::Ordering,
+{
+    let mut v = iter.into_iter().collect::<Vec<_>>();
+    v.sort_unstable_by(compare);
+    v.into_iter()
+}
+
+/// Like [`sorted_by`], but sorts on rayon's thread pool. The sort is still
+/// stable. Requires the `rayon` feature.
+///
+/// This is what `sorted parallel [by <fields>]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![Vec<_>; for x in (0..10_000).rev() => (x % 10, x), sorted parallel by 0];
+/// assert_eq!(v[0], (0, 9990));
+/// assert_eq!(v[9_999], (9, 9));
+/// ```
+#[cfg(feature = "rayon")]
+pub fn par_sorted_by<I, F>(iter: I, compare: F) -> std::vec::IntoIter<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Send,
+    F: Fn(&I::Item, &I::Item) -> std::cmp::Ordering + Sync,
+{
+    use rayon::slice::ParallelSliceMut;
+
+    let mut v = iter.into_iter().collect::<Vec<_>>();
+    v.par_sort_by(compare);
+    v.into_iter()
+}
+
+/// Sorts `(key, item)` pairs by their keys and yields just the items. The
+/// sort is stable, so items with equal keys keep their order either way.
+///
+/// This is what the `order by <key> [asc|desc]` clause of [`query!`]
+/// expands to, where the key is computed alongside the selected item so it
+/// can use the `from` bindings.
+///
+/// [`query!`]: crate::query
+///
+/// # Example
+///
+/// ```rust
+/// use rustcomp::adapters::order_by;
+///
+/// let v = order_by([(2, 'b'), (1, 'a'), (2, 'c')], true).collect::<String>();
+/// assert_eq!(v, "bca");
+/// ```
+pub fn order_by<I, K, T>(iter: I, descending: bool) -> impl Iterator<Item = T>
+where
+    I: IntoIterator<Item = (K, T)>,
+    K: Ord,
+{
+    let mut pairs = iter.into_iter().collect::<Vec<_>>();
+    if descending {
+        pairs.sort_by(|(a, _), (b, _)| b.cmp(a));
+    } else {
+        pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+    pairs.into_iter().map(|(_, item)| item)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rcomp;
+
+    #[test]
+    fn test_simd_map_matches_plain_map() {
+        for n in [0, 1, 7, 8, 9, 16, 23] {
+            let xs = (0..n).collect::<Vec<i64>>();
+            let expected = rcomp![Vec<_>; for x in &xs => x * x - 3];
+            let actual = rcomp![simd; Vec<_>; for x in &xs => x * x - 3];
+            assert_eq!(expected, actual, "{n} items");
+        }
+        let squares = rcomp![simd; for x in [1u8, 2, 3] => u32::from(*x).pow(2)].sum::<u32>();
+        assert_eq!(squares, 14);
+    }
+
+    #[test]
+    fn test_unique_by_keeps_first_occurrence() {
+        let v = vec![(1, 'a'), (2, 'b'), (1, 'c'), (3, 'd'), (2, 'e')];
+        let actual = rcomp![Vec<_>; for x in v => x, unique by |(k, _)| *k];
+        assert_eq!(actual, vec![(1, 'a'), (2, 'b'), (3, 'd')]);
+    }
+
+    #[test]
+    fn test_intersperse_clause() {
+        let v = rcomp![Vec<_>; for x in 0..4 => x, if x % 2 == 0, intersperse -1];
+        assert_eq!(v, vec![0, -1, 2]);
+        let it = rcomp![for x in 0..4 => x, intersperse -1];
+        assert_eq!(it.size_hint(), (0, Some(7)));
+        assert_eq!(super::intersperse(0..4, -1).size_hint(), (7, Some(7)));
+        assert_eq!(it.count(), 7);
+        let empty = rcomp![Vec<_>; for x in 0..4 => x, if x > 9, intersperse -1];
+        assert!(empty.is_empty());
+        let one = rcomp![Vec<_>; for c in "a".chars() => c, intersperse '|', sorted];
+        assert_eq!(one, vec!['a']);
+    }
+
+    #[test]
+    fn test_coalesce_merges_intervals() {
+        let intervals = vec![(5, 7), (1, 3), (2, 4), (8, 9), (6, 8)];
+        let merged = rcomp![Vec<_>; for r in intervals => r, sorted,
+            coalesce |(a, b), (c, d)| if c <= b { Ok((a, b.max(d))) } else { Err(((a, b), (c, d))) }
+        ];
+        assert_eq!(merged, vec![(1, 4), (5, 9)]);
+        let none = rcomp![Vec<i32>; for x in 0..3 => x, if x > 5, coalesce |a, b| Ok(a + b)];
+        assert!(none.is_empty());
+        let all = rcomp![Vec<_>; for x in 1..=4 => x, coalesce |a, b| Ok(a * b)];
+        assert_eq!(all, vec![24]);
+    }
+
+    #[test]
+    fn test_chunk_by_clause() {
+        let readings = [(9, 1.0), (9, 2.0), (10, 4.0), (11, 3.0), (11, 5.0)];
+        let hours =
+            rcomp![Vec<_>; for r in readings => r, if r.1 > 1.0, chunk by |(hour, _)| *hour];
+        assert_eq!(hours.len(), 3);
+        assert_eq!(hours[2], (11, vec![(11, 3.0), (11, 5.0)]));
+        let nested = rcomp![Vec<_>; for (hour, run) in rcomp![for r in readings => r, chunk by |r| r.0] => (hour, run.len())];
+        assert_eq!(nested, [(9, 2), (10, 1), (11, 2)]);
+        let none = rcomp![Vec<_>; for x in 0..0 => x, chunk by |x| x % 2];
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn test_split_when_clause() {
+        let text = "\nname: a\nid: 1\n\n\nname: b\n\n";
+        let records =
+            rcomp![Vec<_>; for line in text.lines() => line.trim(), split when |l| l.is_empty()];
+        assert_eq!(records, [vec!["name: a", "id: 1"], vec!["name: b"]]);
+        let counts = rcomp![Vec<_>; for rec in rcomp![for l in text.lines() => l, split when |l| l.is_empty()] => rec.len()];
+        assert_eq!(counts, [2, 1]);
+        let whole = rcomp![Vec<_>; for x in 0..3 => x, split when |_| false];
+        assert_eq!(whole, [vec![0, 1, 2]]);
+        let none = rcomp![Vec<Vec<i32>>; for x in 0..3 => x, split when |_| true];
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn test_via_clause() {
+        use super::CompAdapter;
+
+        struct Pairs;
+
+        impl<I: Iterator<Item = i32>> CompAdapter<I> for Pairs {
+            type Output = std::vec::IntoIter<(i32, i32)>;
+
+            fn adapt(self, iter: I) -> Self::Output {
+                let v = iter.collect::<Vec<_>>();
+                v.windows(2)
+                    .map(|w| (w[0], w[1]))
+                    .collect::<Vec<_>>()
+                    .into_iter()
+            }
+        }
+
+        let v =
+            rcomp![Vec<_>; for x in [1, 4, 9, 16] => x, via Pairs, via |it| it.map(|(a, b)| b - a)];
+        assert_eq!(v, [3, 5, 7]);
+        let v = rcomp![Vec<_>; for x in 0..6 => x, if x != 2, via |it| it.skip(1), sorted desc];
+        assert_eq!(v, [5, 4, 3, 1]);
+        let n = 2;
+        let v = rcomp![Vec<_>; for x in 0..6 => x, via move |it| it.take(n)];
+        assert_eq!(v, [0, 1]);
+    }
+
+    #[test]
+    fn test_pad_to_clause() {
+        let width = 2;
+        let v = rcomp![Vec<_>; for x in 0..5 => x, if x > 2, pad to width * 2 with -1];
+        assert_eq!(v, vec![3, 4, -1, -1]);
+        let v = rcomp![Vec<i32>; for x in 0..5 => x, pad to 3];
+        assert_eq!(v, vec![0, 1, 2, 3, 4]);
+        let v = rcomp![Vec<i32>; for x in 0..5 => x, if x > 9, pad to 2, intersperse 7];
+        assert_eq!(v, vec![0, 7, 0]);
+        assert_eq!(super::pad_to(0..2, 5, || 0).size_hint(), (5, Some(5)));
+    }
+
+    #[test]
+    fn test_rolling_matches_windows() {
+        let v = rcomp![Vec<u64>; for x in 0..50 => x * x % 17];
+        for n in [1, 2, 3, 7, 50, 51] {
+            let expected = v
+                .windows(n)
+                .map(|w| w.iter().sum::<u64>())
+                .collect::<Vec<_>>();
+            let actual = rcomp![Vec<_>; for x in &v => *x, rolling n, |w| w.iter().sum::<u64>()];
+            assert_eq!(expected, actual, "window of {n}");
+            let actual = rcomp![Vec<_>; for x in &v => *x, rolling sum n];
+            assert_eq!(expected, actual, "window of {n}");
+        }
+        assert_eq!(super::rolling_sum(0..10, 3).size_hint(), (8, Some(8)));
+        assert_eq!(
+            super::rolling(0..2, 3, <[_]>::len).size_hint(),
+            (0, Some(0))
+        );
+    }
+
+    #[test]
+    fn test_rolling_sum_near_max() {
+        let v = rcomp![Vec<_>; for x in [100u8, 100, 100] => x, rolling sum 2];
+        assert_eq!(v, vec![200, 200]);
+        let v = rcomp![Vec<_>; for x in [250u8, 5, 250, 0] => x, rolling sum 2];
+        assert_eq!(v, vec![255, 255, 250]);
+        // the partial sums along the way don't fit in an `i8`
+        let v = rcomp![Vec<_>; for x in [-100i8, 100, 100, -100] => x, rolling sum 3];
+        assert_eq!(v, vec![100, 100]);
+        let v = rcomp![Vec<_>; for x in [100i8, 100, -100, -100] => x, rolling sum 3];
+        assert_eq!(v, vec![100, -100]);
+    }
+
+    #[test]
+    fn test_rolling_mean_wide_ints() {
+        let v = rcomp![Vec<_>; for x in [10u64, 20, 60] => x, rolling mean 2];
+        assert_eq!(v, vec![15.0, 40.0]);
+        let v = rcomp![Vec<_>; for x in [-4isize, 4, 8] => x, rolling mean 3];
+        assert_eq!(v, vec![8.0 / 3.0]);
+        let v = rcomp![Vec<_>; for x in [i128::MAX, i128::MAX] => x, rolling mean 2];
+        assert_eq!(v, vec![2f64.powi(127)]);
+        let v = rcomp![Vec<_>; for x in [1.5f32, 2.5] => x, rolling mean 2];
+        assert_eq!(v, vec![2.0]);
+    }
+
+    #[test]
+    fn test_rolling_named_sum() {
+        // a closure that happens to be called `sum` is just a closure
+        let sum = |w: &[i32]| w[0] * 100;
+        let v = rcomp![Vec<_>; for x in 1..=3 => x, rolling 2, sum];
+        assert_eq!(v, vec![100, 200]);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one item")]
+    fn test_rolling_zero_panics() {
+        let _ = rcomp![for x in 0..10 => x, rolling sum 0];
+    }
+
+    #[test]
+    fn test_on_error_clause() {
+        let inputs = ["4", "x", "16", "y", "25"];
+        let v = rcomp![Vec<_>; for s in inputs => s.parse::<u32>(), on error skip];
+        assert_eq!(v, vec![4, 16, 25]);
+
+        let mut errors = Vec::new();
+        let v = rcomp![Vec<_>; for s in inputs => s.parse::<u32>(), on error collect &mut errors, sorted desc];
+        assert_eq!(v, vec![25, 16, 4]);
+        assert_eq!(errors.len(), 2);
+
+        let mut error = None;
+        let v = rcomp![Vec<_>; for s in inputs => s.parse::<u32>().map(|n| n * 2), if !s.is_empty(), on error abort &mut error];
+        assert_eq!(v, vec![8]);
+        assert!(error.is_some());
+    }
+
+    #[test]
+    fn test_limit_per_clause() {
+        let v = vec![vec![1, 2, 3, 4], vec![5, 6, 7, 8, 9]];
+        let actual = rcomp![Vec<_>; for row in &v, x in row => *x, limit 1 + 1 per |x| x % 3];
+        assert_eq!(actual, vec![1, 2, 3, 4, 5, 6]);
+        let none = rcomp![Vec<_>; for row in &v, x in row => *x, limit 0 per |x| x % 3];
+        assert!(none.is_empty());
+        let once = rcomp![Vec<_>; for row in &v, x in row => *x, limit 1 per |x| x % 3];
+        let unique = rcomp![Vec<_>; for row in &v, x in row => *x, unique by |x| x % 3];
+        assert_eq!(once, unique);
+    }
+
+    #[test]
+    fn test_sorted_clauses() {
+        let v = vec![3, 1, 2];
+        assert_eq!(rcomp![Vec<_>; for x in &v => *x, sorted], vec![1, 2, 3]);
+        assert_eq!(
+            rcomp![Vec<_>; for x in &v => *x, sorted desc],
+            vec![3, 2, 1]
+        );
+
+        let pairs = vec![(1, 'b'), (0, 'c'), (1, 'a'), (0, 'd')];
+        let actual = rcomp![Vec<_>; for p in &pairs => p, sorted by 0 desc];
+        assert_eq!(actual, vec![&(1, 'b'), &(1, 'a'), &(0, 'c'), &(0, 'd')]);
+        let actual = rcomp![Vec<_>; for p in &pairs => p, sorted by (0, 1 desc,)];
+        assert_eq!(actual, vec![&(0, 'd'), &(0, 'c'), &(1, 'b'), &(1, 'a')]);
+    }
+
+    #[test]
+    fn test_sort_strategies_agree() {
+        let v = rcomp![Vec<_>; for x in 0..500 => (x * 37 % 101, x % 7)];
+        let stable = rcomp![Vec<_>; for p in &v => *p, sorted by (0 desc, 1)];
+        let unstable = rcomp![Vec<_>; for p in &v => *p, sorted unstable by (0 desc, 1)];
+        assert_eq!(stable, unstable);
+        #[cfg(feature = "rayon")]
+        {
+            let parallel = rcomp![Vec<_>; for p in &v => *p, sorted parallel by 0 desc];
+            let stable = rcomp![Vec<_>; for p in &v => *p, sorted by 0 desc];
+            assert_eq!(stable, parallel);
+        }
+        assert_eq!(
+            rcomp![Vec<_>; for x in [2, 1] => x, sorted unstable],
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn test_sorted_by_nested_fields_then_unique() {
+        struct Addr {
+            city: &'static str,
+        }
+        struct Person {
+            name: &'static str,
+            addr: Addr,
+        }
+
+        let people = [
+            Person {
+                name: "c",
+                addr: Addr { city: "oslo" },
+            },
+            Person {
+                name: "a",
+                addr: Addr { city: "rome" },
+            },
+            Person {
+                name: "b",
+                addr: Addr { city: "oslo" },
+            },
+        ];
+        let actual = rcomp![Vec<_>; for p in &people => p, sorted by (addr.city, name desc), unique by |p| p.addr.city];
+        assert_eq!(
+            actual.iter().map(|p| p.name).collect::<Vec<_>>(),
+            ["c", "a"]
+        );
+    }
+
+    #[test]
+    fn test_unique_by_after_guard_and_flattening() {
+        let v = vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]];
+        let actual =
+            rcomp![Vec<_>; for row in &v, x in row => *x, if x % 2 == 1, unique by |x| x % 3];
+        assert_eq!(actual, vec![1, 3, 5]);
+    }
+
+    #[test]
+    fn test_memo_caches_guard_and_mapper() {
+        let mut calls = 0;
+        let actual = rcomp![Vec<_>; for x in [1, 2, 1, 4, 2, 1] => memo { calls += 1; x * 10 }, if x % 2 == 0];
+        assert_eq!(actual, vec![20, 40, 20]);
+        // the guard isn't part of the count, so only the evens are counted
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn test_memo_nested_and_reported() {
+        let v = vec![vec![1, 2, 1], vec![2, 2]];
+        let actual = rcomp![Vec<_>; for row in &v, x in row => memo x * 10];
+        assert_eq!(actual, vec![10, 20, 10, 20, 20]);
+        let (_, report) = rcomp![report; Vec<_>; for x in [1, 2, 1, 3] => memo x, if x < 3];
+        assert_eq!(report.produced, vec![4]);
+        assert_eq!(report.rejected, 1);
+        assert_eq!(report.yielded, 3);
+    }
+}
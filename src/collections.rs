@@ -0,0 +1,181 @@
+/*!
+Collection types that make good targets for the [`rcomp!`](crate::rcomp)
+macro. Like any other collection, they're used by putting them in front of
+the comprehension, e.g. `rcomp![SortedVec<_>; for x in v => x]`.
+*/
+
+use std::ops::Deref;
+
+/// A `Vec` that keeps its items sorted as they're inserted. Each item is
+/// placed with a binary search, after any items equal to it, so equal items
+/// stay in the order they were inserted.
+///
+/// This is handy when the output of a comprehension has to stay ordered
+/// while more items are added later, but a `BTreeSet` would throw away
+/// duplicates and a `BTreeMap` doesn't fit. For a one-off sort, the
+/// `sorted` clause is simpler.
+///
+/// It dereferences to a slice, so it can be read like any other `Vec`.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::collections::SortedVec;
+///
+/// let mut v = rcomp![SortedVec<_>; for x in [5, 1, 4, 1] => x * 10];
+/// assert_eq!(v.as_slice(), [10, 10, 40, 50]);
+/// v.extend(rcomp![for x in [3, 6] => x * 10]);
+/// assert_eq!(v.as_slice(), [10, 10, 30, 40, 50, 60]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SortedVec<T> {
+    items: Vec<T>,
+}
+
+impl<T> SortedVec<T> {
+    /// Creates an empty `SortedVec`.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    /// Returns the items as a sorted slice.
+    #[must_use]
+    pub fn as_slice(&self) -> &[T] {
+        &self.items
+    }
+
+    /// Returns the underlying `Vec`, which is sorted.
+    #[must_use]
+    pub fn into_vec(self) -> Vec<T> {
+        self.items
+    }
+}
+
+impl<T: Ord> SortedVec<T> {
+    /// Inserts `item` in order, after any items equal to it, and returns
+    /// its index.
+    pub fn insert(&mut self, item: T) -> usize {
+        let index = self.items.partition_point(|x| *x <= item);
+        self.items.insert(index, item);
+        index
+    }
+
+    /// Returns `true` if an item equal to `item` is in the `SortedVec`. This
+    /// is a binary search, unlike [`slice::contains`].
+    pub fn contains(&self, item: &T) -> bool {
+        self.items.binary_search(item).is_ok()
+    }
+}
+
+impl<T> Default for SortedVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Deref for SortedVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.items
+    }
+}
+
+impl<T: Ord> From<Vec<T>> for SortedVec<T> {
+    fn from(mut items: Vec<T>) -> Self {
+        items.sort();
+        Self { items }
+    }
+}
+
+impl<T> From<SortedVec<T>> for Vec<T> {
+    fn from(v: SortedVec<T>) -> Self {
+        v.items
+    }
+}
+
+impl<T: Ord> Extend<T> for SortedVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        // one stable sort after appending keeps the new items after any
+        // equal ones, like `insert`, without shifting the tail for each of
+        // them. it's close to linear when the new items are already sorted.
+        self.items.extend(iter);
+        self.items.sort();
+    }
+}
+
+impl<T: Ord> FromIterator<T> for SortedVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        // sorting once is faster than inserting one by one, and the stable
+        // sort keeps equal items in order just the same
+        Self::from(iter.into_iter().collect::<Vec<_>>())
+    }
+}
+
+impl<T> IntoIterator for SortedVec<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a SortedVec<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SortedVec;
+    use crate::rcomp;
+
+    #[test]
+    fn test_sorted_vec_insert() {
+        let mut v = rcomp![SortedVec<_>; for x in [3, 1, 2] => x * 2];
+        assert_eq!(v.as_slice(), [2, 4, 6]);
+        assert_eq!(v.insert(4), 2);
+        assert_eq!(v.insert(0), 0);
+        assert_eq!(v.insert(7), 5);
+        assert_eq!(v.as_slice(), [0, 2, 4, 4, 6, 7]);
+        assert!(v.contains(&7));
+        assert!(!v.contains(&5));
+    }
+
+    #[test]
+    fn test_sorted_vec_extend_keeps_equal_items_in_order() {
+        #[derive(Debug, PartialEq, Eq)]
+        struct Keyed(u8, char);
+
+        impl PartialOrd for Keyed {
+            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Keyed {
+            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+                self.0.cmp(&other.0)
+            }
+        }
+
+        let mut v = rcomp![SortedVec<_>; for (k, c) in [(2, 'a'), (1, 'b')] => Keyed(k, c)];
+        v.extend([Keyed(2, 'c'), Keyed(0, 'd'), Keyed(1, 'e'), Keyed(2, 'f')]);
+        let tags = rcomp![String; for Keyed(_, c) in &v => *c];
+        assert_eq!(tags, "dbeacf");
+    }
+
+    #[test]
+    fn test_sorted_vec_nested_comp() {
+        let v = vec![vec![9, 2], vec![], vec![7, 4, 1]];
+        let actual = rcomp![SortedVec<_>; for row in &v, x in row => *x, if x % 2 == 1];
+        assert_eq!(actual.into_vec(), vec![1, 7, 9]);
+    }
+}
@@ -152,7 +152,9 @@ let v = (&matrix)
 # assert_eq!(v, it);
 ```
 
-Notice the use of `into_iter` in the expansion.
+Notice the use of `into_iter` in the expansion. If you want to see roughly
+what your own comprehension becomes, [`rcomp_expand!`] will sketch it for
+you as a string.
 
 # What about `mapcomp`?
 
@@ -173,90 +175,2519 @@ a macro-based solution that didn't require nightly, so I settled on iterators
 in lieu of generators.
 */
 
+pub mod adapters;
+pub mod collections;
+#[cfg(feature = "rayon")]
+pub mod par;
+#[cfg(feature = "indicatif")]
+pub mod progress;
+pub mod report;
+pub mod sets;
+pub mod sources;
+pub mod terminals;
+pub mod threads;
+#[cfg(feature = "tracing")]
+pub mod trace;
+
 /// Generates an iterator that yields the results of the comprehension. The
 /// syntax allows for flattening, filtering, mapping, and collecting iterators
 /// (in that order).
 ///
-/// There are 4 main components to a comprehension:
-/// - The optional collection type, which is passed to a `collect` call by the
-///   macro. If this is omitted, the macro will return an iterator instead of
-///   a collection.
-/// - The `for-in` clause, which iterates over the input(s). This can be
-///   chained (e.g. `for i in v1, j in v2, k in v3, ...`) to flatten nested
-///   iterators, up to the recursion limit.
-/// - The mapping expression, which transforms the input.
-/// - The optional guard expression, which filters the input. Although this is
-///   the last component of the comprehension, it is applied _before_ the
-///   mapping expression. In a sense, the end of the comprehension looks like
-///   a `match` arm. This has a few implications which are explored more in
-///   the [crate-level documentation](crate).
+/// There are 4 main components to a comprehension:
+/// - The optional collection type, which is passed to a `collect` call by the
+///   macro. If this is omitted, the macro will return an iterator instead of
+///   a collection. A tuple of 2 to 4 collection types, like
+///   `(Vec<_>, Vec<_>, Vec<_>)`, unzips a mapper that returns tuples into
+///   one collection per field.
+/// - The `for-in` clause, which iterates over the input(s). This can be
+///   chained (e.g. `for i in v1, j in v2, k in v3, ...`) to flatten nested
+///   iterators, up to the recursion limit.
+/// - The mapping expression, which transforms the input.
+/// - The optional guard expression, which filters the input. Although this is
+///   the last component of the comprehension, it is applied _before_ the
+///   mapping expression. In a sense, the end of the comprehension looks like
+///   a `match` arm. This has a few implications which are explored more in
+///   the [crate-level documentation](crate).
+///
+/// With that explained, here's the full syntax:
+///
+/// ```text
+/// rcomp!([modifier; ...][collect_ty;] for <pattern> in <iterator>, ...[ => [memo] <mapper>][, if <guard>[, else <on_reject>]][, <clause>...]);
+/// ```
+///
+/// The modifiers and trailing clauses are optional and are covered
+/// [below](#modifiers).
+///
+/// The mapper can be left out when it would just repeat the innermost
+/// pattern, which is handy for comprehensions that only filter. The pattern
+/// is then used as the mapper, so this works for identifiers and tuples of
+/// them, but not for patterns like `&x` or `Some(x)`.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = vec![3, -1, 4, -1, 5];
+/// let positive = rcomp![Vec<_>; for x in v, if x > 0];
+/// assert_eq!(positive, vec![3, 4, 5]);
+/// ```
+///
+/// # Examples
+///
+/// Comprehensions can be as simple or complex as you want. They can collect
+/// the input, filter it, map it, and flatten it all in one go. For example,
+/// here's how you can create a `HashMap` of numbers and their squares using
+/// a comprehension:
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// # use std::collections::HashMap;
+/// let m = rcomp![HashMap<_, _>; for i in 0..10 => (i, i * i)];
+/// let it = (0..10).map(|i| (i, i * i)).collect::<HashMap<_, _>>();
+/// assert_eq!(m, it);
+/// ```
+///
+/// The parentheses around the tuple are required, since a comma after the
+/// mapper starts the guard or a clause:
+///
+/// ```compile_fail
+/// # use rustcomp::rcomp;
+/// # use std::collections::HashMap;
+/// // error: expected a guard or a clause after the mapper, found `i * i`;
+/// // if the mapper is a tuple, wrap it in parentheses
+/// let m = rcomp![HashMap<_, _>; for i in 0..10 => i, i * i];
+/// ```
+///
+/// Another example is removing duplicates from a `Vec` by converting it to
+/// a `HashSet` and back:
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// # use std::collections::HashSet;
+/// let v = vec![1, 2, 3, 4, 5, 1, 2, 3, 4, 5];
+/// let s = rcomp![Vec<_>; for i in rcomp![HashSet<_>; for j in &v => *j] => i];
+/// assert!(v.into_iter().all(|i| s.contains(&i)));
+/// ```
+///
+/// # Sources
+///
+/// The source of a `for-in` clause is usually just an expression, but a few
+/// keywords can go around it to change how it's iterated. The iterators behind
+/// them live in the [`sources`] module:
+///
+/// - `<roots> descend <children_fn>` walks a tree depth-first, starting at
+///   each of the roots. `children_fn` is given a reference to each node and
+///   returns its children.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// # use std::collections::HashMap;
+/// let deps = HashMap::from([("app", vec!["log", "http"]), ("http", vec!["tls"])]);
+/// let v = rcomp![Vec<_>; for dep in ["app"] descend |d| deps.get(d).cloned().unwrap_or_default() => dep];
+/// assert_eq!(v, vec!["app", "log", "http", "tls"]);
+/// ```
+///
+/// - `<a> zip longest <b>` iterates over both in lockstep until _both_ are
+///   exhausted, yielding pairs of `Option`s. The shorter side is `None` once
+///   it runs out.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![Vec<_>; for (a, b) in [1, 2, 3] zip longest [10] => a.unwrap_or(0) + b.unwrap_or(0)];
+/// assert_eq!(v, vec![11, 2, 3]);
+/// ```
+///
+/// - `<map_a> outer join <map_b>` iterates over every key in either map,
+///   yielding `(key, Option<&A>, Option<&B>)` triples. The maps are
+///   borrowed, so they're written as references.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// # use std::collections::HashMap;
+/// let stock = HashMap::from([("apples", 3)]);
+/// let orders = HashMap::from([("apples", 5), ("pears", 2)]);
+/// let mut short = rcomp![Vec<_>; for (item, have, want) in &stock outer join &orders =>
+///     (*item, want.unwrap_or(&0) - have.unwrap_or(&0)), if want > have
+/// ];
+/// short.sort_unstable();
+/// assert_eq!(short, [("apples", 2), ("pears", 2)]);
+/// ```
+///
+/// - `<source> while <cond>` stops the `for-in` clause at the first item
+///   for which `cond` is false. `cond` can use the clause's pattern, which
+///   is bound to a copy of each item, so the item has to be `Copy`. For
+///   items that aren't, `cond` can be a closure instead, which is given a
+///   reference to each item. On an inner clause, this only ends that
+///   clause, like a `break` out of an inner loop, and the outer clauses
+///   carry on.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let rows = vec![vec![1, 2, 0, 3], vec![4, 0], vec![5]];
+/// let v = rcomp![Vec<_>; for row in &rows, x in row while *x != 0 => *x];
+/// assert_eq!(v, vec![1, 2, 4, 5]);
+/// let names = ["ann", "bo", "", "cy"].map(String::from);
+/// let v = rcomp![Vec<_>; for s in &names while |s| !s.is_empty() => s.len()];
+/// assert_eq!(v, vec![3, 2]);
+/// ```
+///
+/// - `static <source>` on an inner `for-in` clause marks a source that
+///   doesn't depend on the clauses before it. It's evaluated once, along
+///   with the outermost source, and cloned for each item of the clause
+///   before it, instead of being evaluated again every time. The source has
+///   to be [`Clone`], so a one-shot iterator that isn't needs to be
+///   collected first.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// # use std::cell::Cell;
+/// let built = Cell::new(0);
+/// let columns = || {
+///     built.set(built.get() + 1);
+///     vec!['a', 'b']
+/// };
+/// let v = rcomp![move; Vec<_>; for row in 1..=3, col in static columns() => format!("{col}{row}")];
+/// assert_eq!(v, ["a1", "b1", "a2", "b2", "a3", "b3"]);
+/// assert_eq!(built.get(), 1);
+/// ```
+///
+/// - `<source> @ <name>` numbers the items of the source, starting at `0`,
+///   and binds the number to `name`, like `for (name, pattern) in
+///   source.enumerate()`. This works on any `for-in` clause, including the
+///   ones using the other keywords here. `name` is an outer binding to the
+///   inner clauses, so using it in them may need the `move` modifier.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let lines = ["a b", "c"];
+/// let v = rcomp![move; Vec<_>; for line in lines @ row, word in line.split(' ') @ col => (row, col, word)];
+/// assert_eq!(v, [(0, 0, "a"), (0, 1, "b"), (1, 0, "c")]);
+/// ```
+///
+/// - `cycle <source> take <n>` repeats the source from the start every time
+///   it runs out, stopping after `n` items in total. The `take` is
+///   required, so the comprehension can't loop forever by accident.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![String; for c in cycle "ab".chars() take 5 => c];
+/// assert_eq!(v, "ababa");
+/// ```
+///
+/// - `from <init> then <step> [while <cond>]` generates a sequence,
+///   starting at `init` and applying `step` to the previous item to get
+///   the next one, for as long as `cond` holds. `step` is given a reference
+///   to the previous item, and `cond` is written like the `while` above.
+///   Without `while`, the sequence never ends, so it has to be cut short
+///   with something like `take`.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![Vec<_>; for x in from 1 then |x| x * 3 while x < 100 => x];
+/// assert_eq!(v, vec![1, 3, 9, 27, 81]);
+/// let fib = rcomp![Vec<_>; for (a, _) in from (0, 1) then |&(a, b)| (b, a + b) while a < 20 => a];
+/// assert_eq!(fib, vec![0, 1, 1, 2, 3, 5, 8, 13]);
+/// ```
+///
+/// - `combinations <k> of <source>` and `permutations <k> of <source>`
+///   iterate over every way to pick `k` of the items, without and with
+///   regard to order. `k` has to be a constant, since each pick is an
+///   array that can be destructured like `[a, b]`.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let points = [(0, 0), (3, 4), (6, 8)];
+/// let squared_dists = rcomp![Vec<_>; for [(x1, y1), (x2, y2)] in combinations 2 of points =>
+///     (x2 - x1) * (x2 - x1) + (y2 - y1) * (y2 - y1)
+/// ];
+/// assert_eq!(squared_dists, vec![25, 100, 25]);
+/// ```
+///
+/// - `powerset [<max> of] <source>` iterates over every subset of the items
+///   as a `Vec`, smallest first, optionally only up to `max` items.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![Vec<_>; for s in powerset 1 of ['a', 'b'] => s];
+/// assert_eq!(v, vec![vec![], vec!['a'], vec!['b']]);
+/// ```
+///
+/// - `peeking <source>` pairs each item with a clone of the next one, or
+///   `None` for the last item, for spotting boundaries between items.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let words = ["let", "x", "=", "1", ";", "let", "y"];
+/// let v = rcomp![Vec<_>; for (w, next) in peeking words => w, if next == Some("=")];
+/// assert_eq!(v, ["x"]);
+/// ```
+///
+/// - `merge sorted(<a>, <b>, ...)` merges sources that are already sorted
+///   into one sorted stream, without collecting and sorting them again.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![Vec<_>; for x in merge sorted([1, 4, 9], 2..4, vec![5]) => x];
+/// assert_eq!(v, vec![1, 2, 3, 4, 5, 9]);
+/// ```
+///
+/// The rest of the [`sources`] module is plain functions, which are imported
+/// and called like any other source:
+///
+/// - [`zip_with(<f>, <a>, <b>)`](sources::zip_with) iterates over `a` and
+///   `b` in lockstep and combines each pair with `f`, stopping at the
+///   shorter side. It's a plain `zip` and `map` under the hood, so it
+///   doesn't allocate.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::zip_with;
+///
+/// let v = rcomp![Vec<_>; for d in zip_with(|a, b| a - b, [5, 7, 9], [1, 2, 3]) => d, if d > 4];
+/// assert_eq!(v, vec![5, 6]);
+/// ```
+///
+/// - [`repeat(<value>, <n>)`](sources::repeat) yields `n` clones of
+///   `value`, and [`repeat_with(<f>, <n>)`](sources::repeat_with) yields the
+///   results of calling `f` `n` times. Unlike the `std::iter` versions, they
+///   can't run forever by mistake.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::repeat;
+///
+/// let v = rcomp![Vec<_>; for mut row in repeat(vec![0; 2], 3) => { row.push(1); row }];
+/// assert_eq!(v, vec![vec![0, 0, 1]; 3]);
+/// ```
+///
+/// - [`transpose(<rows>)`](sources::transpose) iterates over the columns
+///   of a matrix, like a `Vec<Vec<T>>` or `&[Vec<T>]`, as `Vec`s. The rows
+///   must all be the same length, or it returns a
+///   [`RaggedError`](sources::RaggedError) instead.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::transpose;
+///
+/// # fn main() -> Result<(), rustcomp::sources::RaggedError> {
+/// let m = vec![vec![1, 2], vec![3, 4]];
+/// let v = rcomp![Vec<Vec<_>>; for col in transpose(&m)? => col];
+/// assert_eq!(v, [[&1, &3], [&2, &4]]);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// - [`grid(<rows>, <cols>)`](sources::grid) iterates over every
+///   `(row, col)` pair in row-major order, as one flat loop instead of two
+///   `for-in` clauses.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::grid;
+///
+/// let board = ["#.", ".#"];
+/// let walls = rcomp![Vec<_>; for (y, x) in grid(0..2, 0..2) => (y, x), if board[y].as_bytes()[x] == b'#'];
+/// assert_eq!(walls, [(0, 0), (1, 1)]);
+/// ```
+///
+/// - [`upper_triangle(<n>)`](sources::upper_triangle) yields every index
+///   pair `(i, j)` with `i < j < n`, and
+///   [`pairs_of(<slice>)`](sources::pairs_of) yields every pair of distinct
+///   items in a slice the same way, as references. Each pair comes up
+///   once, so comparing every pair doesn't need any index juggling.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::upper_triangle;
+///
+/// let ids = vec![3, 7, 3];
+/// let dupes = rcomp![Vec<_>; for (i, j) in upper_triangle(ids.len()) => (i, j), if ids[i] == ids[j]];
+/// assert_eq!(dupes, [(0, 2)]);
+/// ```
+///
+/// # `let` clauses
+///
+/// Between the `for-in` clauses, `let <pattern> = <expr>` binds a value
+/// for the clauses after it and the mapper, so it's only computed once per
+/// item. With `else skip`, the pattern can be refutable, like `Some(x)` or
+/// `Ok(x)`, and the items it doesn't match are dropped without counting as
+/// rejected by the guard. Like the bindings of a `for-in` clause, using
+/// them in the clauses after it may need the `move` modifier. A `let`
+/// clause can't be the first clause, and it can't be the last one when the
+/// mapper uses `memo`.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// # use std::collections::HashMap;
+/// let prices = HashMap::from([("apple", 3), ("pear", 5)]);
+/// let orders = [("apple", 2), ("kiwi", 1), ("pear", 1)];
+/// let v = rcomp![Vec<_>; for (item, n) in orders,
+///     let Some(price) = prices.get(item) else skip,
+///     let total = price * n
+///     => (item, total), if total > 4
+/// ];
+/// assert_eq!(v, [("apple", 6), ("pear", 5)]);
+/// ```
+///
+/// # Memoization
+///
+/// If the mapper is expensive and the source has a lot of repeats, putting
+/// `memo` in front of it caches its result for each item, so it only runs
+/// once per distinct item. The items must be `Hash + Eq + Clone` and the
+/// output must be `Clone`. See [`memo_map`](adapters::memo_map) for the
+/// details.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// fn collatz_len(mut n: u64) -> usize {
+///     let mut len = 1;
+///     while n != 1 {
+///         n = if n % 2 == 0 { n / 2 } else { 3 * n + 1 };
+///         len += 1;
+///     }
+///     len
+/// }
+///
+/// let v = rcomp![Vec<_>; for x in [27, 27, 9, 27] => memo collatz_len(x)];
+/// assert_eq!(v, vec![112, 112, 20, 112]);
+/// ```
+///
+/// # Auditing the guard
+///
+/// A guard silently drops whatever it rejects. To find out what was dropped,
+/// and why, follow the guard with `else <on_reject>`. The `on_reject`
+/// expression runs for every rejected item, with the same bindings as the
+/// guard, so it can push the item (or a reason) into a side channel.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let records = ["7", "x", "-3", "12"];
+/// let mut dropped = Vec::new();
+/// let kept = rcomp![Vec<u32>; for r in records => r.parse().unwrap(),
+///     if r.parse::<u32>().is_ok(), else dropped.push((r, "not a u32"))
+/// ];
+/// assert_eq!(kept, vec![7, 12]);
+/// assert_eq!(dropped, vec![("x", "not a u32"), ("-3", "not a u32")]);
+/// ```
+///
+/// With more than one `for-in` clause, the inner levels are closures that
+/// outlive each call of the outer ones, so the side channel can't be
+/// borrowed mutably. Use something that only needs `&`, like a `RefCell`
+/// or a channel's `Sender`.
+///
+/// # Block mappers
+///
+/// When the mapper is a block, two macros are available inside it: `skip!()`
+/// drops the current item, and `emit!(value)` yields `value` right away and
+/// keeps going. The value of the block is yielded after anything emitted,
+/// so a block that only emits should end with `skip!()`.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let lines = ["a=1", "# comment", "b=2,3"];
+/// let v = rcomp![Vec<_>; for line in lines => {
+///     let Some((key, values)) = line.split_once('=') else {
+///         skip!();
+///     };
+///     for value in values.split(',') {
+///         emit!((key, value));
+///     }
+///     skip!()
+/// }];
+/// assert_eq!(v, vec![("a", "1"), ("b", "2"), ("b", "3")]);
+/// ```
+///
+/// Both macros work by returning from the closure around the mapper, so they
+/// can't be used inside a closure of their own, like one passed to `map`.
+///
+/// # Clauses
+///
+/// Clauses come after the guard and apply to the _output_ of the
+/// comprehension, in the order they're written. Unlike the guard, they see
+/// the whole flattened stream, not just the innermost level. The adapters
+/// behind them live in the [`adapters`] module:
+///
+/// - `unique by <key_fn>` keeps only the first item for each key returned
+///   by `key_fn`, which is given a reference to the item. The keys must be
+///   `Hash + Eq`.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let words = ["apple", "avocado", "banana", "blueberry", "cherry"];
+/// let v = rcomp![Vec<_>; for w in words => w, unique by |w| w.chars().next()];
+/// assert_eq!(v, vec!["apple", "banana", "cherry"]);
+/// ```
+///
+/// - `limit <n> per <key_fn>` keeps only the first `n` items for each key,
+///   like `unique by` with room for more than one.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let logins = [("ann", 9), ("bo", 10), ("ann", 11), ("ann", 12)];
+/// let v = rcomp![Vec<_>; for (user, hour) in logins => (user, hour), limit 2 per |(user, _)| *user];
+/// assert_eq!(v, [("ann", 9), ("bo", 10), ("ann", 11)]);
+/// ```
+///
+/// - `sorted [asc|desc]` sorts the items themselves, and
+///   `sorted by <field> [asc|desc]` sorts them by one of their fields.
+///   Several fields can be given in parentheses, each with its own
+///   direction, e.g. `sorted by (dept asc, salary desc)`; later fields only
+///   break ties between earlier ones. Fields are accessed with `.`, so
+///   tuple indices and nested fields like `addr.city` work too. The sort is
+///   stable and ascending by default.
+///
+///   `sorted` can be followed by `unstable` to use `sort_unstable_by`
+///   instead, which is faster and doesn't allocate, or by `parallel`
+///   (requires the `rayon` feature) to sort on rayon's thread pool, e.g.
+///   `sorted unstable by id`.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let staff = [("eng", 120, "alice"), ("ops", 90, "bob"), ("eng", 150, "carol")];
+/// let v = rcomp![Vec<_>; for e in staff => e, sorted by (0 asc, 1 desc)];
+/// assert_eq!(v.iter().map(|e| e.2).collect::<Vec<_>>(), ["carol", "alice", "bob"]);
+/// ```
+///
+/// - `intersperse <sep>` yields a clone of `sep` between every two items,
+///   which is handy for building delimited output.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let words = ["select", "from", "where"];
+/// let sql = rcomp![String; for w in words => w.to_uppercase(), intersperse " ".to_owned()];
+/// assert_eq!(sql, "SELECT FROM WHERE");
+/// ```
+///
+/// - `coalesce <f>` merges adjacent items with `f`, which is given two
+///   items and returns either `Ok(merged)` or `Err` with both of them back.
+///   Merging keeps going until `f` says no, which makes it good for
+///   run-length encoding and merging intervals.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![Vec<_>; for x in [1, 2, 4, 5, 6, 9] => (x, x),
+///     coalesce |(a, b), (c, d)| if b + 1 == c { Ok((a, d)) } else { Err(((a, b), (c, d))) }
+/// ];
+/// assert_eq!(v, vec![(1, 2), (4, 6), (9, 9)]);
+/// ```
+///
+/// - `chunk by <key_fn>` splits the stream into runs of adjacent items
+///   with the same key and yields `(key, Vec<item>)` for each run. It's not
+///   the `group by` terminal: a key that comes back later starts a new run,
+///   which is what time-ordered data wants. `key_fn` is given a reference
+///   to the item.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let temps = [(1, 20), (1, 24), (2, 19), (3, 22), (3, 25)];
+/// let highs = rcomp![Vec<_>; for (day, run) in rcomp![for t in temps => t, chunk by |(day, _)| *day] =>
+///     (day, run.iter().map(|(_, t)| *t).max().unwrap())
+/// ];
+/// assert_eq!(highs, [(1, 24), (2, 19), (3, 25)]);
+/// ```
+///
+/// - `split when <pred>` splits the stream into `Vec`s wherever `pred`
+///   matches an item, like blank lines between records. The matching items
+///   are dropped, and so are the empty segments between them. `pred` is
+///   given a reference to the item.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let input = "1000\n2000\n\n4000\n\n5000\n6000";
+/// let totals = rcomp![Vec<_>;
+///     for elf in rcomp![for l in input.lines() => l, split when |l| l.is_empty()] =>
+///     elf.iter().map(|l| l.parse::<u32>().unwrap()).sum::<u32>()
+/// ];
+/// assert_eq!(totals, [3000, 4000, 11000]);
+/// ```
+///
+/// - `pad to <n> [with <fill>]` adds copies of `fill` (or the default
+///   value) to the end until there are at least `n` items. `fill` is
+///   evaluated again for each one, so it doesn't have to be `Clone`.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let cells = rcomp![Vec<_>; for c in "ab".chars() => c, pad to 4 with '.'];
+/// assert_eq!(cells, vec!['a', 'b', '.', '.']);
+/// ```
+///
+/// - `rolling <n>, <f>` slides a window of `n` items over the output and
+///   yields `f` of each window, which is given a slice. `rolling sum <n>`
+///   and `rolling mean <n>` are faster shortcuts for the most common
+///   aggregates; `mean` yields `f64`s.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![Vec<_>; for x in [1, 5, 3, 8] => x, rolling 2, |w| w[1] - w[0]];
+/// assert_eq!(v, vec![4, -2, 5]);
+/// let v = rcomp![Vec<_>; for x in [1u8, 5, 3, 8] => x, rolling mean 3];
+/// assert_eq!(v, vec![3.0, 16.0 / 3.0]);
+/// ```
+///
+/// - `via <adapter>` hands the stream to a value that implements
+///   [`CompAdapter`](adapters::CompAdapter) and carries on with what it
+///   returns. That's how adapters from other crates, or a closure like
+///   `|it| it.skip(1)`, become clauses.
+///
+/// - `on error skip`, `on error collect <&mut Vec>`, and
+///   `on error abort <&mut Option>` unwrap a mapper that returns `Result`s.
+///   Errors are dropped, pushed into the `Vec`, or stop the comprehension
+///   and get stored in the `Option`, respectively. See
+///   [`OnError`](adapters::OnError).
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let mut errors = Vec::new();
+/// let v = rcomp![Vec<_>; for s in ["1", "x", "3"] => s.parse::<i32>(), on error collect &mut errors];
+/// assert_eq!(v, vec![1, 3]);
+/// assert_eq!(errors.len(), 1);
+/// ```
+///
+/// - `then <f>` applies `f` to the _collected_ result, so wrapping it in an
+///   `Arc` or a `Some` or boxing it stays part of the one expression. It
+///   has to be the last clause, and it needs a collection type, since
+///   there's nothing to apply it to otherwise.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// # use std::sync::Arc;
+/// let shared = rcomp![Vec<_>; for x in 1..4 => x * 10, then Arc::<[i32]>::from];
+/// assert_eq!(*shared, [10, 20, 30]);
+/// let boxed = rcomp![Vec<_>; for x in 1..4 => x, if x != 2, then |v| v.into_boxed_slice()];
+/// assert_eq!(&*boxed, [1, 3]);
+/// ```
+///
+/// # Modifiers
+///
+/// Modifiers change _how_ the comprehension runs without changing what it
+/// produces. They go in front of everything else, including the collection
+/// type, and are followed by a semicolon:
+///
+/// - `threads <n>` splits the outermost source into `n` chunks and runs
+///   each one on a scoped thread, keeping the output in order. See the
+///   [`threads`] module for the details.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![threads 4; Vec<_>; for x in 0..100 => x * x, if x % 3 == 0];
+/// let it = (0..100).filter(|x| x % 3 == 0).map(|x| x * x).collect::<Vec<_>>();
+/// assert_eq!(v, it);
+/// ```
+///
+/// - `par` (requires the `rayon` feature) runs each item of the outermost
+///   source as a task on rayon's thread pool, with the inner clauses
+///   running sequentially inside it, and keeps the output in order.
+///   `par chunks <n>` does the same with runs of `n` consecutive items per
+///   task, which is cheaper when the items are. Both use rayon's global
+///   pool unless `pool <pool>` gives them a `&ThreadPool` (or an expression
+///   that builds one) to run on instead. See the `par` module for the
+///   details.
+/// - `loops` runs the `for-in` clauses as plain nested `for` loops in the
+///   surrounding function, pushing each item into a `Vec`, instead of
+///   building a tower of `flat_map` closures. Deep nesting tends to stop
+///   the closures from being inlined, and loops don't have that problem.
+///   Since nothing is a closure, the mapper and guard can borrow from the
+///   outer clauses and use `?` or `return`. The catch is that the clauses
+///   all run up front, so `loops` needs a collection type: it can't return
+///   an iterator or feed a terminal, and it doesn't work with infinite
+///   sources. The mapper can't be a block or use `memo` either. Any
+///   trailing clauses are applied before collecting as usual; for a plain
+///   `Vec`, [`vcomp!`] does the same thing with less machinery. The
+///   `nesting` benchmark (`cargo bench --bench nesting`) compares the two
+///   strategies at a few depths.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// fn parse_grid(text: &str) -> Result<Vec<u8>, std::num::ParseIntError> {
+///     Ok(rcomp![loops; Vec<_>; for line in text.lines(), cell in line.split(' ') => cell.parse()?, if !cell.is_empty()])
+/// }
+///
+/// assert_eq!(parse_grid("1 2\n3  4"), Ok(vec![1, 2, 3, 4]));
+/// assert!(parse_grid("1 x").is_err());
+/// ```
+///
+/// - `simd` maps a slice eight items at a time in a plain loop, which the
+///   optimizer can vectorize far more easily than a chain of closures. It
+///   only works with one `for-in` clause over a slice, `Vec`, or array, a
+///   mapper without `memo` or a block, no guard, and no `report`,
+///   `trace`, or `progress`. Clauses after the mapper are fine. The pattern
+///   binds references to the items, as if the source were `&slice`.
+/// - `indexed` numbers the items of every `for-in` clause and pairs each
+///   output with the numbers, as `(i, value)` for one clause and
+///   `((i, j, ...), value)` for more, without any `enumerate` bookkeeping.
+///   Each number counts the items of its clause for the current item of
+///   the clause before it, before the guard is applied. A block mapper
+///   can't use `emit!` or `skip!` here.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// # use std::collections::HashMap;
+/// let grid = vec![vec![0, 3, 0], vec![0, 0, 7]];
+/// let sparse = rcomp![indexed; HashMap<_, _>; for row in &grid, x in row => *x, if *x != 0];
+/// assert_eq!(sparse, HashMap::from([((0, 1), 3), ((1, 2), 7)]));
+/// ```
+///
+/// - `report` makes the comprehension evaluate to a tuple of its result and
+///   a [`CompReport`](report::CompReport), which counts the items produced
+///   by each `for-in` clause and rejected by the guard. See the [`report`]
+///   module for the details.
+/// - `trace <name>` (requires the `tracing` feature) runs the comprehension
+///   inside a span and records how many items were scanned, filtered, and
+///   yielded. See the `trace` module for the details.
+///
+/// ```rust
+/// # #[cfg(feature = "tracing")] {
+/// # use rustcomp::rcomp;
+/// let users = vec!["alice", "bob", "carol"];
+/// let v = rcomp![trace "load_users"; Vec<_>; for u in &users => u.len(), if u.len() > 3];
+/// assert_eq!(v, vec![5, 5]);
+/// # }
+/// ```
+///
+/// - `progress <message>` (requires the `indicatif` feature) shows a
+///   progress bar that ticks for each item of the outermost source, with
+///   the source's length if it's known. See the `progress` module for the
+///   details.
+/// - `move` makes every closure in the comprehension a `move` closure. Each
+///   level normally borrows the bindings of the levels around it, which
+///   doesn't work once the iterator outlives them, e.g. when an inner source
+///   or the mapper uses an outer binding. With `move`, the bindings are
+///   moved into the inner levels instead, so the comprehension only borrows
+///   what its sources borrow. Like any `move` closure, variables from the
+///   surrounding scope are moved too, so borrow anything that isn't `Copy`
+///   first.
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// fn fields(text: &str) -> impl Iterator<Item = (usize, &str)> {
+///     rcomp![move; for (i, line) in text.lines().enumerate(), f in line.split(',') => (i, f.trim())]
+/// }
+///
+/// let v = fields("a, b\nc").collect::<Vec<_>>();
+/// assert_eq!(v, vec![(0, "a"), (0, "b"), (1, "c")]);
+/// ```
+///
+/// # Terminals
+///
+/// Instead of a collection type, a comprehension can end in a terminal,
+/// which consumes the iterator in some other way. The available terminals
+/// live in the [`terminals`] module:
+///
+/// - `end <terminal>` hands the iterator to a value that implements
+///   [`CompTerminal`](terminals::CompTerminal), so other crates can define
+///   their own terminals.
+/// - `shuffled [with <rng>]` (requires the `rand` feature) collects into a
+///   `Vec` and shuffles it, either with the thread-local RNG or the given
+///   `&mut` RNG.
+/// - `in <arena>, Vec<_>` (requires the `bumpalo` feature) collects into a
+///   `bumpalo::collections::Vec` allocated in the given `&Bump`.
+/// - `into <&mut collection>` adds the items to the end of an existing
+///   collection and returns the `&mut`, so a buffer can be cleared and
+///   reused without reallocating.
+/// - `as <ty>` collects into a `Vec` and converts it into `ty` with `From`,
+///   so a wrapper type like `struct SortedIds(Vec<u32>)` can come straight
+///   out of the macro. `try as <ty>` converts with `TryFrom` instead and
+///   returns its `Result`, e.g. to get an array of a fixed length.
+/// - `bounded <collect_ty>` collects into a fixed-capacity
+///   [`Bounded`](terminals::Bounded) collection, like a `heapless::Vec`
+///   with the `heapless` feature, returning `Err` with the first item that
+///   didn't fit.
+/// - `arrow <array_ty>` (requires the `arrow` feature) builds an Arrow
+///   array, like an `Int64Array` or a `StringArray`. The mapper can return
+///   plain values or `Option`s, with `None` becoming a null.
+/// - `minmax` finds the minimum and maximum in one pass, returning a
+///   [`MinMax`](terminals::MinMax).
+/// - `tee (<collect_ty>, <collect_ty>)` takes a mapper that returns pairs
+///   of `Option`s and collects the first halves into one collection and
+///   the second halves into the other, skipping `None`s, in a single pass
+///   over the sources. Each half gets its own mapper and, with
+///   `bool::then`, its own guard. Plain pairs don't need `tee`; a tuple of
+///   collection types already collects them.
+/// - `demux { <pattern> [if <cond>] => <name>: <collect_ty> = <value>, ... }`
+///   matches each item against the arms and adds the first matching arm's
+///   `value` to its collection, returning a tuple with one collection per
+///   arm, in order. The match has to be exhaustive, and the collections
+///   must implement `Default` and `Extend`.
+/// - `partition (<collect_ty>, <collect_ty>)` (requires the `either`
+///   feature) takes a mapper that returns `Either`s and collects the `Left`
+///   values into one collection and the `Right` values into the other.
+/// - `fminmax` is `minmax` for floats. NaNs are skipped, and everything
+///   else is compared with `total_cmp`.
+/// - `validate` takes a mapper that returns `Result`s and returns either
+///   `Ok` with every value or `Err` with every error, in a `Vec`.
+/// - `group [(<map_ty>)] by <key_fn>` groups the items by the key returned
+///   by `key_fn`, into a `HashMap<_, Vec<_>>` unless another map (and group
+///   collection) is given, like `BTreeMap<_, Vec<_>>`.
+/// - `reduce [(<map_ty>)] with <f>` takes a mapper that returns
+///   `(key, value)` pairs and folds the values for each key with `f`, into
+///   a `HashMap<_, _>` unless another map is given, like `BTreeMap<_, _>`.
+/// - `approx_count_distinct` (requires the `hll` feature) estimates the
+///   number of distinct items with a
+///   [`HyperLogLog`](terminals::HyperLogLog) sketch, in a fixed 16 KiB
+///   instead of a `HashSet` that grows with the stream.
+/// - `sorted unique` collects into a `Vec`, sorts it, and removes the
+///   duplicates, instead of chaining `sort` and `dedup` after the macro.
+/// - `cumsum` collects the running totals into a `Vec`.
+/// - `try_sum` sums integers, returning `None` if the sum overflows
+///   instead of wrapping around.
+/// - `try_fold [<name> =] <init>, <f>` folds the items into an accumulator
+///   that starts at `init`, where `f` returns a `Result` or an `Option`.
+///   It stops at the first `Err` or `None` and returns it, so reducing and
+///   validating happen in one pass. The name only labels the accumulator.
+/// - `most_common [<n>]` counts each distinct item and returns
+///   `(item, count)` pairs, most common first, optionally keeping only the
+///   top `n`.
+/// - `histogram [by <bucket>]` counts the items in each bucket, or each
+///   distinct item, into a `BTreeMap`. `bucket` is given a reference to the
+///   item.
+///
+/// ```rust
+/// # #[cfg(feature = "rand")] {
+/// # use rustcomp::rcomp;
+/// let mut v = rcomp![shuffled; for x in 0..10 => x, if x % 2 == 0];
+/// v.sort_unstable();
+/// assert_eq!(v, vec![0, 2, 4, 6, 8]);
+/// # }
+/// ```
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// # use std::collections::HashSet;
+/// let users = [("alice", "admin"), ("bob", "dev"), ("carol", "dev")];
+/// let (admins, roles) = rcomp![tee (Vec<_>, HashSet<_>);
+///     for (name, role) in users => ((role == "admin").then_some(name), Some(role.len()))
+/// ];
+/// assert_eq!(admins, ["alice"]);
+/// assert_eq!(roles, HashSet::from([5, 3]));
+/// ```
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// # use std::collections::BTreeSet;
+/// let inputs = ["1", "x", "", "3", "y"];
+/// let (numbers, blanks, errors) = rcomp![demux {
+///     (_, Ok(n)) => numbers: Vec<u32> = n,
+///     (s, Err(_)) if s.is_empty() => blanks: Vec<_> = (),
+///     (s, Err(e)) => errors: BTreeSet<_> = (s, e.to_string()),
+/// }; for s in inputs => (s, s.parse::<u32>())];
+/// assert_eq!(numbers, [1, 3]);
+/// assert_eq!(blanks.len(), 1);
+/// assert_eq!(errors.iter().map(|(s, _)| *s).collect::<Vec<_>>(), ["x", "y"]);
+/// ```
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// #[derive(Debug, PartialEq)]
+/// struct Overflow;
+///
+/// let total = rcomp![try_fold acc = 0u8, |acc, x| acc.checked_add(x).ok_or(Overflow);
+///     for x in [100, 100, 100] => x
+/// ];
+/// assert_eq!(total, Err(Overflow));
+/// ```
+///
+/// See the [crate-level documentation](crate) for more examples.
+#[macro_export]
+macro_rules! rcomp {
+    // a `let` clause isn't a level of its own. it matches its pattern inside
+    // the level before it, and the levels after it go in the match arm.
+    (@__ [$($mv:tt)?] $mapper:tt $guard:tt $probe:tt (let $pat:tt $e:tt $($skip:ident)?) $($recurse:tt)+) => (
+        $crate::rcomp!(@bind [flatten] $pat $e [$($skip)?] $crate::rcomp!(@__ [$($mv)?] $mapper $guard $probe $($recurse)+))
+    );
+    (@__ $mv:tt [memo $($mapper:tt)*] $guard:tt $probe:tt (let $($bind:tt)*)) => (
+        ::std::compile_error!("the innermost clause has to be a `for-in` clause for `memo` to cache anything")
+    );
+    // a block mapper has to be in a closure, so it gets a level that yields
+    // a single `()`
+    (@__ $mv:tt [block $($mapper:tt)*] $guard:tt $probe:tt (let $($bind:tt)*)) => (
+        $crate::rcomp!(@__ $mv [block $($mapper)*] $guard $probe (let $($bind)*) ([()] [::std::iter::once(())]))
+    );
+    // the mapper is matched as tokens, since every rule up to the one
+    // that matches it is tried for block mappers too
+    (@__ [$($mv:tt)?] [$($mapper:tt)*] [$($guard:expr)?] [$($probe:expr $(, $lvl:tt)*)?] (let $pat:tt $e:tt $($skip:ident)?)) => (
+        $crate::rcomp!(@bind [] $pat $e [$($skip)?] {
+            $($crate::report::Probe::scanned(&$probe);)?
+            if $($guard &&)? true {
+                ::std::option::Option::Some($($mapper)*)
+            } else {
+                $($crate::report::Probe::rejected(&$probe);)?
+                ::std::option::Option::None
+            }
+        })
+    );
+    (@bind [$($flatten:ident)?] [$pat:pat] [$e:expr] [] $body:expr) => (
+        match $e {
+            $pat => $body,
+        }
+    );
+    (@bind [] [$pat:pat] [$e:expr] [skip] $body:expr) => (
+        match $e {
+            $pat => $body,
+            #[allow(unreachable_patterns)]
+            _ => ::std::option::Option::None,
+        }
+    );
+    (@bind [flatten] [$pat:pat] [$e:expr] [skip] $body:expr) => (
+        ::std::iter::Iterator::flatten(::std::option::Option::into_iter(match $e {
+            $pat => ::std::option::Option::Some($body),
+            #[allow(unreachable_patterns)]
+            _ => ::std::option::Option::None,
+        }))
+    );
+    // the probe slot also counts how deep the current level is, as a
+    // series of `1`s following the probe
+    (@__ [$($mv:tt)?] [memo $mapper:expr] [$($guard:expr)?] [$($probe:expr $(, $lvl:tt)*)?] ([$var:pat] [$($src:tt)*])) => (
+        $crate::adapters::memo_map(
+            $crate::rcomp!(@sourced [$($probe $(, $lvl)*)?] $crate::rcomp!(@source $($src)*))
+                $(.inspect(|_| {
+                    $crate::report::Probe::produced(&$probe, 0 $(+ $lvl)*);
+                    $crate::report::Probe::scanned(&$probe);
+                }))?,
+            $($mv)? |$var| if $($guard &&)? true { Some($mapper) } else { None },
+        )
+        $(.inspect(|__out| if __out.is_none() { $crate::report::Probe::rejected(&$probe) }))?
+        .flatten()
+    );
+    // a block mapper can use `emit!` and `skip!`, so it yields any number of
+    // items per input
+    (@__ [$($mv:tt)?] [block $mapper:block] [$($guard:expr)?] [$($probe:expr $(, $lvl:tt)*)?] ([$($vars:pat),+] [$($src:tt)*])) => (
+        $crate::rcomp!(@sourced [$($probe $(, $lvl)*)?] $crate::rcomp!(@source $($src)*))
+            .flat_map($($mv)? |$($vars),*| {
+                $(
+                    $crate::report::Probe::produced(&$probe, 0 $(+ $lvl)*);
+                    $crate::report::Probe::scanned(&$probe);
+                )?
+                let mut __emits = $crate::adapters::Emits::new();
+                if $($guard &&)? true {
+                    #[allow(unused_macros)]
+                    macro_rules! emit {
+                        ($value:expr) => {
+                            __emits.push($value)
+                        };
+                    }
+                    #[allow(unused_macros)]
+                    macro_rules! skip {
+                        () => {
+                            return __emits
+                        };
+                    }
+                    #[allow(unreachable_code)]
+                    {
+                        let __last = $mapper;
+                        __emits.push(__last);
+                    }
+                } else {
+                    $($crate::report::Probe::rejected(&$probe);)?
+                }
+                __emits
+            })
+    );
+    (@__ [$($mv:tt)?] [$($mapper:tt)*] [$($guard:expr)?] [$($probe:expr $(, $lvl:tt)*)?] ([$($vars:pat),+] [$($src:tt)*]) $($recurse:tt)+) => (
+        $crate::rcomp!(@sourced [$($probe $(, $lvl)*)?] $crate::rcomp!(@source $($src)*))
+            .flat_map($($mv)? |$($vars),*| {
+                $($crate::report::Probe::produced(&$probe, 0 $(+ $lvl)*);)?
+                $crate::rcomp!(@__ [$($mv)?] [$($mapper)*] [$($guard)?] [$($probe $(, $lvl)*, 1)?] $($recurse)+)
+            })
+    );
+    (@__ [$($mv:tt)?] [$mapper:expr] [$($guard:expr)?] [$($probe:expr $(, $lvl:tt)*)?] ([$($vars:pat),+] [$($src:tt)*])) => (
+        $crate::rcomp!(@sourced [$($probe $(, $lvl)*)?] $crate::rcomp!(@source $($src)*))
+            .filter_map($($mv)? |$($vars),*| {
+                $(
+                    $crate::report::Probe::produced(&$probe, 0 $(+ $lvl)*);
+                    $crate::report::Probe::scanned(&$probe);
+                )?
+                // `&& true` is a trick to make the guard optional
+                if $($guard &&)? true {
+                    Some($mapper)
+                } else {
+                    $($crate::report::Probe::rejected(&$probe);)?
+                    None
+                }
+            })
+    );
+    // tells the probe, if there is one, how long each source looks
+    (@sourced [] $src:expr) => (
+        ::std::iter::IntoIterator::into_iter($src)
+    );
+    (@sourced [$probe:expr $(, $lvl:tt)*] $src:expr) => (
+        $crate::report::sourced($src, &$probe, 0 $(+ $lvl)*)
+    );
+    // sources are usually plain expressions, but a few keywords can go with
+    // them to change how they're iterated
+    // `cycle [a, b]` would parse as indexing, which fails on the comma
+    // instead of moving on, and `ident [..] take` is never an expression
+    (@source cycle [$($items:tt)*] take $n:expr) => (
+        $crate::sources::cycle([$($items)*], $n)
+    );
+    (@source $iter:expr) => (
+        $iter
+    );
+    (@source combinations $k:tt of $src:expr) => (
+        $crate::sources::combinations::<_, $k>($src)
+    );
+    (@source permutations $k:tt of $src:expr) => (
+        $crate::sources::permutations::<_, $k>($src)
+    );
+    (@source powerset $max:tt of $src:expr) => (
+        $crate::sources::powerset($src, $max)
+    );
+    // after the plain expression, so a variable named `cycle` can still be
+    // used as a source, like `cycle.iter()`
+    (@source cycle $($t:tt)+) => (
+        $crate::rcomp!(@cycle [] $($t)+)
+    );
+    (@source peeking $src:expr) => (
+        $crate::sources::peeking($src)
+    );
+    (@source powerset $src:expr) => (
+        $crate::sources::powerset($src, ::std::primitive::usize::MAX)
+    );
+    (@source merge sorted($($src:expr),+ $(,)?)) => (
+        $crate::sources::merge_sorted([$(
+            ::std::boxed::Box::new(::std::iter::IntoIterator::into_iter($src))
+                as ::std::boxed::Box<dyn ::std::iter::Iterator<Item = _>>
+        ),+])
+    );
+    (@source from $($t:tt)+) => (
+        $crate::rcomp!(@generate [] $($t)+)
+    );
+    (@source $($t:tt)+) => (
+        $crate::rcomp!(@source_munch [] $($t)+)
+    );
+    // `from <init> then <step> [while <pred>]` is munched up to each keyword,
+    // since `then` and `while` can't follow an expression
+    (@generate [$($init:tt)+] then $($rest:tt)+) => (
+        $crate::rcomp!(@generate [$($init)+] [] $($rest)+)
+    );
+    (@generate [$($init:tt)+] [$($step:tt)+] while $($pred:tt)+) => (
+        $crate::sources::generate($($init)+, $($step)+).take_while($($pred)+)
+    );
+    (@generate [$($init:tt)+] [$($step:tt)+]) => (
+        $crate::sources::generate($($init)+, $($step)+)
+    );
+    (@generate [$($init:tt)+] [$($step:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp!(@generate [$($init)+] [$($step)* $next] $($rest)*)
+    );
+    (@generate [$($init:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp!(@generate [$($init)* $next] $($rest)*)
+    );
+    // `cycle <source> take <n>` is munched up to `take`, which can't follow
+    // an expression. without it, the source would never end.
+    (@cycle [$($src:tt)+] take $n:expr) => (
+        $crate::sources::cycle($($src)+, $n)
+    );
+    (@cycle [$($src:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp!(@cycle [$($src)* $next] $($rest)*)
+    );
+    (@cycle [$($src:tt)*]) => (
+        ::std::compile_error!("`cycle` never ends on its own, so it needs a `take <n>` after the source")
+    );
+    (@source_munch [$($roots:tt)+] descend $children:expr) => (
+        $crate::sources::descend($($roots)+, $children)
+    );
+    (@source_munch [$($a:tt)+] outer join $b:expr) => (
+        $crate::sources::outer_join($($a)+, $b)
+    );
+    (@source_munch [$($src:tt)+] while $pred:expr) => (
+        ::std::iter::Iterator::take_while(::std::iter::IntoIterator::into_iter($($src)+), $pred)
+    );
+    (@source_munch [$($a:tt)+] zip longest $b:expr) => (
+        $crate::sources::zip_longest($($a)+, $b)
+    );
+    (@source_munch [$($acc:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp!(@source_munch [$($acc)* $next] $($rest)*)
+    );
+    // builds the body of the comprehension, once any `static` sources have
+    // been hoisted out of the closures and, with `indexed`, the sources have
+    // been numbered
+    (@body $cfg:tt $mapper:tt $guard:tt indexed $($gens:tt)*) => (
+        $crate::rcomp!(@at [indexed] $cfg $mapper $guard [] $($gens)*)
+    );
+    (@body $cfg:tt $mapper:tt $guard:tt $($gens:tt)*) => (
+        $crate::rcomp!(@at [] $cfg $mapper $guard [] $($gens)*)
+    );
+    // a source ending in `@ <name>` is enumerated into `name`. the source is
+    // munched to find out whether it ends that way.
+    (@at $idx:tt $cfg:tt $mapper:tt $guard:tt [$($done:tt)*] ([$($pat:tt)*] [$($src:tt)*]) $($rest:tt)*) => (
+        $crate::rcomp!(@at_src ($idx $cfg $mapper $guard [$($done)*] [$($rest)*]) [$($pat)*] [] [$($src)*])
+    );
+    (@at $idx:tt $cfg:tt $mapper:tt $guard:tt [$($done:tt)*] (let $($bind:tt)*) $($rest:tt)*) => (
+        $crate::rcomp!(@at $idx $cfg $mapper $guard [$($done)* (let $($bind)*)] $($rest)*)
+    );
+    (@at $idx:tt $cfg:tt $mapper:tt $guard:tt [$($done:tt)*]) => (
+        $crate::rcomp!(@hoist $idx $cfg $mapper $guard [] $($done)*)
+    );
+    (@at_src $state:tt [$($pat:tt)*] [static $($src:tt)+] [@ $i:ident]) => (
+        $crate::rcomp!(@at_gen $state ([($i, $($pat)*)] [static $crate::rcomp!(@enumerate $($src)+)]))
+    );
+    (@at_src $state:tt [$($pat:tt)*] [$($src:tt)+] [@ $i:ident]) => (
+        $crate::rcomp!(@at_gen $state ([($i, $($pat)*)] [$crate::rcomp!(@enumerate $($src)+)]))
+    );
+    // a `while` followed by a condition instead of a closure is turned into
+    // one, with the clause's pattern bound to a copy of the item
+    (@at_src $state:tt [$($pat:tt)*] [$($src:tt)+] [while | $($more:tt)*]) => (
+        $crate::rcomp!(@at_src $state [$($pat)*] [$($src)+ while |] [$($more)*])
+    );
+    (@at_src $state:tt [$($pat:tt)*] [$($src:tt)+] [while || $($more:tt)*]) => (
+        $crate::rcomp!(@at_src $state [$($pat)*] [$($src)+ while ||] [$($more)*])
+    );
+    (@at_src $state:tt [$($pat:tt)*] [$($src:tt)+] [while move $($more:tt)*]) => (
+        $crate::rcomp!(@at_src $state [$($pat)*] [$($src)+ while move] [$($more)*])
+    );
+    (@at_src $state:tt [$($pat:tt)*] [$($src:tt)+] [while $($cond:tt)+]) => (
+        $crate::rcomp!(@at_gen $state ([$($pat)*] [$($src)+ while |__item| {
+            #[allow(unused_variables, unused_mut)]
+            let $($pat)* = *__item;
+            $($cond)+
+        }]))
+    );
+    (@at_src $state:tt [$($pat:tt)*] [$($src:tt)*] [$next:tt $($more:tt)*]) => (
+        $crate::rcomp!(@at_src $state [$($pat)*] [$($src)* $next] [$($more)*])
+    );
+    (@at_src $state:tt [$($pat:tt)*] [$($src:tt)*] []) => (
+        $crate::rcomp!(@at_gen $state ([$($pat)*] [$($src)*]))
+    );
+    (@at_gen ($idx:tt $cfg:tt $mapper:tt $guard:tt [$($done:tt)*] [$($rest:tt)*]) $gen:tt) => (
+        $crate::rcomp!(@at $idx $cfg $mapper $guard [$($done)* $gen] $($rest)*)
+    );
+    (@enumerate $($src:tt)+) => (
+        ::std::iter::Iterator::enumerate(::std::iter::IntoIterator::into_iter($crate::rcomp!(@source $($src)+)))
+    );
+    // a `static` source is evaluated with the outermost source, and a clone
+    // of it is passed down alongside the items of every level above it.
+    // there's nothing to hoist on the outermost level.
+    (@hoist $idx:tt $cfg:tt $mapper:tt $guard:tt [] ([$($pat:tt)*] [static $($src:tt)+]) $($rest:tt)*) => (
+        $crate::rcomp!(@hoist $idx $cfg $mapper $guard [([$($pat)*] [$($src)+])] $($rest)*)
+    );
+    (@hoist $idx:tt $cfg:tt $mapper:tt $guard:tt [$($done:tt)+] ([$($pat:tt)*] [static $($src:tt)+]) $($rest:tt)*) => (
+        $crate::rcomp!(
+            @thread __shared [$($src)+] [] [$($done)+]
+            ($idx $cfg $mapper $guard ([$($pat)*] [__shared]) $($rest)*)
+        )
+    );
+    (@hoist $idx:tt $cfg:tt $mapper:tt $guard:tt [$($done:tt)*] $gen:tt $($rest:tt)*) => (
+        $crate::rcomp!(@hoist $idx $cfg $mapper $guard [$($done)* $gen] $($rest)*)
+    );
+    (@hoist [] $cfg:tt $mapper:tt $guard:tt [$($done:tt)*]) => (
+        $crate::rcomp!(@drive $cfg $mapper $guard $($done)*)
+    );
+    (@hoist [indexed] $cfg:tt $mapper:tt $guard:tt [$($done:tt)*]) => (
+        $crate::rcomp!(@index $cfg $mapper $guard [] [] [] $($done)*)
+    );
+    // the first level evaluates the hoisted source, the rest pass it on
+    (@thread $s:ident [$($value:tt)+] [$($out:tt)*] [([$($pat:tt)*] [$($src:tt)*]) $($done:tt)*] $next:tt) => (
+        $crate::rcomp!(
+            @thread $s [$s]
+            [$($out)* ([($($pat)*, $s)] [$crate::sources::shared($crate::rcomp!(@source $($src)*), $crate::rcomp!(@source $($value)+))])]
+            [$($done)*] $next
+        )
+    );
+    (@thread $s:ident $value:tt [$($out:tt)*] [(let $($bind:tt)*) $($done:tt)*] $next:tt) => (
+        $crate::rcomp!(@thread $s $value [$($out)* (let $($bind)*)] [$($done)*] $next)
+    );
+    (@thread $s:ident $value:tt [$($out:tt)*] [] ($idx:tt $cfg:tt $mapper:tt $guard:tt $($rest:tt)*)) => (
+        $crate::rcomp!(@hoist $idx $cfg $mapper $guard [$($out)*] $($rest)*)
+    );
+    // the numbers are passed down through the sources, since the inner
+    // levels can't borrow them from the outer ones. each level's key is the
+    // previous key paired with its own number, so the final key is nested,
+    // like `((i, j), k)`, and is flattened with a pattern of fresh `__i`s.
+    (@index $cfg:tt $mapper:tt $guard:tt [$($done:tt)*] [] [] ([$($pat:tt)*] [$($src:tt)*]) $($rest:tt)*) => (
+        $crate::rcomp!(
+            @index $cfg $mapper $guard
+            [([(__key, $($pat)*)] [::std::iter::Iterator::enumerate(::std::iter::IntoIterator::into_iter($crate::rcomp!(@source $($src)*)))])]
+            [__i] [__key __i] $($rest)*
+        )
+    );
+    (@index $cfg:tt $mapper:tt $guard:tt [$($done:tt)*] [$($ids:ident),+] [$prev:ident $keypat:tt] ([$($pat:tt)*] [$($src:tt)*]) $($rest:tt)*) => (
+        $crate::rcomp!(
+            @index $cfg $mapper $guard
+            [$($done)* ([(__key, $($pat)*)] [$crate::sources::index_under($crate::rcomp!(@source $($src)*), $prev)])]
+            [$($ids,)+ __i] [__key ($keypat, __i)] $($rest)*
+        )
+    );
+    (@index $cfg:tt $mapper:tt $guard:tt [$($done:tt)*] $ids:tt $key:tt (let $($bind:tt)*) $($rest:tt)*) => (
+        $crate::rcomp!(@index $cfg $mapper $guard [$($done)* (let $($bind)*)] $ids $key $($rest)*)
+    );
+    (@index $cfg:tt [block $($mapper:tt)*] $guard:tt [$($done:tt)*] $ids:tt $key:tt) => (
+        $crate::rcomp!(@index $cfg [$($mapper)*] $guard [$($done)*] $ids $key)
+    );
+    (@index $cfg:tt [memo $($mapper:tt)*] $guard:tt [$($done:tt)*] [$($ids:ident),+] [$key:ident $keypat:tt]) => (
+        $crate::rcomp!(@drive $cfg [memo {
+            let $keypat = $key;
+            ($crate::rcomp!(@key $($ids),+), $($mapper)*)
+        }] $guard $($done)*)
+    );
+    (@index $cfg:tt [$($mapper:tt)*] $guard:tt [$($done:tt)*] [$($ids:ident),+] [$key:ident $keypat:tt]) => (
+        $crate::rcomp!(@drive $cfg [{
+            let $keypat = $key;
+            ($crate::rcomp!(@key $($ids),+), $($mapper)*)
+        }] $guard $($done)*)
+    );
+    // tells the probe, if there is one, how many `for-in` clauses there are
+    (@started [] $($gens:tt)*) => ();
+    (@started [$probe:expr] $($gens:tt)*) => (
+        $crate::report::Probe::started(&$probe, $crate::rcomp!(@levels $($gens)*))
+    );
+    (@levels) => (
+        0
+    );
+    (@levels (let $($bind:tt)*) $($gens:tt)*) => (
+        $crate::rcomp!(@levels $($gens)*)
+    );
+    (@levels $gen:tt $($gens:tt)*) => (
+        1 + $crate::rcomp!(@levels $($gens)*)
+    );
+    (@key $i:ident) => (
+        $i
+    );
+    (@key $($i:ident),+) => (
+        ($($i),+)
+    );
+    // drives the comprehension. the config comes from any modifiers in
+    // front of the collection type and holds, in order, the mode that
+    // decides how the outermost `for-in` clause is driven, the probe,
+    // whether the closures are `move`, and the collection type, if any.
+    (@drive {[] [] [$($mv:tt)?] $collect:tt} [$($mapper:tt)*] [$($guard:expr)?] $($gens:tt)*) => (
+        $crate::rcomp!(@__ [$($mv)?] [$($mapper)*] [$($guard)?] [] $($gens)*)
+    );
+    (@drive {[] [$probe:expr] [$($mv:tt)?] $collect:tt} [$($mapper:tt)*] [$($guard:expr)?] $($gens:tt)*) => ({
+        $crate::rcomp!(@started [$probe] $($gens)*);
+        $crate::rcomp!(@__ [$($mv)?] [$($mapper)*] [$($guard)?] [$probe] $($gens)*)
+    });
+    // block mappers would be parsed as struct literals by the `expr` rules
+    // below, so they're turned away first
+    (@drive {[simd] $($cfg:tt)*} [block $($mapper:tt)*] $($t:tt)*) => (
+        ::std::compile_error!("the `simd` modifier doesn't support block mappers")
+    );
+    (@drive {[loops] $($cfg:tt)*} [block $($mapper:tt)*] $($t:tt)*) => (
+        ::std::compile_error!("the `loops` modifier doesn't support block mappers")
+    );
+    (@drive {[simd] [] [$($mv:tt)?] $collect:tt} [$mapper:expr] [] ([$var:pat] [$($src:tt)*])) => (
+        $crate::adapters::simd_map(&$crate::rcomp!(@source $($src)*), $($mv)? |$var| $mapper)
+    );
+    (@drive {[simd] $($cfg:tt)*} $($t:tt)*) => (
+        ::std::compile_error!(
+            "the `simd` modifier needs a single `for-in` clause over a slice, a plain mapper, no guard, and no probes"
+        )
+    );
+    // every clause runs up front, so without a collection type there'd be
+    // an iterator or a terminal that looks lazy but isn't
+    (@drive {[loops] [] [$($mv:tt)?] [$collect:path]} [$mapper:expr] [$($guard:expr)?] $($gens:tt)+) => ({
+        let mut __v = ::std::vec::Vec::new();
+        $crate::rcomp!(@loops __v [$mapper] [$($guard)?] $($gens)+);
+        __v.into_iter()
+    });
+    (@drive {[loops] [] $mv:tt []} $($t:tt)*) => (
+        ::std::compile_error!(
+            "the `loops` modifier runs every clause up front, so it needs a collection type instead of an iterator or a terminal"
+        )
+    );
+    (@drive {[loops] $($cfg:tt)*} $($t:tt)*) => (
+        ::std::compile_error!("the `loops` modifier doesn't support `memo` or probes")
+    );
+    // the `Vec` is passed around by name so that every level refers to the
+    // same binding
+    (@loops $v:ident [$mapper:expr] [$($guard:expr)?] (let [$pat:pat] [$e:expr]) $($recurse:tt)*) => (
+        match $e {
+            $pat => $crate::rcomp!(@loops $v [$mapper] [$($guard)?] $($recurse)*),
+        }
+    );
+    (@loops $v:ident [$mapper:expr] [$($guard:expr)?] (let [$pat:pat] [$e:expr] skip) $($recurse:tt)*) => (
+        match $e {
+            $pat => $crate::rcomp!(@loops $v [$mapper] [$($guard)?] $($recurse)*),
+            #[allow(unreachable_patterns)]
+            _ => {}
+        }
+    );
+    (@loops $v:ident [$mapper:expr] [$($guard:expr)?]) => (
+        if $($guard &&)? true {
+            $v.push($mapper);
+        }
+    );
+    (@loops $v:ident [$mapper:expr] [$($guard:expr)?] ([$var:pat] [$($src:tt)*])) => (
+        for $var in $crate::rcomp!(@source $($src)*) {
+            if $($guard &&)? true {
+                $v.push($mapper);
+            }
+        }
+    );
+    (@loops $v:ident [$mapper:expr] [$($guard:expr)?] ([$var:pat] [$($src:tt)*]) $($recurse:tt)+) => (
+        for $var in $crate::rcomp!(@source $($src)*) {
+            $crate::rcomp!(@loops $v [$mapper] [$($guard)?] $($recurse)+);
+        }
+    );
+    (@drive {[par] [$($probe:expr)?] [$($mv:tt)?] $collect:tt} [$($mapper:tt)*] [$($guard:expr)?] ([$var:pat] [$($src:tt)*]) $($gens:tt)*) => ({
+        $crate::rcomp!(@started [$($probe)?] ([$var] [$($src)*]) $($gens)*);
+        $crate::par::each($crate::rcomp!(@source $($src)*), $($mv)? |__item| {
+            $crate::rcomp!(@__ [$($mv)?] [$($mapper)*] [$($guard)?] [$($probe)?] ([$var] [::std::iter::once(__item)]) $($gens)*)
+                .collect::<::std::vec::Vec<_>>()
+        })
+    });
+    (@drive {[par chunks $n:expr] [$($probe:expr)?] [$($mv:tt)?] $collect:tt} [$($mapper:tt)*] [$($guard:expr)?] ([$var:pat] [$($src:tt)*]) $($gens:tt)*) => ({
+        $crate::rcomp!(@started [$($probe)?] ([$var] [$($src)*]) $($gens)*);
+        $crate::par::chunks($n, $crate::rcomp!(@source $($src)*), $($mv)? |__chunk| {
+            $crate::rcomp!(@__ [$($mv)?] [$($mapper)*] [$($guard)?] [$($probe)?] ([$var] [__chunk]) $($gens)*)
+                .collect::<::std::vec::Vec<_>>()
+        })
+    });
+    (@drive {[threads $n:expr] [$($probe:expr)?] [$($mv:tt)?] $collect:tt} [$($mapper:tt)*] [$($guard:expr)?] ([$var:pat] [$($src:tt)*]) $($gens:tt)*) => ({
+        $crate::rcomp!(@started [$($probe)?] ([$var] [$($src)*]) $($gens)*);
+        $crate::threads::scoped($n, $crate::rcomp!(@source $($src)*), $($mv)? |__chunk| {
+            $crate::rcomp!(@__ [$($mv)?] [$($mapper)*] [$($guard)?] [$($probe)?] ([$var] [__chunk]) $($gens)*)
+                .collect::<::std::vec::Vec<_>>()
+        })
+    });
+    // splits the `for-in` clauses into `([pattern] [source])` pairs. the
+    // pattern ends at `in` and the source ends at the next top-level comma,
+    // or at the `=>` right before the mapper.
+    // a `let` clause is split into its pattern, which ends at `=`, and its
+    // expression, which ends like a source, with an optional `else skip`
+    (@parse $cfg:tt [$($gens:tt)*] [] let $($rest:tt)*) => (
+        $crate::rcomp!(@let_pat $cfg [$($gens)*] [] $($rest)*)
+    );
+    (@parse $cfg:tt [$($gens:tt)*] [$($pat:tt)*] in $($rest:tt)*) => (
+        $crate::rcomp!(@src $cfg [$($gens)*] [$($pat)*] [] $($rest)*)
+    );
+    (@parse $cfg:tt [$($gens:tt)*] [$($pat:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp!(@parse $cfg [$($gens)*] [$($pat)* $next] $($rest)*)
+    );
+    // without a mapper, the innermost pattern doubles as the mapper
+    (@src $cfg:tt [$($gens:tt)*] [$($pat:tt)*] [$($src:tt)+] , if $($rest:tt)*) => (
+        $crate::rcomp!(@guard $cfg [$($gens)* ([$($pat)*] [$($src)+])] [$($pat)*] if $($rest)*)
+    );
+    (@src $cfg:tt [$($gens:tt)*] [$($pat:tt)*] [$($src:tt)+]) => (
+        $crate::rcomp!(@guard $cfg [$($gens)* ([$($pat)*] [$($src)+])] [$($pat)*])
+    );
+    (@src $cfg:tt [$($gens:tt)*] [$($pat:tt)*] [$($src:tt)*] , $($rest:tt)*) => (
+        $crate::rcomp!(@parse $cfg [$($gens)* ([$($pat)*] [$($src)*])] [] $($rest)*)
+    );
+    (@src $cfg:tt [$($gens:tt)*] [$($pat:tt)*] [$($src:tt)*] => memo $mapper:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@guard $cfg [$($gens)* ([$($pat)*] [$($src)*])] [memo $mapper] $($($rest)*)?)
+    );
+    // the block is matched by its braces, since the mapper might already be
+    // an `expr` fragment, which a `block` fragment won't take
+    (@src $cfg:tt [$($gens:tt)*] [$($pat:tt)*] [$($src:tt)*] => { $($body:tt)* } $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@guard $cfg [$($gens)* ([$($pat)*] [$($src)*])] [block { $($body)* }] $($($rest)*)?)
+    );
+    (@src $cfg:tt [$($gens:tt)*] [$($pat:tt)*] [$($src:tt)*] => $mapper:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@guard $cfg [$($gens)* ([$($pat)*] [$($src)*])] [$mapper] $($($rest)*)?)
+    );
+    (@src $cfg:tt [$($gens:tt)*] [$($pat:tt)*] [$($src:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp!(@src $cfg [$($gens)*] [$($pat)*] [$($src)* $next] $($rest)*)
+    );
+    (@let_pat $cfg:tt [$($gens:tt)*] [$($pat:tt)+] = $($rest:tt)*) => (
+        $crate::rcomp!(@let_src $cfg [$($gens)*] [$($pat)+] [] $($rest)*)
+    );
+    (@let_pat $cfg:tt [$($gens:tt)*] [$($pat:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp!(@let_pat $cfg [$($gens)*] [$($pat)* $next] $($rest)*)
+    );
+    (@let_src $cfg:tt [$($gens:tt)*] [$($pat:tt)+] [$($e:tt)+] else skip $($rest:tt)*) => (
+        $crate::rcomp!(@let_gen $cfg [$($gens)* (let [$($pat)+] [$($e)+] skip)] $($rest)*)
+    );
+    (@let_src $cfg:tt [$($gens:tt)*] [$($pat:tt)+] [$($e:tt)+] , $($rest:tt)*) => (
+        $crate::rcomp!(@let_gen $cfg [$($gens)* (let [$($pat)+] [$($e)+])] , $($rest)*)
+    );
+    (@let_src $cfg:tt [$($gens:tt)*] [$($pat:tt)+] [$($e:tt)+] => $($rest:tt)*) => (
+        $crate::rcomp!(@let_gen $cfg [$($gens)* (let [$($pat)+] [$($e)+])] => $($rest)*)
+    );
+    (@let_src $cfg:tt [$($gens:tt)*] [$($pat:tt)+] [$($e:tt)+]) => (
+        $crate::rcomp!(@let_gen $cfg [$($gens)* (let [$($pat)+] [$($e)+])])
+    );
+    (@let_src $cfg:tt [$($gens:tt)*] [$($pat:tt)+] [$($e:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp!(@let_src $cfg [$($gens)*] [$($pat)+] [$($e)* $next] $($rest)*)
+    );
+    (@let_gen $cfg:tt [$($gens:tt)*] , $($rest:tt)*) => (
+        $crate::rcomp!(@parse $cfg [$($gens)*] [] $($rest)*)
+    );
+    (@let_gen $cfg:tt [$($gens:tt)*] => memo $mapper:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@guard $cfg [$($gens)*] [memo $mapper] $($($rest)*)?)
+    );
+    (@let_gen $cfg:tt [$($gens:tt)*] => { $($body:tt)* } $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@guard $cfg [$($gens)*] [block { $($body)* }] $($($rest)*)?)
+    );
+    (@let_gen $cfg:tt [$($gens:tt)*] => $mapper:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@guard $cfg [$($gens)*] [$mapper] $($($rest)*)?)
+    );
+    (@let_gen $cfg:tt [$($gens:tt)*]) => (
+        ::std::compile_error!("a comprehension that ends in a `let` clause needs a mapper")
+    );
+    (@guard $cfg:tt [$($gens:tt)*] [$($mapper:tt)*] if $guard:expr, else $reject:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(
+            @guard $cfg [$($gens)*] [$($mapper)*]
+            if {
+                let __keep = $guard;
+                if !__keep {
+                    $reject;
+                }
+                __keep
+            }
+            $(, $($rest)*)?
+        )
+    );
+    (@guard $cfg:tt [$($gens:tt)*] [$($mapper:tt)*] if $guard:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(
+            @clauses $cfg
+            ($crate::rcomp!(@body $cfg [$($mapper)*] [$guard] $($gens)*))
+            $($($rest)*)?
+        )
+    );
+    (@guard $cfg:tt [$($gens:tt)*] [$($mapper:tt)*] $($rest:tt)*) => (
+        $crate::rcomp!(
+            @clauses $cfg
+            ($crate::rcomp!(@body $cfg [$($mapper)*] [] $($gens)*))
+            $($rest)*
+        )
+    );
+    // trailing clauses wrap the whole iterator, not just the innermost level
+    (@clauses $cfg:tt ($it:expr) unique by $key:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::unique_by($it, $key)) $($($rest)*)?)
+    );
+    (@clauses $cfg:tt ($it:expr) intersperse $sep:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::intersperse($it, $sep)) $($($rest)*)?)
+    );
+    (@clauses $cfg:tt ($it:expr) chunk by $key:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::chunk_by($it, $key)) $($($rest)*)?)
+    );
+    (@clauses $cfg:tt ($it:expr) split when $pred:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::split_when($it, $pred)) $($($rest)*)?)
+    );
+    // closures are passed to a function that says what their argument is
+    (@clauses $cfg:tt ($it:expr) via |$arg:pat_param| $body:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::adapt_with($it, |$arg| $body)) $($($rest)*)?)
+    );
+    (@clauses $cfg:tt ($it:expr) via move |$arg:pat_param| $body:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::adapt_with($it, move |$arg| $body)) $($($rest)*)?)
+    );
+    (@clauses $cfg:tt ($it:expr) via $adapter:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(
+            @clauses $cfg
+            ($crate::adapters::CompAdapter::adapt($adapter, ::std::iter::IntoIterator::into_iter($it)))
+            $($($rest)*)?
+        )
+    );
+    (@clauses $cfg:tt ($it:expr) coalesce $f:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::coalesce($it, $f)) $($($rest)*)?)
+    );
+    (@clauses $cfg:tt ($it:expr) pad to $($rest:tt)+) => (
+        $crate::rcomp!(@pad $cfg ($it) [] $($rest)+)
+    );
+    (@clauses $cfg:tt ($it:expr) rolling sum $n:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::rolling_sum($it, $n)) $($($rest)*)?)
+    );
+    (@clauses $cfg:tt ($it:expr) rolling mean $n:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::rolling_mean($it, $n)) $($($rest)*)?)
+    );
+    (@clauses $cfg:tt ($it:expr) rolling $n:expr, $f:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::rolling($it, $n, $f)) $($($rest)*)?)
+    );
+    (@clauses $cfg:tt ($it:expr) on error skip $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(
+            @clauses $cfg
+            ($crate::adapters::on_error($it, $crate::adapters::OnError::Skip))
+            $($($rest)*)?
+        )
+    );
+    (@clauses $cfg:tt ($it:expr) on error collect $errors:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(
+            @clauses $cfg
+            ($crate::adapters::on_error($it, $crate::adapters::OnError::Collect($errors)))
+            $($($rest)*)?
+        )
+    );
+    (@clauses $cfg:tt ($it:expr) on error abort $error:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(
+            @clauses $cfg
+            ($crate::adapters::on_error($it, $crate::adapters::OnError::Abort($error)))
+            $($($rest)*)?
+        )
+    );
+    (@clauses $cfg:tt ($it:expr) limit $($rest:tt)+) => (
+        $crate::rcomp!(@limit $cfg ($it) [] $($rest)+)
+    );
+    (@clauses $cfg:tt ($it:expr) sorted unstable $($rest:tt)*) => (
+        $crate::rcomp!(@sorted $cfg ($it) [unstable] $($rest)*)
+    );
+    (@clauses $cfg:tt ($it:expr) sorted parallel $($rest:tt)*) => (
+        $crate::rcomp!(@sorted $cfg ($it) [parallel] $($rest)*)
+    );
+    (@clauses $cfg:tt ($it:expr) sorted $($rest:tt)*) => (
+        $crate::rcomp!(@sorted $cfg ($it) [stable] $($rest)*)
+    );
+    (@clauses {$mode:tt [$($probe:expr)?] $mv:tt []} ($it:expr)) => (
+        $it $(.inspect(|_| $crate::report::Probe::yielded(&$probe)))?
+    );
+    (@clauses {$mode:tt $probe:tt $mv:tt [$collect:path]} ($it:expr)) => (
+        $crate::terminals::collect::<$collect, _>($crate::rcomp!(@clauses {$mode $probe $mv []} ($it)))
+    );
+    // `then` is applied to the collected result, which is why the
+    // collection type is in the config at all
+    (@clauses {$mode:tt $probe:tt $mv:tt [$collect:path]} ($it:expr) then $f:expr) => (
+        $crate::terminals::then($crate::rcomp!(@clauses {$mode $probe $mv [$collect]} ($it)), $f)
+    );
+    (@clauses $cfg:tt ($it:expr) then $($rest:tt)*) => (
+        ::std::compile_error!(
+            "`then` has to be the last clause, and it needs a collection type to apply to"
+        )
+    );
+    // anything else after the mapper is most likely the second half of a
+    // tuple that's missing its parentheses
+    (@clauses $cfg:tt ($it:expr) $($rest:tt)+) => (
+        ::std::compile_error!(::std::concat!(
+            "expected a guard or a clause after the mapper, found `",
+            ::std::stringify!($($rest)+),
+            "`; if the mapper is a tuple, wrap it in parentheses",
+        ))
+    );
+    // the length of the `pad to` clause can't be an `expr`, since `with`
+    // isn't allowed after one
+    (@pad $cfg:tt ($it:expr) [$($n:tt)+] with $fill:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::pad_to($it, $($n)+, || $fill)) $($($rest)*)?)
+    );
+    (@pad $cfg:tt ($it:expr) [$($n:tt)+] $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(
+            @clauses $cfg
+            ($crate::adapters::pad_to($it, $($n)+, ::std::default::Default::default))
+            $($($rest)*)?
+        )
+    );
+    (@pad $cfg:tt ($it:expr) [$($n:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp!(@pad $cfg ($it) [$($n)* $next] $($rest)*)
+    );
+    // same as `pad to`, `per` isn't allowed after an `expr`
+    (@limit $cfg:tt ($it:expr) [$($n:tt)+] per $key:expr $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@clauses $cfg ($crate::adapters::limit_per($it, $($n)+, $key)) $($($rest)*)?)
+    );
+    (@limit $cfg:tt ($it:expr) [$($n:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp!(@limit $cfg ($it) [$($n)* $next] $($rest)*)
+    );
+    // the `sorted` clause, once the sort strategy has been picked
+    (@sorted $cfg:tt ($it:expr) [$how:ident] $($dir:ident)? $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(
+            @clauses $cfg
+            ($crate::rcomp!(@sort [$how] $it, |__a, __b| $crate::rcomp!(@cmp [$($dir)?] __a, __b)))
+            $($($rest)*)?
+        )
+    );
+    (@sorted $cfg:tt ($it:expr) [$how:ident] by ($($($field:tt).+ $($dir:ident)?),+ $(,)?) $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(
+            @clauses $cfg
+            ($crate::rcomp!(@sort [$how] $it, |__a, __b| {
+                ::std::cmp::Ordering::Equal
+                    $(.then_with(|| $crate::rcomp!(@cmp [$($dir)?] &__a.$($field).+, &__b.$($field).+)))+
+            }))
+            $($($rest)*)?
+        )
+    );
+    (@sorted $cfg:tt ($it:expr) [$how:ident] by $($field:tt).+ $($dir:ident)? $(, $($rest:tt)*)?) => (
+        $crate::rcomp!(@sorted $cfg ($it) [$how] by ($($field).+ $($dir)?) $(, $($rest)*)?)
+    );
+    (@sort [stable] $it:expr, $cmp:expr) => (
+        $crate::adapters::sorted_by($it, $cmp)
+    );
+    (@sort [unstable] $it:expr, $cmp:expr) => (
+        $crate::adapters::sorted_unstable_by($it, $cmp)
+    );
+    (@sort [parallel] $it:expr, $cmp:expr) => (
+        $crate::adapters::par_sorted_by($it, $cmp)
+    );
+    (@cmp [$(asc)?] $a:expr, $b:expr) => (
+        ::std::cmp::Ord::cmp($a, $b)
+    );
+    (@cmp [desc] $a:expr, $b:expr) => (
+        ::std::cmp::Ord::cmp($b, $a)
+    );
+    // modifiers are moved past the collection type (or terminal) so that
+    // they end up right in front of the `for` keyword
+    (@modify [$($marker:tt)*] [$($pre:tt)*] for $($rest:tt)*) => (
+        $crate::rcomp!($($pre)* $($marker)* for $($rest)*)
+    );
+    (@modify [$($marker:tt)*] [$($pre:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp!(@modify [$($marker)*] [$($pre)* $next] $($rest)*)
+    );
+    // the markers left by the modifiers can come in any order, so they're
+    // gathered into the config before parsing. two probes are combined.
+    (@config {[$($mode:tt)*] $probe:tt $mv:tt $collect:tt} @with [$($new:tt)*] $($t:tt)*) => (
+        $crate::rcomp!(@config {[$($new)*] $probe $mv $collect} $($t)*)
+    );
+    (@config {$mode:tt [] $mv:tt $collect:tt} @probe [$new:expr] $($t:tt)*) => (
+        $crate::rcomp!(@config {$mode [$new] $mv $collect} $($t)*)
+    );
+    (@config {$mode:tt [$probe:expr] $mv:tt $collect:tt} @probe [$new:expr] $($t:tt)*) => (
+        $crate::rcomp!(@config {$mode [($probe, $new)] $mv $collect} $($t)*)
+    );
+    (@config {$mode:tt $probe:tt $mv:tt $collect:tt} @move $($t:tt)*) => (
+        $crate::rcomp!(@config {$mode $probe [move] $collect} $($t)*)
+    );
+    (@config {$mode:tt $probe:tt $mv:tt $collect:tt} @collect [$($new:tt)*] $($t:tt)*) => (
+        $crate::rcomp!(@config {$mode $probe $mv [$($new)*]} $($t)*)
+    );
+    // `indexed` doesn't go in the config. it's passed on to the end of the
+    // markers and then starts the list of `for-in` clauses.
+    (@config $cfg:tt @indexed @$marker:ident [$($arg:tt)*] $($t:tt)*) => (
+        $crate::rcomp!(@config $cfg @$marker [$($arg)*] @indexed $($t)*)
+    );
+    (@config $cfg:tt @indexed @move $($t:tt)*) => (
+        $crate::rcomp!(@config $cfg @move @indexed $($t)*)
+    );
+    (@config $cfg:tt @indexed for $($t:tt)*) => (
+        $crate::rcomp!(@parse $cfg [indexed] [] $($t)*)
+    );
+    (@config $cfg:tt for $($t:tt)*) => (
+        $crate::rcomp!(@parse $cfg [] [] $($t)*)
+    );
+    (@with $($t:tt)*) => (
+        $crate::rcomp!(@config {[] [] [] []} @with $($t)*)
+    );
+    (@probe $($t:tt)*) => (
+        $crate::rcomp!(@config {[] [] [] []} @probe $($t)*)
+    );
+    (@move $($t:tt)*) => (
+        $crate::rcomp!(@config {[] [] [] []} @move $($t)*)
+    );
+    (@indexed $($t:tt)*) => (
+        $crate::rcomp!(@config {[] [] [] []} @indexed $($t)*)
+    );
+    (@collect $($t:tt)*) => (
+        $crate::rcomp!(@config {[] [] [] []} @collect $($t)*)
+    );
+    // these two rules MUST stay in this order, otherwise the `for`
+    // keyword causes ambiguity. the tt munching shouldn't go too
+    // deep since it has an end condition.
+    (for $($t:tt)*) => (
+        $crate::rcomp!(@parse {[] [] [] []} [] [] $($t)*)
+    );
+    (threads $n:expr; $($t:tt)*) => (
+        $crate::rcomp!(@modify [@with [threads $n]] [] $($t)*)
+    );
+    (loops; $($t:tt)*) => (
+        $crate::rcomp!(@modify [@with [loops]] [] $($t)*)
+    );
+    (simd; $($t:tt)*) => (
+        $crate::rcomp!(@modify [@with [simd]] [] $($t)*)
+    );
+    (pool $pool:expr; $($t:tt)*) => (
+        $crate::par::install(&$pool, || $crate::rcomp!($($t)*))
+    );
+    (par chunks $n:expr; $($t:tt)*) => (
+        $crate::rcomp!(@modify [@with [par chunks $n]] [] $($t)*)
+    );
+    (par; $($t:tt)*) => (
+        $crate::rcomp!(@modify [@with [par]] [] $($t)*)
+    );
+    (move; $($t:tt)*) => (
+        $crate::rcomp!(@modify [@move] [] $($t)*)
+    );
+    (indexed; $($t:tt)*) => (
+        $crate::rcomp!(@modify [@indexed] [] $($t)*)
+    );
+    // the probes are references so that `move` closures copy them
+    (report; $($t:tt)*) => ({
+        let __recorder = $crate::report::Recorder::default();
+        let __probe = &__recorder;
+        let __out = $crate::rcomp!(@modify [@probe [__probe]] [] $($t)*);
+        (__out, __recorder.finish())
+    });
+    (progress $message:expr; $($t:tt)*) => ({
+        let __progress = $crate::progress::Progress::new($message);
+        let __probe = &__progress;
+        let __out = $crate::rcomp!(@modify [@probe [__probe]] [] $($t)*);
+        __progress.finish();
+        __out
+    });
+    (trace $name:expr; $($t:tt)*) => ({
+        let __tracer = $crate::trace::Tracer::new($name);
+        let __probe = &__tracer;
+        let __entered = __tracer.enter();
+        let __out = $crate::rcomp!(@modify [@probe [__probe]] [] $($t)*);
+        drop(__entered);
+        __tracer.finish();
+        __out
+    });
+    // terminals have to come before the collection rule, since they'd
+    // happily parse as a `path` otherwise
+    (end $terminal:expr; $($t:tt)*) => (
+        $crate::terminals::CompTerminal::finish($terminal, $crate::rcomp!($($t)*))
+    );
+    (in $arena:expr, Vec<$ty:ty>; $($t:tt)*) => (
+        $crate::terminals::collect_in::<$ty, _>($crate::rcomp!($($t)*), $arena)
+    );
+    (into $collection:expr; $($t:tt)*) => (
+        $crate::terminals::collect_into($crate::rcomp!($($t)*), $collection)
+    );
+    (as $ty:ty; $($t:tt)*) => (
+        $crate::terminals::collect_as::<$ty, _>($crate::rcomp!($($t)*))
+    );
+    (try as $ty:ty; $($t:tt)*) => (
+        $crate::terminals::try_collect_as::<$ty, _>($crate::rcomp!($($t)*))
+    );
+    (bounded $collect:ty; $($t:tt)*) => (
+        $crate::terminals::collect_bounded::<$collect, _>($crate::rcomp!($($t)*))
+    );
+    (arrow $array:ty; $($t:tt)*) => (
+        $crate::terminals::collect_arrow::<$array, _>($crate::rcomp!($($t)*))
+    );
+    (shuffled with $rng:expr; $($t:tt)*) => (
+        $crate::terminals::shuffled_with($crate::rcomp!($($t)*), $rng)
+    );
+    (shuffled; $($t:tt)*) => (
+        $crate::terminals::shuffled($crate::rcomp!($($t)*))
+    );
+    (minmax; $($t:tt)*) => (
+        $crate::terminals::minmax($crate::rcomp!($($t)*))
+    );
+    (histogram by $bucket:expr; $($t:tt)*) => (
+        $crate::terminals::histogram_by($crate::rcomp!($($t)*), $bucket)
+    );
+    (histogram; $($t:tt)*) => (
+        $crate::terminals::histogram_by($crate::rcomp!($($t)*), |__item| ::std::clone::Clone::clone(__item))
+    );
+    (most_common; $($t:tt)*) => (
+        $crate::terminals::most_common($crate::rcomp!($($t)*))
+    );
+    (most_common $n:expr; $($t:tt)*) => ({
+        let mut __counts = $crate::terminals::most_common($crate::rcomp!($($t)*));
+        __counts.truncate($n);
+        __counts
+    });
+    (tee ($a:ty, $b:ty $(,)?); $($t:tt)*) => (
+        $crate::terminals::tee::<_, _, _, $a, $b>($crate::rcomp!($($t)*))
+    );
+    (demux { $($pat:pat $(if $guard:expr)? => $name:ident: $ty:ty = $val:expr),+ $(,)? }; $($t:tt)*) => ({
+        $(let mut $name = <$ty as ::std::default::Default>::default();)+
+        for __item in $crate::rcomp!($($t)*) {
+            match __item {
+                $($pat $(if $guard)? => ::std::iter::Extend::extend(&mut $name, ::std::iter::once($val)),)+
+            }
+        }
+        ($($name,)+)
+    });
+    (partition ($a:ty, $b:ty $(,)?); $($t:tt)*) => (
+        $crate::terminals::partition_either::<_, _, _, $a, $b>($crate::rcomp!($($t)*))
+    );
+    (fminmax; $($t:tt)*) => (
+        $crate::terminals::minmax_by(
+            ::std::iter::Iterator::filter($crate::rcomp!($($t)*), |__x| !__x.is_nan()),
+            |__a, __b| __a.total_cmp(__b),
+        )
+    );
+    (try_fold $acc:ident = $init:expr, $f:expr; $($t:tt)*) => (
+        $crate::rcomp!(try_fold $init, $f; $($t)*)
+    );
+    (try_fold $init:expr, $f:expr; $($t:tt)*) => (
+        ::std::iter::Iterator::try_fold(
+            &mut ::std::iter::IntoIterator::into_iter($crate::rcomp!($($t)*)),
+            $init,
+            $f,
+        )
+    );
+    (try_sum; $($t:tt)*) => (
+        $crate::terminals::try_sum($crate::rcomp!($($t)*))
+    );
+    (validate; $($t:tt)*) => (
+        $crate::terminals::validate($crate::rcomp!($($t)*))
+    );
+    (reduce ($map:ty) with $f:expr; $($t:tt)*) => (
+        $crate::terminals::reduce_by_key::<$map, _, _, _, _>($crate::rcomp!($($t)*), $f)
+    );
+    (reduce with $f:expr; $($t:tt)*) => (
+        $crate::rcomp!(reduce (::std::collections::HashMap<_, _>) with $f; $($t)*)
+    );
+    (group ($map:ty) by $key:expr; $($t:tt)*) => (
+        $crate::terminals::group_by::<$map, _, _, _, _>($crate::rcomp!($($t)*), $key)
+    );
+    (group by $key:expr; $($t:tt)*) => (
+        $crate::rcomp!(group (::std::collections::HashMap<_, ::std::vec::Vec<_>>) by $key; $($t)*)
+    );
+    (approx_count_distinct; $($t:tt)*) => (
+        $crate::terminals::approx_count_distinct($crate::rcomp!($($t)*))
+    );
+    (sorted unique; $($t:tt)*) => (
+        $crate::terminals::sorted_unique($crate::rcomp!($($t)*))
+    );
+    (cumsum; $($t:tt)*) => (
+        $crate::terminals::cumsum($crate::rcomp!($($t)*))
+    );
+    (($($collect:ty),+ $(,)?); $($t:tt)*) => (
+        $crate::terminals::unzip::<($($collect,)+), _, _>($crate::rcomp!($($t)*))
+    );
+    ($collect:path; $($t:tt)*) => (
+        $crate::rcomp!(@modify [@collect [$collect]] [] $($t)*)
+    );
+}
+
+/// Sketches the iterator chain behind a [`rcomp!`] as a `&'static str`, so
+/// you can see roughly what a comprehension becomes without reaching for
+/// `cargo expand`. The comprehension isn't evaluated.
+///
+/// The output is approximate. It shows the shape of the `flat_map` and
+/// `filter_map` calls, not the exact tokens [`rcomp!`] produces, which also
+/// go through helpers for probes, modes, and sources. Only the core syntax
+/// is supported: the optional collection type, `for-in` clauses over plain
+/// expressions, the mapper, and the guard. Anything else, like `let`
+/// clauses, source keywords, block mappers, or clauses after the mapper, is
+/// a compile error rather than a misleading sketch. Modifiers, clauses, and
+/// terminals each wrap this chain in a call to the function of the same
+/// name in [`threads`], [`adapters`], or [`terminals`].
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp_expand;
+/// let expansion = rcomp_expand![Vec<_>; for row in &matrix, col in row => *col * 2, if *col % 2 == 0];
+/// assert_eq!(
+///     expansion,
+///     "(&matrix).into_iter().flat_map(|row| (row).into_iter().filter_map(|col| \
+///     { if (*col % 2 == 0) && true { Some((*col * 2)) } else { None } })).collect::<Vec<_>>()"
+/// );
+/// ```
+#[macro_export]
+macro_rules! rcomp_expand {
+    (@__ [$mapper:expr] [$($guard:expr)?] $($vars:pat),+ in $iter:expr $(,)?) => (
+        concat!(
+            "(", stringify!($iter), ").into_iter().filter_map(|", stringify!($($vars),*), "| { if ",
+            $("(", stringify!($guard), ") && ",)? "true { Some((", stringify!($mapper), ")) } else { None } })"
+        )
+    );
+    (@__ [$mapper:expr] [$($guard:expr)?] $($vars:pat),+ in $iter:expr, $($recurse:tt)+) => (
+        concat!(
+            "(", stringify!($iter), ").into_iter().flat_map(|", stringify!($($vars),*), "| ",
+            $crate::rcomp_expand!(@__ [$mapper] [$($guard)?] $($recurse)+), ")"
+        )
+    );
+    // `let` and `static` would be fatal to the `pat` and `expr` fragments
+    // above, and a block mapper would parse as an expression, so they're
+    // turned away while munching
+    (@parse $gens:tt let $($rest:tt)*) => (
+        $crate::rcomp_expand!(@unsupported)
+    );
+    (@parse $gens:tt static $($rest:tt)*) => (
+        $crate::rcomp_expand!(@unsupported)
+    );
+    (@parse $gens:tt => { $($body:tt)* } $($rest:tt)*) => (
+        $crate::rcomp_expand!(@unsupported)
+    );
+    (@parse [$($gens:tt)*] => $mapper:expr $(, if $guard:expr)? $(,)?) => (
+        $crate::rcomp_expand!(@__ [$mapper] [$($guard)?] $($gens)*)
+    );
+    (@parse [$($gens:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::rcomp_expand!(@parse [$($gens)* $next] $($rest)*)
+    );
+    (for $($t:tt)*) => (
+        $crate::rcomp_expand!(@parse [] $($t)*)
+    );
+    // `move` would be fatal to the `path` fragment below
+    (move; $($t:tt)*) => (
+        $crate::rcomp_expand!(@unsupported)
+    );
+    ($collect:path; for $($t:tt)*) => (
+        concat!($crate::rcomp_expand!(for $($t)*), ".collect::<", stringify!($collect), ">()")
+    );
+    // anything the rules above didn't take, including the end of `@parse`
+    // without a mapper
+    ($($t:tt)*) => (
+        ::std::compile_error!(
+            "`rcomp_expand!` only sketches the core syntax: an optional collection type, `for-in` clauses over plain expressions, a mapper, and an optional guard"
+        )
+    );
+}
+
+/// A [`rcomp!`] that always collects into a `Vec`, built from plain nested
+/// `for` loops that push straight into it instead of an iterator chain.
+/// With a single `for-in` clause and no guard, the length is known up
+/// front, so the `Vec` is allocated once with the source's `size_hint`.
+///
+/// Only the core syntax is supported: the `for-in` clauses, the mapper, and
+/// the guard. The sources are plain expressions, without any of the
+/// keywords from the [Sources](rcomp#sources) section. Since nothing is a
+/// closure, the mapper and guard can borrow from the outer clauses and use
+/// `?` or `return` like any other loop body.
 ///
-/// With that explained, here's the full syntax:
+/// # Example
 ///
-/// ```text
-/// rcomp!([collect_ty;] for <pattern> in <iterator>, ... => <mapper>[, if <guard>]);
+/// ```rust
+/// # use rustcomp::vcomp;
+/// let matrix = vec![vec![1, 2, 3], vec![4, 5, 6]];
+/// let v = vcomp![for row in &matrix, x in row => x * 10, if x % 2 == 1];
+/// assert_eq!(v, vec![10, 30, 50]);
+/// let lens = vcomp![for row in &matrix => row.len()];
+/// assert_eq!(lens, vec![3, 3]);
+/// assert_eq!(lens.capacity(), 2);
 /// ```
+#[macro_export]
+macro_rules! vcomp {
+    // the clauses are turned into the ones `rcomp!` parses, so the loops
+    // themselves come from its `loops` modifier
+    (@loop $v:ident [$mapper:expr] [$($guard:expr)?] [$($gens:tt)*] $pat:pat in $iter:expr $(,)?) => (
+        $crate::rcomp!(@loops $v [$mapper] [$($guard)?] $($gens)* ([$pat] [$iter]))
+    );
+    (@loop $v:ident [$mapper:expr] [$($guard:expr)?] [$($gens:tt)*] $pat:pat in $iter:expr, $($rest:tt)+) => (
+        $crate::vcomp!(@loop $v [$mapper] [$($guard)?] [$($gens)* ([$pat] [$iter])] $($rest)+)
+    );
+    (@parse [$($gens:tt)*] => $($rest:tt)*) => (
+        $crate::vcomp!(@build [$($gens)*] $($rest)*)
+    );
+    (@parse [$($gens:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::vcomp!(@parse [$($gens)* $next] $($rest)*)
+    );
+    (@build [$pat:pat in $iter:expr] $mapper:expr $(,)?) => ({
+        let __iter = ::std::iter::IntoIterator::into_iter($iter);
+        let mut __v = ::std::vec::Vec::with_capacity(__iter.size_hint().0);
+        for $pat in __iter {
+            __v.push($mapper);
+        }
+        __v
+    });
+    (@build [$($gens:tt)*] $mapper:expr $(, if $guard:expr)? $(,)?) => ({
+        let mut __v = ::std::vec::Vec::new();
+        $crate::vcomp!(@loop __v [$mapper] [$($guard)?] [] $($gens)*);
+        __v
+    });
+    (for $($t:tt)*) => (
+        $crate::vcomp!(@parse [] $($t)*)
+    );
+}
+
+/// Lazily evaluates a comprehension the first time it's used, for building
+/// global lookup tables. The comprehension is wrapped in a
+/// [`LazyLock`](std::sync::LazyLock), so it's only run once.
 ///
-/// # Examples
+/// There are two ways to use it. The first declares the whole `static`, with
+/// the type of the static doubling as the collection type:
 ///
-/// Comprehensions can be as simple or complex as you want. They can collect
-/// the input, filter it, map it, and flatten it all in one go. For example,
-/// here's how you can create a `HashMap` of numbers and their squares using
-/// a comprehension:
+/// ```rust
+/// # use rustcomp::static_rcomp;
+/// static_rcomp!(pub static SQUARES: Vec<u64> = for i in 0..1024 => i * i);
+///
+/// assert_eq!(SQUARES[12], 144);
+/// assert_eq!(SQUARES.len(), 1024);
+/// ```
+///
+/// The second is just an expression, so it takes any comprehension that
+/// [`rcomp!`] would. The `LazyLock` has to be written out in the type:
 ///
 /// ```rust
-/// # use rustcomp::rcomp;
-/// # use std::collections::HashMap;
-/// let m = rcomp![HashMap<_, _>; for i in 0..10 => (i, i * i)];
-/// let it = (0..10).map(|i| (i, i * i)).collect::<HashMap<_, _>>();
-/// assert_eq!(m, it);
+/// # use rustcomp::static_rcomp;
+/// use std::collections::HashMap;
+/// use std::sync::LazyLock;
+///
+/// static NAMES: LazyLock<HashMap<u8, char>> =
+///     static_rcomp![HashMap<_, _>; for c in 'a'..='z' => (c as u8 - b'a', c)];
+///
+/// assert_eq!(NAMES[&2], 'c');
 /// ```
 ///
-/// Another example is removing duplicates from a `Vec` by converting it to
-/// a `HashSet` and back:
+/// Either way, the comprehension can't capture anything from its
+/// surroundings, since it's turned into a plain function pointer.
+#[macro_export]
+macro_rules! static_rcomp {
+    ($(#[$attr:meta])* $vis:vis static $name:ident : $ty:ty = for $($t:tt)*) => (
+        $(#[$attr])*
+        $vis static $name: ::std::sync::LazyLock<$ty> =
+            ::std::sync::LazyLock::new(|| $crate::rcomp!($ty; for $($t)*));
+    );
+    ($($t:tt)*) => (
+        ::std::sync::LazyLock::new(|| $crate::rcomp!($($t)*))
+    );
+}
+
+/// Defines reusable comprehension fragments. Each one is a comprehension
+/// with `_` in place of its first source, and becomes a macro that fills in
+/// the source and expands to the whole comprehension with [`rcomp!`]. That
+/// way a chain of guards, mappers, and clauses can be written once and
+/// shared by every comprehension that needs it.
+///
+/// The macro takes what [`rcomp!`] takes in front of the `for-in` clauses,
+/// like a collection type or modifiers, followed by `in <source>`:
 ///
 /// ```rust
-/// # use rustcomp::rcomp;
-/// # use std::collections::HashSet;
-/// let v = vec![1, 2, 3, 4, 5, 1, 2, 3, 4, 5];
-/// let s = rcomp![Vec<_>; for i in rcomp![HashSet<_>; for j in &v => *j] => i];
-/// assert!(v.into_iter().all(|i| s.contains(&i)));
+/// # use rustcomp::pipeline;
+/// struct User {
+///     name: &'static str,
+///     active: bool,
+/// }
+///
+/// pipeline! {
+///     /// The names of active users, without duplicates.
+///     active_names = for u in _ => u.name, if u.active, unique by |name| *name;
+///     long_words = for line in _, w in line.split_whitespace() => w, if w.len() > 3;
+/// }
+///
+/// let users = [
+///     User { name: "ann", active: true },
+///     User { name: "bo", active: false },
+///     User { name: "ann", active: true },
+/// ];
+/// assert_eq!(active_names!(Vec<_>; in &users), ["ann"]);
+/// assert_eq!(active_names!(in &users[1..]).count(), 1);
+/// let words = long_words!(Vec<_>; in ["some short words", "and longer ones"]);
+/// assert_eq!(words, ["some", "short", "words", "longer", "ones"]);
 /// ```
 ///
-/// See the [crate-level documentation](crate) for more examples.
+/// The fragments are ordinary `macro_rules!` macros, so they're only in
+/// scope after they're defined. Attributes like `#[macro_export]` are passed
+/// on to them. Since they're macros, the fragments can't use local
+/// variables from where they're used, only constants, statics, and the
+/// like.
 #[macro_export]
-macro_rules! rcomp {
-    (@__ $($vars:pat),+ in $iter:expr => $mapper:expr $(, if $guard:expr)? $(,)?) => (
-        $iter
-            .into_iter()
-            .filter_map(|$($vars),*| {
-                // `&& true` is a trick to make the guard optional
-                if $($guard &&)? true {
-                    Some($mapper)
-                } else {
-                    None
-                }
-            })
+macro_rules! pipeline {
+    () => ();
+    ($(#[$attr:meta])* $name:ident = for $($t:tt)*) => (
+        $crate::pipeline!(@pat ($) [$(#[$attr])*] $name [] $($t)*);
     );
-    (@__ $($vars:pat),+ in $iter:expr, $($recurse:tt)+) => (
-        $iter
-            .into_iter()
-            .flat_map(|$($vars),*| $crate::rcomp!(@__ $($recurse)+))
+    // the `$` is passed along as a token so the macro being defined can
+    // have metavariables of its own
+    (@pat ($d:tt) $attrs:tt $name:ident [$($pat:tt)*] in _ $($t:tt)*) => (
+        $crate::pipeline!(@rest $d $attrs $name [$($pat)*] [] $($t)*);
     );
-    // these two rules MUST stay in this order, otherwise the `for`
-    // keyword causes ambiguity. the tt munching shouldn't go too
-    // deep since it has an end condition.
-    (for $($t:tt)*) => (
-        $crate::rcomp!(@__ $($t)*)
+    (@pat ($d:tt) $attrs:tt $name:ident [$($pat:tt)*] $next:tt $($t:tt)*) => (
+        $crate::pipeline!(@pat ($d) $attrs $name [$($pat)* $next] $($t)*);
+    );
+    (@rest $d:tt [$($attrs:tt)*] $name:ident [$($pat:tt)*] [$($rest:tt)*] $(; $($more:tt)*)?) => (
+        $($attrs)*
+        macro_rules! $name {
+            (@pre [$d($d pre:tt)*] in $d src:expr) => (
+                $crate::rcomp!($d($d pre)* for $($pat)* in $d src $($rest)*)
+            );
+            (@pre [$d($d pre:tt)*] $d next:tt $d($d t:tt)*) => (
+                $name!(@pre [$d($d pre)* $d next] $d($d t)*)
+            );
+            ($d($d t:tt)*) => (
+                $name!(@pre [] $d($d t)*)
+            );
+        }
+        $($crate::pipeline!($($more)*);)?
+    );
+    (@rest $d:tt $attrs:tt $name:ident $pat:tt [$($rest:tt)*] $next:tt $($t:tt)*) => (
+        $crate::pipeline!(@rest $d $attrs $name $pat [$($rest)* $next] $($t)*);
+    );
+}
+
+/// A SQL-flavored front end for [`rcomp!`], for those who'd rather read
+/// `select` than `=>`. Queries lower to the same iterator chain as the
+/// equivalent comprehension.
+///
+/// # Syntax
+///
+/// ```text
+/// query!([collect_ty;] select <expr> from <pattern> in <iterator>, ... [where <cond>] [order by <key> [asc|desc]] [limit <n>]);
+/// ```
+///
+/// - `from` takes the same `for-in` clauses as [`rcomp!`], separated by
+///   commas.
+/// - `where` is the guard.
+/// - `order by` sorts by `key`, which can use the `from` bindings just
+///   like the selected expression. Sorting is stable and ascending by
+///   default. Since the whole query has to be collected to sort it, this
+///   uses [`order_by`](adapters::order_by) under the hood.
+/// - `limit` takes at most `n` items, after sorting.
+///
+/// Without a collection type, the query evaluates to an iterator.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::query;
+/// struct Employee {
+///     name: &'static str,
+///     dept: &'static str,
+///     salary: u32,
+/// }
+///
+/// let staff = vec![
+///     Employee { name: "alice", dept: "eng", salary: 120 },
+///     Employee { name: "bob", dept: "ops", salary: 90 },
+///     Employee { name: "carol", dept: "eng", salary: 150 },
+///     Employee { name: "dave", dept: "eng", salary: 100 },
+/// ];
+/// let top = query![Vec<_>;
+///     select e.name from e in &staff where e.dept == "eng" order by e.salary desc limit 2
+/// ];
+/// assert_eq!(top, vec!["carol", "alice"]);
+/// ```
+#[macro_export]
+macro_rules! query {
+    // each part of the query is munched up to the keyword that starts the
+    // next one. the optional parts are stored as zero or one expressions.
+    (@select [$($sel:tt)*] from $($rest:tt)*) => (
+        $crate::query!(@from [($($sel)*)] [] $($rest)*)
+    );
+    (@select [$($sel:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::query!(@select [$($sel)* $next] $($rest)*)
+    );
+    (@from $sel:tt $from:tt where $($rest:tt)*) => (
+        $crate::query!(@where $sel $from [] $($rest)*)
+    );
+    (@from $sel:tt $from:tt order by $($rest:tt)*) => (
+        $crate::query!(@order $sel $from [] [] $($rest)*)
+    );
+    (@from $sel:tt $from:tt $(limit $n:expr)?) => (
+        $crate::query!(@build $sel $from [] [] [] [$($n)?])
+    );
+    (@from $sel:tt [$($from:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::query!(@from $sel [$($from)* $next] $($rest)*)
+    );
+    (@where $sel:tt $from:tt [$($cond:tt)+] order by $($rest:tt)*) => (
+        $crate::query!(@order $sel $from [($($cond)+)] [] $($rest)*)
+    );
+    (@where $sel:tt $from:tt [$($cond:tt)+] $(limit $n:expr)?) => (
+        $crate::query!(@build $sel $from [($($cond)+)] [] [] [$($n)?])
+    );
+    (@where $sel:tt $from:tt [$($cond:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::query!(@where $sel $from [$($cond)* $next] $($rest)*)
+    );
+    (@order $sel:tt $from:tt $cond:tt [$($key:tt)+] asc $(limit $n:expr)?) => (
+        $crate::query!(@build $sel $from $cond [($($key)+)] [false] [$($n)?])
+    );
+    (@order $sel:tt $from:tt $cond:tt [$($key:tt)+] desc $(limit $n:expr)?) => (
+        $crate::query!(@build $sel $from $cond [($($key)+)] [true] [$($n)?])
+    );
+    (@order $sel:tt $from:tt $cond:tt [$($key:tt)+] $(limit $n:expr)?) => (
+        $crate::query!(@build $sel $from $cond [($($key)+)] [false] [$($n)?])
+    );
+    (@order $sel:tt $from:tt $cond:tt [$($key:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::query!(@order $sel $from $cond [$($key)* $next] $($rest)*)
+    );
+    (@build [$sel:expr] [$($from:tt)+] [$($cond:expr)?] [] [] [$($n:expr)?]) => (
+        $crate::rcomp!(for $($from)+ => $sel $(, if $cond)?)
+        $(.take($n))?
+    );
+    (@build [$sel:expr] [$($from:tt)+] [$($cond:expr)?] [$key:expr] [$desc:tt] [$($n:expr)?]) => (
+        $crate::adapters::order_by($crate::rcomp!(for $($from)+ => ($key, $sel) $(, if $cond)?), $desc)
+        $(.take($n))?
+    );
+    (select $($t:tt)*) => (
+        $crate::query!(@select [] $($t)*)
     );
     ($collect:path; $($t:tt)*) => (
-        $crate::rcomp!($($t)*)
-        .collect::<$collect>()
+        $crate::terminals::collect::<$collect, _>($crate::query!($($t)*))
+    );
+}
+
+/// Swaps the keys and values of a map, or of anything else that iterates
+/// over `(key, value)` pairs. Since several keys can share a value, the
+/// inverted map needs a policy for collisions:
+///
+/// - `last wins` (the default) keeps the last key seen for each value.
+/// - `keep first` keeps the first key seen for each value.
+/// - `group` keeps every key, collecting the keys that share a value in
+///   the order they were seen.
+///
+/// Keep in mind that "first" and "last" follow the iteration order of the
+/// source, which is arbitrary for a `HashMap`. Use `group`, or a source
+/// with a meaningful order, if that matters.
+///
+/// # Syntax
+///
+/// ```text
+/// invert!([into <map_ty>;] <map>[, last wins | keep first | group]);
+/// ```
+///
+/// The inverted map is a `HashMap<_, _>` (`HashMap<_, Vec<_>>` for
+/// `group`) unless another one is given with `into`. Any map with the
+/// usual `entry` API works, and with `group` the value type picks the
+/// collection the keys are grouped into.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::invert;
+/// use std::collections::{BTreeMap, BTreeSet};
+///
+/// let grades = [("alice", 'A'), ("bob", 'B'), ("carol", 'A')];
+/// assert_eq!(invert!(grades)[&'A'], "carol");
+/// assert_eq!(invert!(grades, keep first)[&'A'], "alice");
+/// let by_grade = invert!(into BTreeMap<_, BTreeSet<_>>; grades, group);
+/// assert_eq!(by_grade[&'A'], BTreeSet::from(["alice", "carol"]));
+/// assert_eq!(by_grade.keys().collect::<String>(), "AB");
+/// ```
+#[macro_export]
+macro_rules! invert {
+    (@swap $map:expr) => (
+        $crate::rcomp!(for (key, value) in $map => (value, key))
+    );
+    (into $ty:ty; $map:expr $(, last wins)?) => (
+        $crate::rcomp!($ty; for (key, value) in $map => (value, key))
+    );
+    (into $ty:ty; $map:expr, keep first) => ({
+        let mut inverted: $ty = ::std::default::Default::default();
+        for (value, key) in $crate::invert!(@swap $map) {
+            inverted.entry(value).or_insert(key);
+        }
+        inverted
+    });
+    (into $ty:ty; $map:expr, group) => ({
+        // see `terminals::group_by` for why the `FromIterator` bound is here
+        fn add<C: ::std::iter::Extend<T> + ::std::iter::FromIterator<T>, T>(group: &mut C, key: T) {
+            group.extend(::std::iter::once(key));
+        }
+        let mut inverted: $ty = ::std::default::Default::default();
+        for (value, key) in $crate::invert!(@swap $map) {
+            add(inverted.entry(value).or_default(), key);
+        }
+        inverted
+    });
+    ($map:expr, group) => (
+        $crate::invert!(into ::std::collections::HashMap<_, ::std::vec::Vec<_>>; $map, group)
+    );
+    ($map:expr $(, $($policy:tt)+)?) => (
+        $crate::invert!(into ::std::collections::HashMap<_, _>; $map $(, $($policy)+)?)
+    );
+}
+
+/// Yields the distinct items that are in both operands, in the order they
+/// appear in the first one. See the [`sets`] module for how the operands
+/// are used.
+///
+/// # Syntax
+///
+/// ```text
+/// intersect!(<operand>, <lookup>);
+/// ```
+///
+/// The first operand is streamed, and can be any iterable expression or a
+/// comprehension in brackets, like `[for x in v => x.id]`. The second one
+/// is only used to look items up: if it's a comprehension, it's collected
+/// into a `HashSet` first, and otherwise it has to be a
+/// [`Lookup`](sets::Lookup), like a `HashSet` or a reference to one, which
+/// is used directly. Putting the side that's already a set second saves
+/// copying it.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::intersect;
+/// use std::collections::HashSet;
+///
+/// let admins = HashSet::from(["root", "alice"]);
+/// let logins = ["bob", "alice", "root", "alice"];
+/// let v = intersect!([for name in logins => name], &admins).collect::<Vec<_>>();
+/// assert_eq!(v, ["alice", "root"]);
+/// ```
+#[macro_export]
+macro_rules! intersect {
+    ($($t:tt)+) => (
+        $crate::__set_op!(@split intersect [] $($t)+)
+    );
+}
+
+/// Yields the distinct items that are in either operand: those in the
+/// first one, followed by those only in the second one. Both operands are
+/// streamed, and each one can be any iterable expression or a comprehension
+/// in brackets.
+///
+/// # Syntax
+///
+/// ```text
+/// union!(<operand>, <operand>);
+/// ```
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::union;
+/// let v = union!([for x in 0..5 => x * 2], [for x in 0..5 => x * 3]).collect::<Vec<_>>();
+/// assert_eq!(v, [0, 2, 4, 6, 8, 3, 9, 12]);
+/// ```
+#[macro_export]
+macro_rules! union {
+    ($($t:tt)+) => (
+        $crate::__set_op!(@split union [] $($t)+)
+    );
+}
+
+/// Yields the distinct items of the first operand that aren't in the
+/// second one. The operands work the same way as they do for
+/// [`intersect!`].
+///
+/// # Syntax
+///
+/// ```text
+/// difference!(<operand>, <lookup>);
+/// ```
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::difference;
+/// let seen = vec![2, 3];
+/// let v = difference!(1..6, [for x in &seen => *x]).collect::<Vec<_>>();
+/// assert_eq!(v, [1, 4, 5]);
+/// ```
+#[macro_export]
+macro_rules! difference {
+    ($($t:tt)+) => (
+        $crate::__set_op!(@split difference [] $($t)+)
+    );
+}
+
+/// The shared implementation of [`intersect!`], [`union!`], and
+/// [`difference!`]. Not part of the public API.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __set_op {
+    // the operands are split at the first top-level comma, since a bracketed
+    // comprehension can't be parsed as an expression
+    (@split $op:ident [$($a:tt)+] , $($b:tt)+) => (
+        $crate::__set_op!(@op $op [$($a)+] [$($b)+])
+    );
+    (@split $op:ident [$($a:tt)*] $next:tt $($rest:tt)*) => (
+        $crate::__set_op!(@split $op [$($a)* $next] $($rest)*)
+    );
+    (@op union [$($a:tt)+] [$($b:tt)+]) => (
+        $crate::sets::union($crate::__set_op!(@stream $($a)+), $crate::__set_op!(@stream $($b)+))
+    );
+    (@op $op:ident [$($a:tt)+] [$($b:tt)+]) => (
+        $crate::sets::$op($crate::__set_op!(@stream $($a)+), $crate::__set_op!(@lookup $($b)+))
+    );
+    (@stream [for $($t:tt)+]) => (
+        $crate::rcomp!(for $($t)+)
+    );
+    (@stream $e:expr) => (
+        $e
+    );
+    (@lookup [for $($t:tt)+]) => (
+        $crate::rcomp!(::std::collections::HashSet<_>; for $($t)+)
+    );
+    (@lookup $e:expr) => (
+        $e
     );
 }
 
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn test_vcomp_matches_rcomp() {
+        let v = vec![vec![1, 2, 3], vec![], vec![4, 5, 6]];
+        let expected = rcomp![Vec<_>; for row in &v, x in row => x * x, if x % 2 == 0];
+        assert_eq!(
+            vcomp![for row in &v, x in row => x * x, if x % 2 == 0],
+            expected
+        );
+        let expected = rcomp![Vec<_>; for (i, row) in v.iter().enumerate() => i + row.len()];
+        let actual = vcomp![for (i, row) in v.iter().enumerate() => i + row.len()];
+        assert_eq!((actual.len(), actual.capacity()), (3, 3));
+        assert_eq!(actual, expected);
+        // nothing is a closure, so inner levels can borrow outer bindings
+        let pairs = vcomp![for a in 0..3, b in a..3 => (a, b), if a != b];
+        assert_eq!(pairs, [(0, 1), (0, 2), (1, 2)]);
+    }
+
+    #[test]
+    fn test_index_bindings() {
+        let v = rcomp![Vec<_>; for c in "abc".chars() @ i => (i, c), if i != 1];
+        assert_eq!(v, [(0, 'a'), (2, 'c')]);
+        let v = rcomp![move; Vec<_>;
+            for (a, b) in [1, 2] zip longest [3] @ i, x in static [10, 20] @ j => (i, j, a.unwrap_or(0) + b.unwrap_or(0) + x)
+        ];
+        assert_eq!(v, [(0, 0, 14), (0, 1, 24), (1, 0, 12), (1, 1, 22)]);
+        let v = rcomp![indexed; Vec<_>; for x in ['p', 'q'] @ n => (n, x)];
+        assert_eq!(v, [(0, (0, 'p')), (1, (1, 'q'))]);
+    }
+
+    #[test]
+    fn test_indexed() {
+        let v = rcomp![indexed; Vec<_>; for c in "abc".chars() => c, if c != 'b'];
+        assert_eq!(v, [(0, 'a'), (2, 'c')]);
+        let cube = [[[1, 2], [3, 4]], [[5, 6], [7, 8]]];
+        let (v, report) = rcomp![indexed; report; threads 2; Vec<_>;
+            for plane in &cube, row in plane, x in static [10, 20] => memo row[0] * x,
+            if row[1] % 4 == 0
+        ];
+        assert_eq!(
+            v,
+            [
+                ((0, 1, 0), 30),
+                ((0, 1, 1), 60),
+                ((1, 1, 0), 70),
+                ((1, 1, 1), 140)
+            ]
+        );
+        assert_eq!(report.rejected, 4);
+    }
+
+    #[test]
+    fn test_loops_matches_closures() {
+        let v = vec![vec![1, 2, 2], vec![], vec![3, 1, 4]];
+        let expected = rcomp![move; Vec<_>; for row in &v, x in row, y in 0..*x => (*x, y), if x != &4, unique by |p| p.0 + p.1];
+        let actual = rcomp![loops; Vec<_>; for row in &v, x in row, y in 0..*x => (*x, y), if x != &4, unique by |p| p.0 + p.1];
+        assert_eq!(expected, actual);
+        let sums = rcomp![loops; Vec<_>; for (a, b) in [1, 2] zip longest [10] => a.unwrap_or(0) + b.unwrap_or(0)];
+        assert_eq!(sums, [11, 2]);
+    }
+
+    #[test]
+    fn test_static_sources_are_evaluated_once() {
+        use std::cell::Cell;
+
+        let calls = Cell::new(0);
+        let count = |v: Vec<u32>| {
+            calls.set(calls.get() + 1);
+            v
+        };
+        let (v, report) = rcomp![move; report; threads 2; Vec<_>;
+            for a in 0..3, b in static count(vec![10, 20]), c in static count(vec![100]).into_iter() =>
+            a + b + c, if a != 1
+        ];
+        assert_eq!(v, [110, 120, 112, 122]);
+        assert_eq!(calls.get(), 2);
+        assert_eq!(report.produced, [3, 6, 6]);
+    }
+
+    #[test]
+    fn test_inner_while_only_ends_its_level() {
+        let rows = [vec![3, 1, 4], vec![1, 5, 9], vec![2, 6]];
+        let (v, report) = rcomp![move; report; Vec<_>;
+            for (i, row) in rows.iter().enumerate() while |(i, _)| *i < 2,
+            x in row while |x| **x != 1 => (i, *x)
+        ];
+        assert_eq!(v, [(0, 3)]);
+        assert_eq!(report.produced, [2, 1]);
+        let v = rcomp![move; Vec<_>;
+            for (i, row) in rows.iter().enumerate() while i < 2,
+            mut x in row while *x != 1 => { x = x.min(&2); (i, *x) }
+        ];
+        assert_eq!(v, [(0, 2)]);
+    }
+
+    #[test]
+    fn test_block_mapper_emit_and_skip() {
+        let v = vec![vec![1, 2, 3], vec![4, 5]];
+        let actual = rcomp![Vec<_>; for row in &v, x in row => {
+            if x % 2 == 0 {
+                skip!();
+            }
+            emit!(*x);
+            x * 10
+        }, if *x != 5];
+        assert_eq!(actual, [1, 10, 3, 30]);
+
+        let words = ["a b", "", "c"];
+        let actual = rcomp![Vec<_>; for w in words => {
+            for part in w.split(' ').filter(|p| !p.is_empty()) {
+                emit!(part);
+            }
+            skip!()
+        }];
+        assert_eq!(actual, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_identity_mapper() {
+        let v = vec![vec![1, -2], vec![3]];
+        let flat = rcomp![Vec<_>; for row in &v, x in row];
+        assert_eq!(flat, [&1, &-2, &3]);
+        let pairs = rcomp![Vec<_>; for (i, x) in flat.into_iter().enumerate(), if i != 1];
+        assert_eq!(pairs, [(0, &1), (2, &3)]);
+    }
+
+    #[test]
+    fn test_then() {
+        use std::collections::BTreeSet;
+
+        let v = vec![vec![3, 1], vec![3, 2]];
+        let largest =
+            rcomp![BTreeSet<_>; for row in &v, x in row => *x, then |s| s.last().copied()];
+        assert_eq!(largest, Some(3));
+        let (firsts, report) =
+            rcomp![report; Vec<_>; for row in &v => row[0], sorted, then |v| v.len()];
+        assert_eq!(firsts, 2);
+        assert_eq!(report.yielded, 2);
+        let steps =
+            rcomp![Vec<_>; for x in from 1 then |x| x * 2 while |x| *x < 10 => x, then |v| v.len()];
+        assert_eq!(steps, 4);
+    }
+
+    #[test]
+    fn test_let_clauses() {
+        let lines = ["a=1", "b", "c=x", "d=4"];
+        let v = rcomp![Vec<_>; for line in lines,
+            let Some((key, value)) = line.split_once('=') else skip,
+            let Ok(n) = value.parse::<i32>() else skip
+            => (key, n)
+        ];
+        assert_eq!(v, [("a", 1), ("d", 4)]);
+
+        // a `let` between levels, with the levels after it using its binding
+        let rows = vec![vec![1, 2], vec![], vec![3]];
+        let expected =
+            rcomp![move; Vec<_>; for row in &rows, x in row, y in 0..row.len() => (*x, y)];
+        let actual = rcomp![move; Vec<_>; for row in &rows, let n = row.len(), x in row, y in 0..n => (*x, y)];
+        assert_eq!(actual, expected);
+        let looped = rcomp![loops; Vec<_>; for row in &rows, let n = row.len(), x in row, y in 0..n => (*x, y)];
+        assert_eq!(looped, expected);
+        let looped = rcomp![loops; Vec<_>; for row in &rows, let Some(first) = row.first() else skip => *first];
+        assert_eq!(looped, [1, 3]);
+
+        let (firsts, report) = rcomp![report; Vec<_>; for row in &rows, let Some(first) = row.first() else skip => *first, if *first > 1];
+        assert_eq!(firsts, [3]);
+        assert_eq!(
+            (report.produced, report.rejected, report.yielded),
+            (vec![3], 1, 1)
+        );
+
+        let v = rcomp![indexed; Vec<_>; for row in &rows, let Some(last) = row.last() else skip => *last];
+        assert_eq!(v, [(0, 2), (2, 3)]);
+        let v = rcomp![move; Vec<_>; for row in &rows, let Some(first) = row.first() else skip => {
+            emit!(*first);
+            first * 10
+        }];
+        assert_eq!(v, [1, 10, 3, 30]);
+        let v =
+            rcomp![move; Vec<_>; for row in &rows, let k = row.len(), x in static [1, 2] => k * x];
+        assert_eq!(v, [2, 4, 0, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_pipeline() {
+        use std::collections::HashSet;
+
+        pipeline! {
+            evens = for x in _ => x, if x % 2 == 0;
+            cells = for row in _, x in row => *x, if *x > 0, sorted;
+        }
+
+        assert_eq!(evens!(Vec<_>; in 0..7), [0, 2, 4, 6]);
+        assert_eq!(evens!(HashSet<_>; in [2, 2, 3]).len(), 1);
+        let (v, report) = evens!(report; Vec<_>; in [1, 2, 3]);
+        assert_eq!(v, [2]);
+        assert_eq!(report.rejected, 2);
+        assert_eq!(evens!(in 0..4).sum::<i32>(), 2);
+        let rows = vec![vec![3, -1], vec![1]];
+        assert_eq!(cells!(Vec<_>; in &rows), [1, 3]);
+        assert_eq!(cells!(move; Vec<_>; in &rows), [1, 3]);
+    }
+
+    #[test]
+    fn test_guard_else_records_rejects() {
+        use std::cell::RefCell;
+
+        let v = vec![vec![1, -2, 3], vec![-4], vec![]];
+        let rejected = RefCell::new(Vec::new());
+        let (kept, report) = rcomp![report; Vec<_>;
+            for row in &v, x in row => *x,
+            if *x > 0, else rejected.borrow_mut().push(*x),
+            unique by |x| x % 2
+        ];
+        assert_eq!(kept, [1]);
+        assert_eq!(rejected.into_inner(), [-2, -4]);
+        assert_eq!(report.rejected, 2);
+    }
+
+    #[test]
+    fn test_invert_policies() {
+        use std::collections::{BTreeMap, HashMap};
+
+        let pairs = vec![(1, 'a'), (2, 'b'), (3, 'a'), (4, 'c'), (5, 'a')];
+        let last: BTreeMap<_, _> = invert!(into BTreeMap<_, _>; pairs.clone(), last wins);
+        assert_eq!(last, BTreeMap::from([('a', 5), ('b', 2), ('c', 4)]));
+        let first = invert!(pairs.iter().copied(), keep first);
+        assert_eq!(first, HashMap::from([('a', 1), ('b', 2), ('c', 4)]));
+        let grouped = invert!(&pairs, group);
+        assert_eq!(grouped[&'a'], [&1, &3, &5]);
+        assert_eq!(grouped.len(), 3);
+        let empty = invert!(HashMap::<u8, u8>::new());
+        assert!(empty.is_empty());
+    }
+
     #[test]
     fn test_vec_comp() {
         let v: Vec<u32> = vec![1, 2, 3, 4, 5];
@@ -312,4 +2743,123 @@ mod tests {
         let actual = rcomp![for (_, y) in v => y].collect::<Vec<_>>();
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_nested_split_comp() {
+        let text = "a,b\n\nc, d ,e\n";
+        let actual = rcomp![Vec<_>; for line in text.lines(), field in line.split(',') => field.trim(), if !field.is_empty()];
+        assert_eq!(actual, vec!["a", "b", "c", "d", "e"]);
+        let actual = rcomp![move; Vec<_>; for (i, line) in text.lines().enumerate(), field in line.split(',') => (i, field.trim())];
+        assert_eq!(
+            actual,
+            vec![(0, "a"), (0, "b"), (1, ""), (2, "c"), (2, "d"), (2, "e")]
+        );
+    }
+
+    #[test]
+    fn test_move_comp_borrows_source() {
+        fn cells(text: &str, sep: char) -> impl Iterator<Item = (&str, &str)> {
+            rcomp![move; for line in text.lines(), cell in line.split(sep) => (line, cell)]
+        }
+
+        let text = String::from("x;y\nz");
+        let actual = cells(&text, ';').collect::<Vec<_>>();
+        assert_eq!(actual, vec![("x;y", "x"), ("x;y", "y"), ("z", "z")]);
+        let (v, report) = rcomp![move; report; threads 2; Vec<_>; for line in text.lines(), cell in line.split(';') => (line.len(), cell)];
+        assert_eq!(v, vec![(3, "x"), (3, "y"), (1, "z")]);
+        assert_eq!(report.produced, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_static_comp() {
+        use std::collections::BTreeSet;
+        use std::sync::LazyLock;
+
+        static_rcomp!(
+            /// Every odd cube below 1000.
+            static ODD_CUBES: BTreeSet<u32> = for i in 0..10 => i * i * i, if i % 2 == 1
+        );
+        static EVENS: LazyLock<Vec<u32>> =
+            static_rcomp![Vec<_>; for i in 0..10 => i, if i % 2 == 0];
+
+        assert_eq!(
+            ODD_CUBES.iter().copied().collect::<Vec<_>>(),
+            vec![1, 27, 125, 343, 729]
+        );
+        assert_eq!(*EVENS, vec![0, 2, 4, 6, 8]);
+    }
+
+    #[test]
+    fn test_query_matches_comp() {
+        let v = vec![vec![5, 1, 4], vec![2, 8], vec![7, 3]];
+        let expected = rcomp![Vec<_>; for row in &v, x in row => x * 10, if x % 2 == 1];
+        let actual = query![Vec<_>; select x * 10 from row in &v, x in row where x % 2 == 1];
+        assert_eq!(expected, actual);
+        let actual = query![select *x from row in &v, x in row limit 4].collect::<Vec<_>>();
+        assert_eq!(actual, vec![5, 1, 4, 2]);
+    }
+
+    #[test]
+    fn test_query_order_by() {
+        let words = ["pear", "fig", "banana", "kiwi", "apple"];
+        let actual = query![Vec<_>; select *w from w in &words order by w.len()];
+        assert_eq!(actual, vec!["fig", "pear", "kiwi", "apple", "banana"]);
+        let actual =
+            query![Vec<_>; select w.len() from w in words where w != "fig" order by w desc];
+        assert_eq!(actual, vec![4, 4, 6, 5]);
+        let actual = query![Vec<_>; select w from w in words order by (w.len(), w) asc limit 2];
+        assert_eq!(actual, vec!["fig", "kiwi"]);
+    }
+
+    #[test]
+    fn test_expand_sketches_core_syntax() {
+        let expansion = rcomp_expand![for x in 0..10 => x * 2];
+        assert_eq!(
+            expansion,
+            "(0..10).into_iter().filter_map(|x| { if true { Some((x * 2)) } else { None } })"
+        );
+        let expansion = rcomp_expand![HashSet<_>; for (a, b) in pairs => a + b, if a > b,];
+        assert_eq!(
+            expansion,
+            "(pairs).into_iter().filter_map(|(a, b)| { if (a > b) && true { Some((a + b)) } else { None } }).collect::<HashSet<_>>()"
+        );
+    }
+
+    #[test]
+    fn test_sources_dont_shadow_user_functions() {
+        fn zip_with(a: &[u8], b: &[u8], n: usize) -> Vec<u8> {
+            [a, b].concat().repeat(n)
+        }
+        fn repeat(s: &str, n: usize) -> Vec<String> {
+            vec![s.to_owned(); n]
+        }
+        fn transpose(n: u8) -> [u8; 2] {
+            [n, n + 1]
+        }
+        fn grid(rows: u8, cols: u8) -> [u8; 1] {
+            [rows * cols]
+        }
+        fn upper_triangle(s: &str) -> std::str::Chars<'_> {
+            s.chars()
+        }
+        fn pairs_of(n: u8) -> [(u8, u8); 1] {
+            [(n, n)]
+        }
+
+        let v = rcomp![Vec<_>; for x in zip_with(&[1], &[2], 2) => x];
+        assert_eq!(v, [1, 2, 1, 2]);
+        let v = rcomp![Vec<_>; for s in repeat("ab", 2) => s.len()];
+        assert_eq!(v, [2, 2]);
+        let v = rcomp![Vec<_>; for x in transpose(1) => x];
+        assert_eq!(v, [1, 2]);
+        let v = rcomp![Vec<_>; for x in grid(2, 3) => x];
+        assert_eq!(v, [6]);
+        let v = rcomp![String; for c in upper_triangle("ab") => c.to_ascii_uppercase()];
+        assert_eq!(v, "AB");
+        let v = rcomp![Vec<_>; for (a, b) in pairs_of(4) => a + b];
+        assert_eq!(v, [8]);
+        let cycle = [1, 2, 3];
+        let v = rcomp![Vec<_>; for c in cycle.iter() => c * 2];
+        assert_eq!(v, [2, 4, 6]);
+    }
 }
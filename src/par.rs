@@ -0,0 +1,176 @@
+/*!
+[`rayon`] parallelism for the [`rcomp!`](crate::rcomp) macro, used by the
+`par` modifier. Requires the `rayon` feature.
+
+Only the outermost `for-in` clause runs in parallel. Each of its items is a
+separate task on rayon's thread pool, and the inner clauses, guard, and
+mapper run sequentially inside that task. This suits matrix-shaped work,
+where the rows are spread across threads and each row's columns stay
+together. The results are put back in the original order, so the output is
+the same as the sequential comprehension.
+
+Unlike the [`threads`](crate::threads) modifier, which splits the source
+into one chunk per thread up front, rayon's work stealing keeps every
+thread busy when some items take much longer than others.
+
+When the items are cheap, one task each is mostly overhead. `par chunks n`
+makes each task a run of `n` consecutive items instead, which also keeps
+neighbouring items on the same thread.
+
+Both run on rayon's global pool by default. The `pool <pool>` modifier runs
+them on another [`ThreadPool`] instead, so that a service can keep
+comprehension work away from its latency-sensitive threads.
+*/
+
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use rayon::ThreadPool;
+
+/// Runs `f` on each item of `iter` as a separate task on rayon's thread
+/// pool. The outputs are yielded in the same order as the items.
+///
+/// The whole source is collected before any tasks are started, so this
+/// won't work with infinite iterators.
+///
+/// This is what `rcomp![par; ...]` expands to, where `f` is the rest of the
+/// comprehension for one outer item.
+///
+/// # Panics
+///
+/// Panics if `f` panics on any of the items.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let matrix = vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]];
+/// let v = rcomp![par; Vec<_>; for row in &matrix, x in row => x * 2, if x % 2 == 1];
+/// assert_eq!(v, vec![2, 6, 10, 14, 18]);
+/// ```
+pub fn each<I, F, U>(iter: I, f: F) -> impl Iterator<Item = U>
+where
+    I: IntoIterator,
+    I::Item: Send,
+    F: Fn(I::Item) -> Vec<U> + Sync + Send,
+    U: Send,
+{
+    let items = iter.into_iter().collect::<Vec<_>>();
+    let results = items.into_par_iter().map(f).collect::<Vec<_>>();
+    results.into_iter().flatten()
+}
+
+/// Splits `iter` into chunks of `size` consecutive items and runs `f` on
+/// each one as a separate task on rayon's thread pool. The last chunk may
+/// be shorter. The outputs are yielded in the same order as the chunks.
+///
+/// The whole source is collected before any tasks are started, so this
+/// won't work with infinite iterators.
+///
+/// This is what `rcomp![par chunks n; ...]` expands to, where `f` is the
+/// rest of the comprehension.
+///
+/// # Panics
+///
+/// Panics if `size` is zero, or if `f` panics on any of the chunks.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![par chunks 64; Vec<_>; for x in 0..1000 => x * x, if x % 3 == 0];
+/// let it = (0..1000).filter(|x| x % 3 == 0).map(|x| x * x).collect::<Vec<_>>();
+/// assert_eq!(v, it);
+/// ```
+pub fn chunks<I, F, U>(size: usize, iter: I, f: F) -> impl Iterator<Item = U>
+where
+    I: IntoIterator,
+    I::Item: Send,
+    F: Fn(Vec<I::Item>) -> Vec<U> + Sync + Send,
+    U: Send,
+{
+    assert!(size > 0, "a comprehension can't be split into empty chunks");
+    let items = iter.into_iter().collect::<Vec<_>>();
+    let results = items
+        .into_par_iter()
+        .chunks(size)
+        .map(f)
+        .collect::<Vec<_>>();
+    results.into_iter().flatten()
+}
+
+/// Runs `f` on `pool`, so that any parallel work it does uses that pool's
+/// threads instead of the global pool. This is [`ThreadPool::install`].
+///
+/// This is what `rcomp![pool <pool>; ...]` expands to. It only makes a
+/// difference to the `par` modes, since the rest of a comprehension is
+/// sequential anyway. `pool` can be a `&ThreadPool` or an expression that
+/// builds one.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+/// let v = rcomp![pool &pool; par; Vec<_>; for _ in 0..4 => rayon::current_thread_index().is_some()];
+/// assert_eq!(v, [true; 4]);
+/// ```
+pub fn install<F, R>(pool: &ThreadPool, f: F) -> R
+where
+    F: FnOnce() -> R + Send,
+    R: Send,
+{
+    pool.install(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rcomp;
+
+    #[test]
+    fn test_par_preserves_order() {
+        let rows = (0..50)
+            .map(|i| (0..i).collect::<Vec<u32>>())
+            .collect::<Vec<_>>();
+        let expected = rcomp![Vec<_>; for row in &rows, x in row => x * 3, if x % 7 != 0];
+        let (actual, report) =
+            rcomp![report; par; Vec<_>; for row in &rows, x in row => x * 3, if x % 7 != 0];
+        assert_eq!(expected, actual);
+        assert_eq!(report.produced, [50, 1225]);
+    }
+
+    #[test]
+    fn test_par_chunks_preserves_order() {
+        let expected = rcomp![Vec<_>; for x in 0..1000 => x * 3, if x % 7 != 0];
+        for size in [1, 3, 64, 1000, 5000] {
+            let actual = rcomp![par chunks size; Vec<_>; for x in 0..1000 => x * 3, if x % 7 != 0];
+            assert_eq!(expected, actual, "chunks of {size}");
+        }
+    }
+
+    #[test]
+    fn test_pool_confines_work() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(3)
+            .thread_name(|i| format!("comp-{i}"))
+            .build()
+            .unwrap();
+        let names = rcomp![pool &pool; par chunks 2; Vec<_>; for _ in 0..20 =>
+            std::thread::current().name().unwrap_or_default().to_owned()
+        ];
+        assert_eq!(names.len(), 20);
+        assert!(
+            names.iter().all(|name| name.starts_with("comp-")),
+            "{names:?}"
+        );
+        let built = rcomp![
+            pool rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+            par; Vec<_>; for x in 0..5 => x * 2
+        ];
+        assert_eq!(built, [0, 2, 4, 6, 8]);
+    }
+
+    #[test]
+    #[should_panic = "empty chunks"]
+    fn test_par_chunks_of_zero() {
+        let _ = rcomp![par chunks 0; Vec<_>; for x in 0..10 => x];
+    }
+}
@@ -0,0 +1,106 @@
+/*!
+[`indicatif`] progress bars for the [`rcomp!`](crate::rcomp) macro, used by
+the `progress "message"` modifier. Requires the `indicatif` feature.
+
+The bar ticks once for every item the outermost `for-in` clause produces,
+so it tracks how far through its source the comprehension is, no matter
+how many items the guard rejects. If the source knows its exact length,
+like a `Vec` or a range, the bar shows it; otherwise it just counts. With
+the `threads` modifier, each chunk adds its length to the bar.
+
+The bar is drawn to stderr, and is finished once the comprehension is,
+so like `report`, it needs a collection type or a terminal.
+*/
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::report::Probe;
+
+/// Keeps the progress bar for a comprehension. This is created by
+/// `rcomp![progress "message"; ...]` and shouldn't usually be used
+/// directly.
+#[derive(Debug)]
+pub struct Progress {
+    bar: ProgressBar,
+}
+
+impl Progress {
+    /// Creates a progress bar with the given message.
+    pub fn new(message: impl Into<std::borrow::Cow<'static, str>>) -> Self {
+        Self::with_bar(ProgressBar::new(0).with_message(message))
+    }
+
+    /// Uses an existing progress bar, e.g. one that's hidden or part of a
+    /// `MultiProgress`. The lengths of the sources are added to the bar's
+    /// length, if it has one.
+    #[must_use]
+    pub fn with_bar(bar: ProgressBar) -> Self {
+        if bar.length().is_none() {
+            bar.set_length(0);
+        }
+        if let Ok(style) = ProgressStyle::with_template("{msg} {wide_bar} {pos}/{len} [{elapsed}]")
+        {
+            bar.set_style(style);
+        }
+        Self { bar }
+    }
+
+    /// Returns the progress bar.
+    #[must_use]
+    pub fn bar(&self) -> &ProgressBar {
+        &self.bar
+    }
+
+    /// Finishes the progress bar, leaving it on the screen.
+    pub fn finish(&self) {
+        self.bar.finish();
+    }
+}
+
+impl Probe for Progress {
+    fn sourced(&self, level: usize, size_hint: (usize, Option<usize>)) {
+        if level != 0 {
+            return;
+        }
+        match size_hint {
+            (len, Some(upper)) if len == upper => self.bar.inc_length(len as u64),
+            _ => self.bar.unset_length(),
+        }
+    }
+
+    fn produced(&self, level: usize) {
+        if level == 0 {
+            self.bar.inc(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indicatif::ProgressBar;
+
+    use super::Progress;
+    use crate::rcomp;
+
+    #[test]
+    fn test_progress_counts_outer_items() {
+        let progress = Progress::with_bar(ProgressBar::hidden());
+        let v = vec![vec![1, 2], vec![3], vec![4, 5, 6]];
+        let actual =
+            rcomp![Vec<_>; @probe [&progress] for row in &v, x in row => *x, if x % 2 == 0];
+        assert_eq!(actual, [2, 4, 6]);
+        assert_eq!(progress.bar().position(), 3);
+        assert_eq!(progress.bar().length(), Some(3));
+    }
+
+    #[test]
+    fn test_progress_with_threads_and_unknown_length() {
+        let (v, report) = rcomp![progress "crunching"; report; threads 3; Vec<_>; for x in 0..10 => x * x, if x > 6];
+        assert_eq!(v, [49, 64, 81]);
+        assert_eq!(report.rejected, 7);
+        let progress = Progress::with_bar(ProgressBar::hidden());
+        let n = rcomp![@probe [&progress] for x in (0..5).filter(|x| x % 2 == 0) => x].count();
+        assert_eq!((n, progress.bar().position()), (3, 3));
+        assert_eq!(progress.bar().length(), None);
+    }
+}
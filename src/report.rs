@@ -0,0 +1,242 @@
+/*!
+Statistics for the [`rcomp!`](crate::rcomp) macro, used by the `report`
+modifier.
+
+A comprehension with the `report` modifier evaluates to a tuple of its
+usual result and a [`CompReport`], which says how many items each `for-in`
+clause produced and how many the guard rejected. When a comprehension comes
+up short, this is usually the quickest way to find out where the items
+went.
+
+```rust
+# use rustcomp::rcomp;
+let matrix = vec![vec![1, 2, 3], vec![], vec![4, 5, 6]];
+let (v, report) = rcomp![report; Vec<_>; for row in &matrix, x in row => *x, if x % 2 == 0];
+assert_eq!(v, vec![2, 4, 6]);
+assert_eq!(report.produced, vec![3, 6]);
+assert_eq!(report.rejected, 3);
+assert_eq!(report.yielded, 3);
+```
+
+Since the report is only complete once the iterator has been consumed, the
+`report` modifier needs a collection type or a terminal.
+*/
+
+use std::sync::Mutex;
+
+/// Hooks that a comprehension calls as items move through it. This is how
+/// the `report` and `trace` modifiers observe a comprehension; every method
+/// does nothing by default.
+///
+/// Probes are shared between the closures of a comprehension (and between
+/// threads with the `threads` modifier), so they only get `&self`.
+pub trait Probe {
+    /// Called once, before the comprehension starts, with how many `for-in`
+    /// clauses it has.
+    fn started(&self, levels: usize) {
+        let _ = levels;
+    }
+
+    /// Called with the size hint of each source the `for-in` clause at
+    /// `level` starts on. The outermost clause is level `0`, and its source
+    /// is split into chunks with the `threads` modifier, so this is called
+    /// once per chunk.
+    fn sourced(&self, level: usize, size_hint: (usize, Option<usize>)) {
+        let _ = (level, size_hint);
+    }
+
+    /// Called whenever the `for-in` clause at `level` produces an item. The
+    /// outermost clause is level `0`.
+    fn produced(&self, level: usize) {
+        let _ = level;
+    }
+
+    /// Called whenever an item reaches the guard.
+    fn scanned(&self) {}
+
+    /// Called whenever the guard rejects an item.
+    fn rejected(&self) {}
+
+    /// Called whenever the comprehension yields an item, after any
+    /// trailing clauses.
+    fn yielded(&self) {}
+}
+
+impl<P: Probe + ?Sized> Probe for &P {
+    fn started(&self, levels: usize) {
+        (**self).started(levels);
+    }
+
+    fn sourced(&self, level: usize, size_hint: (usize, Option<usize>)) {
+        (**self).sourced(level, size_hint);
+    }
+
+    fn produced(&self, level: usize) {
+        (**self).produced(level);
+    }
+
+    fn scanned(&self) {
+        (**self).scanned();
+    }
+
+    fn rejected(&self) {
+        (**self).rejected();
+    }
+
+    fn yielded(&self) {
+        (**self).yielded();
+    }
+}
+
+/// Lets two probes watch the same comprehension, e.g. with both the `trace`
+/// and `report` modifiers.
+impl<A: Probe, B: Probe> Probe for (A, B) {
+    fn started(&self, levels: usize) {
+        self.0.started(levels);
+        self.1.started(levels);
+    }
+
+    fn sourced(&self, level: usize, size_hint: (usize, Option<usize>)) {
+        self.0.sourced(level, size_hint);
+        self.1.sourced(level, size_hint);
+    }
+
+    fn produced(&self, level: usize) {
+        self.0.produced(level);
+        self.1.produced(level);
+    }
+
+    fn scanned(&self) {
+        self.0.scanned();
+        self.1.scanned();
+    }
+
+    fn rejected(&self) {
+        self.0.rejected();
+        self.1.rejected();
+    }
+
+    fn yielded(&self) {
+        self.0.yielded();
+        self.1.yielded();
+    }
+}
+
+/// Turns `iter` into an iterator and passes its size hint to
+/// [`Probe::sourced`]. This is how a comprehension with a probe starts on
+/// each of its sources, and shouldn't usually be called directly.
+pub fn sourced<I, P>(iter: I, probe: &P, level: usize) -> I::IntoIter
+where
+    I: IntoIterator,
+    P: Probe + ?Sized,
+{
+    let iter = iter.into_iter();
+    probe.sourced(level, iter.size_hint());
+    iter
+}
+
+/// How many items went through each part of a comprehension. This is
+/// returned by `rcomp![report; ...]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CompReport {
+    /// How many items each `for-in` clause produced, outermost first.
+    pub produced: Vec<usize>,
+    /// How many items the guard rejected.
+    pub rejected: usize,
+    /// How many items the comprehension yielded, after any trailing clauses.
+    pub yielded: usize,
+}
+
+/// A [`Probe`] that builds a [`CompReport`]. This is created by
+/// `rcomp![report; ...]` and shouldn't usually be used directly.
+#[derive(Debug, Default)]
+pub struct Recorder {
+    report: Mutex<CompReport>,
+}
+
+impl Recorder {
+    /// Returns the finished report.
+    pub fn finish(self) -> CompReport {
+        self.report
+            .into_inner()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn update(&self, f: impl FnOnce(&mut CompReport)) {
+        f(&mut self
+            .report
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner));
+    }
+}
+
+impl Probe for Recorder {
+    // the counts are there from the start, so a clause that never got to
+    // produce anything still shows up as a `0`
+    fn started(&self, levels: usize) {
+        self.update(|report| {
+            if report.produced.len() < levels {
+                report.produced.resize(levels, 0);
+            }
+        });
+    }
+
+    fn produced(&self, level: usize) {
+        self.update(|report| {
+            if report.produced.len() <= level {
+                report.produced.resize(level + 1, 0);
+            }
+            report.produced[level] += 1;
+        });
+    }
+
+    fn rejected(&self) {
+        self.update(|report| report.rejected += 1);
+    }
+
+    fn yielded(&self) {
+        self.update(|report| report.yielded += 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompReport;
+    use crate::rcomp;
+
+    #[test]
+    fn test_report_with_clauses_and_threads() {
+        let v = vec![vec![1, 2, 2], vec![3, 1, 4], vec![]];
+        let (actual, report) = rcomp![
+            report; threads 2; Vec<_>; for row in &v, x in row => *x, if *x != 4, unique by |x| *x
+        ];
+        assert_eq!(actual, vec![1, 2, 3]);
+        let expected = CompReport {
+            produced: vec![3, 6],
+            rejected: 1,
+            yielded: 3,
+        };
+        assert_eq!(report, expected);
+    }
+
+    #[test]
+    fn test_report_with_terminal() {
+        let (extremes, report) = rcomp![report; minmax; for x in 0..10 => x, if x > 20];
+        assert_eq!(extremes, crate::terminals::MinMax::NoElements);
+        assert_eq!(report.produced, vec![10]);
+        assert_eq!(report.rejected, 10);
+        assert_eq!(report.yielded, 0);
+    }
+
+    #[test]
+    fn test_report_with_empty_source() {
+        let (v, report) =
+            rcomp![report; move; Vec<i32>; for x in Vec::<i32>::new(), y in 0..3 => x + y];
+        assert!(v.is_empty());
+        assert_eq!(report.produced, vec![0, 0]);
+
+        let (v, report) = rcomp![report; threads 2; move; Vec<_>; for x in 0..4, y in 0..0, z in 0..2 => (x, y, z)];
+        assert!(v.is_empty());
+        assert_eq!(report.produced, vec![4, 0, 0]);
+    }
+}
@@ -0,0 +1,150 @@
+/*!
+Set operations used by the [`intersect!`](crate::intersect),
+[`union!`](crate::union), and [`difference!`](crate::difference) macros.
+
+Each operation is lazy: it streams its first operand and yields every
+distinct item that belongs in the result, in the order they were first
+seen. [`intersect`] and [`difference`] look items up in their second
+operand, which can be any [`Lookup`], so a set that already exists is used
+as it is instead of being copied.
+*/
+
+use std::collections::{BTreeSet, HashSet};
+use std::hash::{BuildHasher, Hash};
+
+use crate::adapters::unique_by;
+use crate::collections::SortedVec;
+
+/// Collections that can tell whether they contain an item without being
+/// iterated over. This is what the second operand of [`intersect`] and
+/// [`difference`] has to be.
+pub trait Lookup<T> {
+    /// Returns `true` if `item` is in the collection.
+    fn contains(&self, item: &T) -> bool;
+}
+
+impl<L: Lookup<T> + ?Sized, T> Lookup<T> for &L {
+    fn contains(&self, item: &T) -> bool {
+        (**self).contains(item)
+    }
+}
+
+impl<T: Hash + Eq, S: BuildHasher> Lookup<T> for HashSet<T, S> {
+    fn contains(&self, item: &T) -> bool {
+        HashSet::contains(self, item)
+    }
+}
+
+impl<T: Ord> Lookup<T> for BTreeSet<T> {
+    fn contains(&self, item: &T) -> bool {
+        BTreeSet::contains(self, item)
+    }
+}
+
+impl<T: Ord> Lookup<T> for SortedVec<T> {
+    fn contains(&self, item: &T) -> bool {
+        SortedVec::contains(self, item)
+    }
+}
+
+/// Yields the distinct items of `iter` that are also in `other`.
+///
+/// This is what `intersect!(a, b)` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// use std::collections::HashSet;
+/// use rustcomp::sets::intersect;
+///
+/// let allowed = HashSet::from([2, 3, 5, 7]);
+/// let v = intersect([1, 2, 3, 2, 4], &allowed).collect::<Vec<_>>();
+/// assert_eq!(v, [2, 3]);
+/// ```
+pub fn intersect<I, L>(iter: I, other: L) -> impl Iterator<Item = I::Item>
+where
+    I: IntoIterator,
+    I::Item: Hash + Eq + Clone,
+    L: Lookup<I::Item>,
+{
+    unique_by(
+        iter.into_iter().filter(move |item| other.contains(item)),
+        Clone::clone,
+    )
+}
+
+/// Yields the distinct items of `iter` that aren't in `other`.
+///
+/// This is what `difference!(a, b)` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// use std::collections::BTreeSet;
+/// use rustcomp::sets::difference;
+///
+/// let banned = BTreeSet::from(["eve"]);
+/// let v = difference(["bob", "eve", "alice", "bob"], banned).collect::<Vec<_>>();
+/// assert_eq!(v, ["bob", "alice"]);
+/// ```
+pub fn difference<I, L>(iter: I, other: L) -> impl Iterator<Item = I::Item>
+where
+    I: IntoIterator,
+    I::Item: Hash + Eq + Clone,
+    L: Lookup<I::Item>,
+{
+    unique_by(
+        iter.into_iter().filter(move |item| !other.contains(item)),
+        Clone::clone,
+    )
+}
+
+/// Yields the distinct items of `a`, followed by the distinct items of `b`
+/// that weren't in `a`.
+///
+/// This is what `union!(a, b)` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// use rustcomp::sets::union;
+///
+/// let v = union([3, 1, 3], [1, 4, 1, 5]).collect::<Vec<_>>();
+/// assert_eq!(v, [3, 1, 4, 5]);
+/// ```
+pub fn union<A, B>(a: A, b: B) -> impl Iterator<Item = A::Item>
+where
+    A: IntoIterator,
+    B: IntoIterator<Item = A::Item>,
+    A::Item: Hash + Eq + Clone,
+{
+    unique_by(a.into_iter().chain(b), Clone::clone)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashSet};
+
+    use crate::{difference, intersect, union};
+
+    #[test]
+    fn test_set_macros_with_comprehensions() {
+        let a = vec![1, 2, 3, 4, 5, 6, 2];
+        let b = vec![4, 5, 6, 7, 8];
+        let both = intersect!([for x in &a => x * 10, if x % 2 == 0], [for y in &b => y * 10]);
+        assert_eq!(both.collect::<Vec<_>>(), [40, 60]);
+        let either = union!(a.iter().copied(), [for y in &b => *y, if *y > 6]);
+        assert_eq!(either.collect::<Vec<_>>(), [1, 2, 3, 4, 5, 6, 7, 8]);
+        let only_a = difference!([for x in a.iter().copied() => x], [for y in b => y]);
+        assert_eq!(only_a.collect::<BTreeSet<_>>(), BTreeSet::from([1, 2, 3]));
+    }
+
+    #[test]
+    fn test_set_macros_use_existing_sets() {
+        let words = HashSet::from(["apple", "pear", "plum"]);
+        let fruit = intersect!([for w in "pear fig apple".split(' ') => w], &words);
+        assert_eq!(fruit.collect::<Vec<_>>(), ["pear", "apple"]);
+        let other = difference!("pear fig apple fig".split(' '), words);
+        assert_eq!(other.collect::<Vec<_>>(), ["fig"]);
+    }
+}
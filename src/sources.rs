@@ -0,0 +1,1936 @@
+/*!
+Sources for the `for-in` clauses of the [`rcomp!`](crate::rcomp) macro.
+
+Some of these are reached through extra syntax in a `for-in` clause, like
+`for node in roots descend |n| n.children()`. The rest are plain functions
+that are meant to be used as the source of a clause.
+*/
+
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, BufRead};
+use std::rc::Rc;
+
+use crate::adapters::{on_error, HandleErrors, OnError};
+
+/// An iterator that walks a tree depth-first. See [`descend`] for more
+/// information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Descend<I, F, C: IntoIterator> {
+    roots: I,
+    children: F,
+    stack: Vec<C::IntoIter>,
+}
+
+impl<I, F, C> Iterator for Descend<I, F, C>
+where
+    I: Iterator,
+    F: FnMut(&I::Item) -> C,
+    C: IntoIterator<Item = I::Item>,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = loop {
+            match self.stack.last_mut() {
+                Some(top) => match top.next() {
+                    Some(node) => break node,
+                    None => {
+                        self.stack.pop();
+                    }
+                },
+                None => break self.roots.next()?,
+            }
+        };
+        self.stack.push((self.children)(&node).into_iter());
+        Some(node)
+    }
+}
+
+/// Walks each of the `roots` and all of their descendants depth-first, in
+/// pre-order. `children` is given a reference to each node and returns its
+/// children, which are visited in the order they're returned.
+///
+/// The traversal uses an explicit stack of iterators, so it won't overflow
+/// the call stack on deep trees.
+///
+/// This is what `for <pattern> in <roots> descend <children>` expands to.
+/// Like any other source, the roots can be anything that implements
+/// `IntoIterator`; for a single root, use something like `[&root]`.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// struct Node {
+///     value: u32,
+///     children: Vec<Node>,
+/// }
+///
+/// impl Node {
+///     fn new(value: u32, children: Vec<Node>) -> Self {
+///         Self { value, children }
+///     }
+/// }
+///
+/// let tree = Node::new(1, vec![
+///     Node::new(2, vec![Node::new(3, vec![])]),
+///     Node::new(4, vec![]),
+/// ]);
+/// let v = rcomp![Vec<_>; for node in [&tree] descend |n| n.children.iter() => node.value];
+/// assert_eq!(v, vec![1, 2, 3, 4]);
+/// ```
+pub fn descend<I, F, C>(roots: I, children: F) -> Descend<I::IntoIter, F, C>
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item) -> C,
+    C: IntoIterator<Item = I::Item>,
+{
+    Descend {
+        roots: roots.into_iter(),
+        children,
+        stack: Vec::new(),
+    }
+}
+
+/// An iterator that runs two iterators in lockstep until both are done. See
+/// [`zip_longest`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct ZipLongest<A, B> {
+    a: std::iter::Fuse<A>,
+    b: std::iter::Fuse<B>,
+}
+
+impl<A, B> Iterator for ZipLongest<A, B>
+where
+    A: Iterator,
+    B: Iterator,
+{
+    type Item = (Option<A::Item>, Option<B::Item>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match (self.a.next(), self.b.next()) {
+            (None, None) => None,
+            pair => Some(pair),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a_lower, a_upper) = self.a.size_hint();
+        let (b_lower, b_upper) = self.b.size_hint();
+        let upper = match (a_upper, b_upper) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            _ => None,
+        };
+        (a_lower.max(b_lower), upper)
+    }
+}
+
+/// Iterates over `a` and `b` in lockstep, like [`Iterator::zip`], but keeps
+/// going until _both_ are exhausted. The side that runs out first is `None`
+/// from then on; to pad it with a fill value instead, use `unwrap_or` in the
+/// mapper.
+///
+/// This is what `for (a, b) in <a> zip longest <b>` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let names = ["alice", "bob", "carol"];
+/// let scores = [90, 85];
+/// let v = rcomp![Vec<_>; for (name, score) in names zip longest scores =>
+///     format!("{}: {}", name.unwrap_or("?"), score.unwrap_or(0))
+/// ];
+/// assert_eq!(v, ["alice: 90", "bob: 85", "carol: 0"]);
+/// ```
+pub fn zip_longest<A, B>(a: A, b: B) -> ZipLongest<A::IntoIter, B::IntoIter>
+where
+    A: IntoIterator,
+    B: IntoIterator,
+{
+    ZipLongest {
+        a: a.into_iter().fuse(),
+        b: b.into_iter().fuse(),
+    }
+}
+
+/// An iterator over every `(row, col)` pair of two sources. See [`grid`]
+/// for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Grid<A: Iterator, B> {
+    rows: A,
+    cols: B,
+    row: Option<A::Item>,
+    rest: B,
+}
+
+impl<A, B> Iterator for Grid<A, B>
+where
+    A: Iterator,
+    A::Item: Clone,
+    B: Iterator + Clone,
+{
+    type Item = (A::Item, B::Item);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = &self.row {
+                if let Some(col) = self.rest.next() {
+                    return Some((row.clone(), col));
+                }
+            }
+            self.row = Some(self.rows.next()?);
+            self.rest = self.cols.clone();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (rows_lo, rows_hi) = self.rows.size_hint();
+        let (cols_lo, cols_hi) = self.cols.size_hint();
+        let (rest_lo, rest_hi) = if self.row.is_some() {
+            self.rest.size_hint()
+        } else {
+            (0, Some(0))
+        };
+        let lo = rows_lo.saturating_mul(cols_lo).saturating_add(rest_lo);
+        let hi = match (rows_hi, cols_hi, rest_hi) {
+            (Some(r), Some(c), Some(rest)) => r.checked_mul(c).and_then(|n| n.checked_add(rest)),
+            _ => None,
+        };
+        (lo, hi)
+    }
+}
+
+/// Iterates over every `(row, col)` pair of `rows` and `cols` in row-major
+/// order, i.e. all the columns of the first row, then the second row, and
+/// so on. This is the same as two `for-in` clauses with a tuple mapper, but
+/// reads better for boards and images, and it's a plain loop instead of a
+/// nested closure.
+///
+/// `cols` is cloned for every row, so it should be cheap to clone, like a
+/// range.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::grid;
+///
+/// let diagonal = rcomp![Vec<_>; for (i, j) in grid(0..3, 0..3) => (i, j), if i == j];
+/// assert_eq!(diagonal, [(0, 0), (1, 1), (2, 2)]);
+/// ```
+pub fn grid<A, B>(rows: A, cols: B) -> Grid<A::IntoIter, B::IntoIter>
+where
+    A: IntoIterator,
+    B: IntoIterator,
+    B::IntoIter: Clone,
+{
+    let cols = cols.into_iter();
+    Grid {
+        rows: rows.into_iter(),
+        rest: cols.clone(),
+        cols,
+        row: None,
+    }
+}
+
+/// An iterator over the index pairs above the diagonal of a square. See
+/// [`upper_triangle`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct UpperTriangle {
+    n: usize,
+    i: usize,
+    j: usize,
+}
+
+impl Iterator for UpperTriangle {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.j >= self.n {
+            self.i += 1;
+            self.j = self.i + 1;
+            if self.j >= self.n {
+                return None;
+            }
+        }
+        let pair = (self.i, self.j);
+        self.j += 1;
+        Some(pair)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // what's left of this row, then every full row after it
+        let row = self.n.saturating_sub(self.j);
+        let after = self.n.saturating_sub(self.i + 1);
+        let rest = after * after.saturating_sub(1) / 2;
+        (row + rest, Some(row + rest))
+    }
+}
+
+impl ExactSizeIterator for UpperTriangle {}
+
+/// Yields every index pair `(i, j)` with `i < j < n`, i.e. every unordered
+/// pair of distinct indices exactly once, in order. That's
+/// `n * (n - 1) / 2` pairs.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::upper_triangle;
+///
+/// let v = rcomp![Vec<_>; for (i, j) in upper_triangle(4) => (i, j)];
+/// assert_eq!(v, [(0, 1), (0, 2), (0, 3), (1, 2), (1, 3), (2, 3)]);
+/// ```
+pub fn upper_triangle(n: usize) -> UpperTriangle {
+    UpperTriangle { n, i: 0, j: 1 }
+}
+
+/// Yields every unordered pair of distinct items in `items` exactly once,
+/// as references, in the order of [`upper_triangle`]. Items that are equal
+/// but at different positions still make a pair.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::pairs_of;
+///
+/// let points: Vec<(i32, i32)> = vec![(0, 0), (3, 4), (6, 8)];
+/// let far = rcomp![Vec<_>; for ((ax, ay), (bx, by)) in pairs_of(&points) =>
+///     (ax - bx) * (ax - bx) + (ay - by) * (ay - by),
+///     if (ax - bx).abs() > 3
+/// ];
+/// assert_eq!(far, [100]);
+/// ```
+pub fn pairs_of<T>(items: &[T]) -> impl ExactSizeIterator<Item = (&T, &T)> + Clone {
+    upper_triangle(items.len()).map(move |(i, j)| (&items[i], &items[j]))
+}
+
+/// An iterator over the columns of a matrix. See [`transpose`] for more
+/// information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Transpose<I> {
+    rows: Vec<I>,
+}
+
+impl<I: ExactSizeIterator> Iterator for Transpose<I> {
+    type Item = Vec<I::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rows.first()?.len() == 0 {
+            return None;
+        }
+        self.rows.iter_mut().map(Iterator::next).collect()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.rows.first().map_or(0, ExactSizeIterator::len);
+        (n, Some(n))
+    }
+}
+
+impl<I: ExactSizeIterator> ExactSizeIterator for Transpose<I> {}
+
+/// The error returned by [`transpose`] when the rows aren't all the same
+/// length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RaggedError {
+    /// The index of the first row that isn't as long as the first one.
+    pub row: usize,
+    /// How many items that row has.
+    pub len: usize,
+    /// How many items the first row has.
+    pub expected: usize,
+}
+
+impl std::fmt::Display for RaggedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "can't transpose ragged rows: row {} has {} items, but row 0 has {}",
+            self.row, self.len, self.expected
+        )
+    }
+}
+
+impl std::error::Error for RaggedError {}
+
+/// Iterates over the columns of a matrix given as rows, so the first item
+/// is a `Vec` of the first item in each row, and so on. A borrowed matrix,
+/// like a `&Vec<Vec<T>>` or a `&[Vec<T>]`, yields columns of references,
+/// and an owned one yields columns of the items themselves.
+///
+/// An empty matrix has no columns.
+///
+/// # Errors
+///
+/// Returns a [`RaggedError`] if the rows aren't all the same length.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::{transpose, RaggedError};
+///
+/// fn column_sums(m: &[Vec<i32>]) -> Result<Vec<i32>, RaggedError> {
+///     Ok(rcomp![Vec<_>; for col in transpose(m)? => col.into_iter().sum()])
+/// }
+///
+/// assert_eq!(column_sums(&[vec![1, 2, 3], vec![4, 5, 6]]), Ok(vec![5, 7, 9]));
+/// let err = column_sums(&[vec![1, 2], vec![3]]).unwrap_err();
+/// assert_eq!(err, RaggedError { row: 1, len: 1, expected: 2 });
+/// ```
+pub fn transpose<R>(rows: R) -> Result<Transpose<<R::Item as IntoIterator>::IntoIter>, RaggedError>
+where
+    R: IntoIterator,
+    R::Item: IntoIterator,
+    <R::Item as IntoIterator>::IntoIter: ExactSizeIterator,
+{
+    let rows = rows
+        .into_iter()
+        .map(IntoIterator::into_iter)
+        .collect::<Vec<_>>();
+    if let Some(first) = rows.first() {
+        let n = first.len();
+        if let Some((i, row)) = rows.iter().enumerate().find(|(_, row)| row.len() != n) {
+            return Err(RaggedError {
+                row: i,
+                len: row.len(),
+                expected: n,
+            });
+        }
+    }
+    Ok(Transpose { rows })
+}
+
+/// An iterator that pairs each item with a clone of the one after it. See
+/// [`peeking`] for more information.
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Peeking<I: Iterator> {
+    iter: std::iter::Peekable<I>,
+}
+
+impl<I> Clone for Peeking<I>
+where
+    I: Iterator + Clone,
+    I::Item: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            iter: self.iter.clone(),
+        }
+    }
+}
+
+impl<I> std::fmt::Debug for Peeking<I>
+where
+    I: Iterator + std::fmt::Debug,
+    I::Item: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Peeking").field("iter", &self.iter).finish()
+    }
+}
+
+impl<I> Iterator for Peeking<I>
+where
+    I: Iterator,
+    I::Item: Clone,
+{
+    type Item = (I::Item, Option<I::Item>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.iter.next()?;
+        Some((item, self.iter.peek().cloned()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<I> ExactSizeIterator for Peeking<I>
+where
+    I: ExactSizeIterator,
+    I::Item: Clone,
+{
+}
+
+/// Pairs each item with the one after it, or `None` for the last item.
+/// This is [`Iterator::peekable`] for comprehensions, where the item can't
+/// be peeked at from inside the mapper. The next item is cloned, so for
+/// large items, iterate over references.
+///
+/// This is what `for (x, next) in peeking <source>` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let readings = [1, 3, 2, 5];
+/// let rises = rcomp![Vec<_>; for (x, next) in peeking readings => (x, next.unwrap()), if next > Some(x)];
+/// assert_eq!(rises, [(1, 3), (2, 5)]);
+/// ```
+pub fn peeking<I>(iter: I) -> Peeking<I::IntoIter>
+where
+    I: IntoIterator,
+    I::Item: Clone,
+{
+    Peeking {
+        iter: iter.into_iter().peekable(),
+    }
+}
+
+/// An iterator that combines the items of two iterators in lockstep. See
+/// [`zip_with`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct ZipWith<A, B, F> {
+    zip: std::iter::Zip<A, B>,
+    f: F,
+}
+
+impl<A, B, F, U> Iterator for ZipWith<A, B, F>
+where
+    A: Iterator,
+    B: Iterator,
+    F: FnMut(A::Item, B::Item) -> U,
+{
+    type Item = U;
+
+    fn next(&mut self) -> Option<U> {
+        let (a, b) = self.zip.next()?;
+        Some((self.f)(a, b))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.zip.size_hint()
+    }
+
+    fn fold<Acc, G>(self, init: Acc, mut g: G) -> Acc
+    where
+        G: FnMut(Acc, U) -> Acc,
+    {
+        let mut f = self.f;
+        self.zip.fold(init, move |acc, (a, b)| g(acc, f(a, b)))
+    }
+}
+
+impl<A, B, F, U> ExactSizeIterator for ZipWith<A, B, F>
+where
+    A: ExactSizeIterator,
+    B: ExactSizeIterator,
+    F: FnMut(A::Item, B::Item) -> U,
+{
+}
+
+impl<A, B, F, U> DoubleEndedIterator for ZipWith<A, B, F>
+where
+    A: DoubleEndedIterator + ExactSizeIterator,
+    B: DoubleEndedIterator + ExactSizeIterator,
+    F: FnMut(A::Item, B::Item) -> U,
+{
+    fn next_back(&mut self) -> Option<U> {
+        let (a, b) = self.zip.next_back()?;
+        Some((self.f)(a, b))
+    }
+}
+
+/// Iterates over `a` and `b` in lockstep, like [`Iterator::zip`], and
+/// combines each pair with `f`. This is the same as `a.zip(b).map(...)`,
+/// so it stops at the shorter side, keeps the exact size of the sources,
+/// and doesn't allocate, which makes it a good fit for elementwise math
+/// like dot products.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::zip_with;
+///
+/// let xs = [1, 2, 3];
+/// let ys = [4, 5, 6];
+/// let dot: i32 = rcomp![for p in zip_with(|x, y| x * y, xs, ys) => p].sum();
+/// assert_eq!(dot, 32);
+/// ```
+pub fn zip_with<A, B, F, U>(f: F, a: A, b: B) -> ZipWith<A::IntoIter, B::IntoIter, F>
+where
+    A: IntoIterator,
+    B: IntoIterator,
+    F: FnMut(A::Item, B::Item) -> U,
+{
+    ZipWith {
+        zip: a.into_iter().zip(b),
+        f,
+    }
+}
+
+/// Maps that [`outer_join`] can look keys up in. This is implemented for
+/// `HashMap`, `BTreeMap`, and, with the `indexmap` feature, `IndexMap`.
+pub trait MapLookup<K, V> {
+    /// Returns the value for `key`, if there is one.
+    fn lookup(&self, key: &K) -> Option<&V>;
+}
+
+impl<K: Hash + Eq, V, S: BuildHasher> MapLookup<K, V> for HashMap<K, V, S> {
+    fn lookup(&self, key: &K) -> Option<&V> {
+        self.get(key)
+    }
+}
+
+impl<K: Ord, V> MapLookup<K, V> for BTreeMap<K, V> {
+    fn lookup(&self, key: &K) -> Option<&V> {
+        self.get(key)
+    }
+}
+
+#[cfg(feature = "indexmap")]
+impl<K: Hash + Eq, V, S: BuildHasher> MapLookup<K, V> for indexmap::IndexMap<K, V, S> {
+    fn lookup(&self, key: &K) -> Option<&V> {
+        self.get(key)
+    }
+}
+
+/// Iterates over every key that's in either map, along with its value in
+/// each one, as `(key, Option<&A>, Option<&B>)`. The keys of `a` come first,
+/// in `a`'s order, followed by the keys that are only in `b`, in `b`'s
+/// order. The maps don't have to be the same type.
+///
+/// This is what `for (key, a, b) in <a> outer join <b>` expands to. Both
+/// maps are borrowed, so they're written as references.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use std::collections::{BTreeMap, HashMap};
+///
+/// let defaults = BTreeMap::from([("color", "auto"), ("pager", "less")]);
+/// let overrides = HashMap::from([("pager", "more"), ("editor", "vi")]);
+/// let merged = rcomp![BTreeMap<_, _>; for (key, default, set) in &defaults outer join &overrides =>
+///     (*key, set.or(default).copied().unwrap_or_default())
+/// ];
+/// assert_eq!(
+///     merged,
+///     BTreeMap::from([("color", "auto"), ("editor", "vi"), ("pager", "more")])
+/// );
+/// ```
+pub fn outer_join<'a, K, A, B, MA, MB>(
+    a: &'a MA,
+    b: &'a MB,
+) -> impl Iterator<Item = (&'a K, Option<&'a A>, Option<&'a B>)>
+where
+    K: 'a,
+    A: 'a,
+    B: 'a,
+    MA: MapLookup<K, A>,
+    MB: MapLookup<K, B>,
+    &'a MA: IntoIterator<Item = (&'a K, &'a A)>,
+    &'a MB: IntoIterator<Item = (&'a K, &'a B)>,
+{
+    let both = a
+        .into_iter()
+        .map(move |(key, value)| (key, Some(value), b.lookup(key)));
+    let b_only = b
+        .into_iter()
+        .filter(move |(key, _)| a.lookup(key).is_none())
+        .map(|(key, value)| (key, None, Some(value)));
+    both.chain(b_only)
+}
+
+/// An iterator over the combinations of a pool of items. See
+/// [`combinations`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Combinations<T, const K: usize> {
+    pool: Vec<T>,
+    indices: [usize; K],
+    first: bool,
+}
+
+impl<T: Clone, const K: usize> Iterator for Combinations<T, K> {
+    type Item = [T; K];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let n = self.pool.len();
+        if K > n {
+            return None;
+        }
+        if self.first {
+            self.first = false;
+        } else {
+            // find the rightmost index that can still move right, move it,
+            // and pack everything after it right behind it
+            let i = (0..K).rev().find(|&i| self.indices[i] != i + n - K)?;
+            self.indices[i] += 1;
+            for j in i + 1..K {
+                self.indices[j] = self.indices[j - 1] + 1;
+            }
+        }
+        Some(std::array::from_fn(|i| self.pool[self.indices[i]].clone()))
+    }
+}
+
+/// Iterates over every way to pick `K` of the items, without repeats and
+/// ignoring order, in lexicographic order of position. The items are
+/// collected first and cloned into each combination.
+///
+/// This is what `for [a, b] in combinations 2 of <source>` expands to.
+///
+/// # Example
+///
+/// Every match in a round-robin tournament:
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let teams = ["ants", "bees", "cats"];
+/// let v = rcomp![Vec<_>; for [home, away] in combinations 2 of teams => format!("{home}-{away}")];
+/// assert_eq!(v, ["ants-bees", "ants-cats", "bees-cats"]);
+/// ```
+pub fn combinations<I, const K: usize>(iter: I) -> Combinations<I::Item, K>
+where
+    I: IntoIterator,
+    I::Item: Clone,
+{
+    Combinations {
+        pool: iter.into_iter().collect(),
+        indices: std::array::from_fn(|i| i),
+        first: true,
+    }
+}
+
+/// An iterator over the permutations of a pool of items. See
+/// [`permutations`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Permutations<T, const K: usize> {
+    pool: Vec<T>,
+    indices: Vec<usize>,
+    cycles: [usize; K],
+    first: bool,
+}
+
+impl<T: Clone, const K: usize> Iterator for Permutations<T, K> {
+    type Item = [T; K];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let n = self.pool.len();
+        if K > n {
+            return None;
+        }
+        if self.first {
+            self.first = false;
+        } else {
+            // the same algorithm as Python's `itertools.permutations`
+            let i = (0..K).rev().find(|&i| {
+                self.cycles[i] -= 1;
+                if self.cycles[i] == 0 {
+                    self.indices[i..].rotate_left(1);
+                    self.cycles[i] = n - i;
+                    false
+                } else {
+                    true
+                }
+            })?;
+            self.indices.swap(i, n - self.cycles[i]);
+        }
+        Some(std::array::from_fn(|i| self.pool[self.indices[i]].clone()))
+    }
+}
+
+/// Iterates over every ordered way to pick `K` of the items without
+/// repeats, in lexicographic order of position. The items are collected
+/// first and cloned into each permutation.
+///
+/// This is what `for [a, b] in permutations 2 of <source>` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let v = rcomp![Vec<String>; for [a, b, c] in permutations 3 of "abc".chars() => [a, b, c].iter().collect()];
+/// assert_eq!(v, ["abc", "acb", "bac", "bca", "cab", "cba"]);
+/// ```
+pub fn permutations<I, const K: usize>(iter: I) -> Permutations<I::Item, K>
+where
+    I: IntoIterator,
+    I::Item: Clone,
+{
+    let pool = iter.into_iter().collect::<Vec<_>>();
+    let n = pool.len();
+    Permutations {
+        indices: (0..n).collect(),
+        cycles: std::array::from_fn(|i| n.saturating_sub(i)),
+        pool,
+        first: true,
+    }
+}
+
+/// An iterator over the subsets of a pool of items. See [`powerset`] for
+/// more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Powerset<T> {
+    pool: Vec<T>,
+    indices: Vec<usize>,
+    max: usize,
+    first: bool,
+}
+
+impl<T: Clone> Iterator for Powerset<T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let n = self.pool.len();
+        let k = self.indices.len();
+        if self.first {
+            self.first = false;
+        } else if let Some(i) = (0..k).rev().find(|&i| self.indices[i] != i + n - k) {
+            // the next combination of the same size, like `Combinations`
+            self.indices[i] += 1;
+            for j in i + 1..k {
+                self.indices[j] = self.indices[j - 1] + 1;
+            }
+        } else if k < self.max.min(n) {
+            self.indices = (0..=k).collect();
+        } else {
+            return None;
+        }
+        Some(self.indices.iter().map(|&i| self.pool[i].clone()).collect())
+    }
+}
+
+/// Iterates over every subset of the items with at most `max` items,
+/// smallest first, starting with the empty set. Subsets of the same size
+/// come in lexicographic order of position, so they're in the same order as
+/// [`combinations`]. The items are collected first and cloned into each
+/// subset.
+///
+/// A pool of `n` items has `2^n` subsets, so `max` is a good way to keep
+/// the search from blowing up. Use `usize::MAX` for no limit.
+///
+/// This is what `for subset in powerset [<max> of] <source>` expands to.
+///
+/// # Example
+///
+/// Finding the smallest set of coins that adds up to a target:
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let coins = [1, 5, 10, 25];
+/// let v = rcomp![Vec<_>; for coins in powerset coins => coins, if coins.iter().sum::<i32>() == 30];
+/// assert_eq!(v[0], [5, 25]);
+/// ```
+pub fn powerset<I>(iter: I, max: usize) -> Powerset<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Clone,
+{
+    Powerset {
+        pool: iter.into_iter().collect(),
+        indices: Vec::new(),
+        max,
+        first: true,
+    }
+}
+
+/// An iterator over the cartesian product of a runtime list of pools. See
+/// [`multi_cross`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct MultiCross<T> {
+    pools: Vec<Vec<T>>,
+    indices: Vec<usize>,
+    done: bool,
+}
+
+impl<T: Clone> Iterator for MultiCross<T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let combo = self
+            .indices
+            .iter()
+            .zip(&self.pools)
+            .map(|(&i, pool)| pool[i].clone())
+            .collect();
+        // tick the indices like an odometer, last pool fastest
+        self.done = true;
+        for (i, pool) in self.indices.iter_mut().zip(&self.pools).rev() {
+            *i += 1;
+            if *i < pool.len() {
+                self.done = false;
+                break;
+            }
+            *i = 0;
+        }
+        Some(combo)
+    }
+}
+
+/// Iterates over the cartesian product of any number of sources, yielding
+/// one `Vec` per combination with an item from each source, in order. This
+/// is the same as chaining `for-in` clauses, except the number of sources
+/// only has to be known at runtime. The sources are collected first and
+/// their items cloned into each combination.
+///
+/// If any source is empty, there are no combinations. If there are no
+/// sources, there's exactly one: the empty one.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::multi_cross;
+///
+/// let options = vec![vec!["S", "M"], vec!["red", "blue"], vec!["cotton"]];
+/// let v = rcomp![Vec<_>; for combo in multi_cross(options) => combo.join("/")];
+/// assert_eq!(v, ["S/red/cotton", "S/blue/cotton", "M/red/cotton", "M/blue/cotton"]);
+/// ```
+pub fn multi_cross<I>(sources: I) -> MultiCross<<I::Item as IntoIterator>::Item>
+where
+    I: IntoIterator,
+    I::Item: IntoIterator,
+    <I::Item as IntoIterator>::Item: Clone,
+{
+    let pools = sources
+        .into_iter()
+        .map(|source| source.into_iter().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    MultiCross {
+        indices: vec![0; pools.len()],
+        done: pools.iter().any(Vec::is_empty),
+        pools,
+    }
+}
+
+/// An iterator that merges several sorted iterators into one. See
+/// [`merge_sorted`] for more information.
+#[derive(Debug, Clone)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct MergeSorted<I: Iterator> {
+    iters: Vec<I>,
+    // the next item from each iterator, along with which iterator it came
+    // from so that ties are broken in source order
+    heap: BinaryHeap<Reverse<(I::Item, usize)>>,
+}
+
+impl<I> Iterator for MergeSorted<I>
+where
+    I: Iterator,
+    I::Item: Ord,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((item, i)) = self.heap.pop()?;
+        if let Some(next) = self.iters[i].next() {
+            self.heap.push(Reverse((next, i)));
+        }
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iters.iter().map(Iterator::size_hint).fold(
+            (self.heap.len(), Some(self.heap.len())),
+            |(lo, hi), (l, h)| {
+                (
+                    lo.saturating_add(l),
+                    hi.zip(h).and_then(|(a, b)| a.checked_add(b)),
+                )
+            },
+        )
+    }
+}
+
+/// Merges several sorted sources into one sorted iterator, without
+/// collecting them first. Only the next item of each source is kept around,
+/// in a binary heap, so each item costs `O(log k)` for `k` sources. Equal
+/// items come out in the order of their sources.
+///
+/// If a source isn't sorted, the output won't be either, but every item
+/// still comes out exactly once.
+///
+/// This is what `for x in merge sorted(a, b, ...)` expands to, with each
+/// source boxed so they can have different types.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let evens = (0..10).step_by(2);
+/// let odds = vec![1, 3, 5];
+/// let v = rcomp![Vec<_>; for x in merge sorted(evens, odds, [7, 11]) => x];
+/// assert_eq!(v, vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 11]);
+/// ```
+pub fn merge_sorted<I>(sources: I) -> MergeSorted<<I::Item as IntoIterator>::IntoIter>
+where
+    I: IntoIterator,
+    I::Item: IntoIterator,
+    <I::Item as IntoIterator>::Item: Ord,
+{
+    let mut iters = sources
+        .into_iter()
+        .map(IntoIterator::into_iter)
+        .collect::<Vec<_>>();
+    let heap = iters
+        .iter_mut()
+        .enumerate()
+        .filter_map(|(i, iter)| Some(Reverse((iter.next()?, i))))
+        .collect();
+    MergeSorted { iters, heap }
+}
+
+/// A handle for adding items to a [`worklist`] while it's being iterated.
+#[derive(Debug)]
+pub struct Enqueue<T> {
+    queue: Rc<RefCell<VecDeque<T>>>,
+}
+
+impl<T> Enqueue<T> {
+    /// Adds an item to the back of the worklist.
+    pub fn push(&self, item: T) {
+        self.queue.borrow_mut().push_back(item);
+    }
+
+    /// Adds every item in `items` to the back of the worklist.
+    pub fn extend(&self, items: impl IntoIterator<Item = T>) {
+        self.queue.borrow_mut().extend(items);
+    }
+
+    /// Returns the number of items still waiting in the worklist.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.queue.borrow().len()
+    }
+
+    /// Returns `true` if there are no items waiting in the worklist.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.queue.borrow().is_empty()
+    }
+}
+
+impl<T> Clone for Enqueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: Rc::clone(&self.queue),
+        }
+    }
+}
+
+/// An iterator over a queue that can grow while it's being iterated. See
+/// [`worklist`] for more information.
+#[derive(Debug)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub struct Worklist<T> {
+    handle: Enqueue<T>,
+}
+
+impl<T> Iterator for Worklist<T> {
+    type Item = (T, Enqueue<T>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.handle.queue.borrow_mut().pop_front()?;
+        Some((item, self.handle.clone()))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        // more items can always be added
+        (self.handle.len(), None)
+    }
+}
+
+/// Turns the comprehension into a breadth-first worklist. Each item is
+/// yielded along with an [`Enqueue`] handle, which the mapper (or guard)
+/// can use to add more items to the back of the queue. Iteration stops once
+/// the queue is empty.
+///
+/// Items are added as soon as `push` is called, so this works best when the
+/// worklist is the only `for-in` clause. The queue isn't shared between
+/// threads, so it can't be used with the `threads` modifier.
+///
+/// # Example
+///
+/// Resolving every transitive dependency of a package, skipping the ones
+/// that were already found:
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::worklist;
+/// use std::collections::{HashMap, HashSet};
+///
+/// let deps = HashMap::from([
+///     ("app", vec!["http", "log"]),
+///     ("http", vec!["tls", "log"]),
+///     ("tls", vec!["log"]),
+/// ]);
+/// let mut seen = HashSet::new();
+/// let order = rcomp![Vec<_>; for (pkg, queue) in worklist(["app"]) => {
+///     queue.extend(deps.get(pkg).into_iter().flatten().copied());
+///     pkg
+/// }, if seen.insert(pkg)];
+/// assert_eq!(order, vec!["app", "http", "log", "tls"]);
+/// ```
+pub fn worklist<I>(seeds: I) -> Worklist<I::Item>
+where
+    I: IntoIterator,
+{
+    Worklist {
+        handle: Enqueue {
+            queue: Rc::new(RefCell::new(seeds.into_iter().collect())),
+        },
+    }
+}
+
+/// Iterates over the lines of `reader`, handling any I/O errors according
+/// to `policy`. The lines don't include the trailing newline, just like
+/// [`BufRead::lines`].
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::adapters::OnError;
+/// use rustcomp::sources::lines;
+///
+/// let file = "name,age\nalice,31\nbob,27\n";
+/// let mut error = None;
+/// let ages = rcomp![Vec<u32>; for line in lines(file.as_bytes(), OnError::Abort(&mut error)) =>
+///     line.split(',').nth(1).unwrap().parse().unwrap(),
+///     if !line.starts_with("name")
+/// ];
+/// assert_eq!(ages, vec![31, 27]);
+/// assert!(error.is_none());
+/// ```
+pub fn lines<R: BufRead>(
+    reader: R,
+    policy: OnError<'_, io::Error>,
+) -> HandleErrors<'_, io::Lines<R>, io::Error> {
+    on_error(reader.lines(), policy)
+}
+
+/// Counts from the start of `range` up to (but not including) its end in
+/// steps of `step`. This works for anything that can be stepped with `+`,
+/// like dates and times or [`Instant`](std::time::Instant)s and
+/// [`Duration`](std::time::Duration)s.
+///
+/// Iteration also stops if adding `step` doesn't move forward, so a zero or
+/// negative step yields at most the start.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::every;
+/// use std::time::Duration;
+///
+/// let start = Duration::from_secs(60);
+/// let v = rcomp![Vec<_>; for t in every(start..start * 2, Duration::from_secs(15)) => t.as_secs()];
+/// assert_eq!(v, vec![60, 75, 90, 105]);
+/// ```
+pub fn every<T, S>(range: std::ops::Range<T>, step: S) -> impl Iterator<Item = T>
+where
+    T: Clone + PartialOrd + std::ops::Add<S, Output = T>,
+    S: Clone,
+{
+    let std::ops::Range { start, end } = range;
+    std::iter::successors(Some(start), move |t| {
+        let next = t.clone() + step.clone();
+        (next > *t).then_some(next)
+    })
+    .take_while(move |t| *t < end)
+}
+
+/// Yields `n` clones of `value`. This is [`std::iter::repeat_n`], which
+/// can't be left running forever by forgetting a `take`.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::repeat;
+///
+/// let v = rcomp![String; for s in repeat("ab", 3) => s];
+/// assert_eq!(v, "ababab");
+/// ```
+pub fn repeat<T: Clone>(value: T, n: usize) -> std::iter::RepeatN<T> {
+    std::iter::repeat_n(value, n)
+}
+
+/// Repeats `iter` from the start every time it runs out, and stops after
+/// `n` items in total. This is [`Iterator::cycle`] with the
+/// [`Iterator::take`] that it always needs.
+///
+/// This is what `for x in cycle <source> take <n>` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let palette = ["red", "green"];
+/// let v = rcomp![Vec<_>; for color in cycle palette take 5 => color];
+/// assert_eq!(v, ["red", "green", "red", "green", "red"]);
+/// ```
+pub fn cycle<I>(iter: I, n: usize) -> std::iter::Take<std::iter::Cycle<I::IntoIter>>
+where
+    I: IntoIterator,
+    I::IntoIter: Clone,
+{
+    iter.into_iter().cycle().take(n)
+}
+
+/// Calls `f` `n` times and yields the results. This is
+/// [`std::iter::repeat_with`] and [`Iterator::take`] in one, for values
+/// that are built fresh each time, like random numbers or buffers.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::repeat_with;
+///
+/// let mut next_id = 0;
+/// let v = rcomp![Vec<_>; for id in repeat_with(|| { next_id += 1; next_id }, 3) => id * 10];
+/// assert_eq!(v, vec![10, 20, 30]);
+/// ```
+pub fn repeat_with<T, F>(f: F, n: usize) -> std::iter::Take<std::iter::RepeatWith<F>>
+where
+    F: FnMut() -> T,
+{
+    std::iter::repeat_with(f).take(n)
+}
+
+/// Pairs every item of `iter` with a clone of `value`.
+///
+/// This is how a `static` source is passed down to its `for-in` clause:
+/// `value` is evaluated once, and every level above that clause carries a
+/// clone of it next to its own items, instead of evaluating the source
+/// again for each of them.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::sources::shared;
+/// let v: Vec<_> = shared([1, 2], "x").collect();
+/// assert_eq!(v, vec![(1, "x"), (2, "x")]);
+/// ```
+pub fn shared<I, T>(iter: I, value: T) -> std::iter::Zip<I::IntoIter, std::iter::Repeat<T>>
+where
+    I: IntoIterator,
+    T: Clone,
+{
+    iter.into_iter().zip(std::iter::repeat(value))
+}
+
+/// Numbers the items of `iter` like [`Iterator::enumerate`], pairing each
+/// number with `key` as `((key, i), item)`.
+///
+/// This is how `rcomp![indexed; ...]` numbers every `for-in` clause after
+/// the first, with `key` being the numbers of the clauses before it.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::sources::index_under;
+/// let v: Vec<_> = index_under(['a', 'b'], 7).collect();
+/// assert_eq!(v, [((7, 0), 'a'), ((7, 1), 'b')]);
+/// ```
+pub fn index_under<I, K>(iter: I, key: K) -> impl Iterator<Item = ((K, usize), I::Item)>
+where
+    I: IntoIterator,
+    K: Copy,
+{
+    iter.into_iter()
+        .enumerate()
+        .map(move |(i, item)| ((key, i), item))
+}
+
+/// Yields `init`, then `step` applied to the previous item, and so on
+/// forever. This is [`std::iter::successors`] for sequences that never
+/// run out on their own; stop them with `while` or [`Iterator::take`].
+///
+/// This is what `for x in from <init> then <step> [while <pred>]` expands
+/// to, with `pred` going to [`Iterator::take_while`]. Both `step` and
+/// `pred` are given a reference to the previous item.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let fib = rcomp![Vec<_>; for (a, _) in from (0, 1) then |(a, b)| (*b, a + b) while |(a, _)| *a < 50 => a];
+/// assert_eq!(fib, vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34]);
+/// ```
+pub fn generate<T, F>(init: T, mut step: F) -> impl Iterator<Item = T>
+where
+    F: FnMut(&T) -> T,
+{
+    std::iter::successors(Some(init), move |prev| Some(step(prev)))
+}
+
+/// Yields `steps` evenly spaced floats from `start` to `end`, inclusive,
+/// like `numpy.linspace`. Float ranges aren't iterators, and stepping
+/// with [`every`] piles up rounding errors, so this computes each point
+/// from its index instead. The last point is always exactly `end`.
+///
+/// A single step yields just `start`, and zero steps yield nothing.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::sources::linspace;
+///
+/// let v = rcomp![Vec<_>; for x in linspace(0.0, 1.0, 5) => x * x];
+/// assert_eq!(v, vec![0.0, 0.0625, 0.25, 0.5625, 1.0]);
+/// ```
+#[allow(clippy::cast_precision_loss)]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub fn linspace(
+    start: f64,
+    end: f64,
+    steps: usize,
+) -> impl DoubleEndedIterator<Item = f64> + ExactSizeIterator {
+    let last = steps.saturating_sub(1);
+    let width = end - start;
+    (0..steps).map(move |i| {
+        if i == last && i > 0 {
+            end
+        } else {
+            start + width * (i as f64 / last.max(1) as f64)
+        }
+    })
+}
+
+/// Counts the days from the start of `range` up to (but not including) its
+/// end. Requires the `chrono` feature.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use chrono::{Datelike, NaiveDate};
+/// use rustcomp::sources::days;
+///
+/// let jan = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+/// let feb = NaiveDate::from_ymd_opt(2024, 2, 1).unwrap();
+/// let mondays = rcomp![Vec<_>; for day in days(jan..feb) => day.day(), if day.weekday() == chrono::Weekday::Mon];
+/// assert_eq!(mondays, vec![1, 8, 15, 22, 29]);
+/// ```
+#[cfg(feature = "chrono")]
+pub fn days(range: std::ops::Range<chrono::NaiveDate>) -> impl Iterator<Item = chrono::NaiveDate> {
+    let std::ops::Range { start, end } = range;
+    start.iter_days().take_while(move |day| *day < end)
+}
+
+/// An entry found by [`walk`]. The fields are public so that the entry can
+/// be destructured right in the `for-in` clause.
+#[cfg(feature = "walkdir")]
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    /// The full path to the entry, starting with the root given to [`walk`].
+    pub path: std::path::PathBuf,
+    /// The entry's metadata. Symbolic links aren't followed, so this is the
+    /// metadata of the link itself.
+    pub metadata: std::fs::Metadata,
+    /// How far below the root the entry is. The root is at depth `0`.
+    pub depth: usize,
+}
+
+/// Recursively walks the directory at `root`, yielding the root itself and
+/// everything below it. Errors, like unreadable directories, are handled
+/// according to `policy`. Requires the `walkdir` feature.
+///
+/// # Example
+///
+/// Finding every Rust file bigger than a kilobyte:
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::adapters::OnError;
+/// use rustcomp::sources::{walk, WalkEntry};
+///
+/// let big_rust_files = rcomp![Vec<_>; for WalkEntry { path, metadata, .. } in walk("src", OnError::Skip) =>
+///     path,
+///     if metadata.is_file() && metadata.len() > 1024 && path.extension().is_some_and(|ext| ext == "rs")
+/// ];
+/// assert!(big_rust_files.iter().any(|p| p.ends_with("lib.rs")));
+/// ```
+#[cfg(feature = "walkdir")]
+pub fn walk<P: AsRef<std::path::Path>>(
+    root: P,
+    policy: OnError<'_, walkdir::Error>,
+) -> impl Iterator<Item = WalkEntry> + '_ {
+    let entries = walkdir::WalkDir::new(root).into_iter().map(|entry| {
+        let entry = entry?;
+        Ok(WalkEntry {
+            metadata: entry.metadata()?,
+            depth: entry.depth(),
+            path: entry.into_path(),
+        })
+    });
+    on_error(entries, policy)
+}
+
+/// Iterates over the paths matching the glob `pattern`, like
+/// `"assets/**/*.png"`, in alphabetical order. Errors reading the
+/// filesystem are handled according to `policy`. Requires the `glob`
+/// feature.
+///
+/// # Errors
+///
+/// Returns an error if `pattern` isn't a valid glob pattern.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::adapters::OnError;
+/// use rustcomp::sources::glob;
+///
+/// let mut errors = Vec::new();
+/// let modules = rcomp![Vec<_>; for path in glob("src/*.rs", OnError::Collect(&mut errors))? =>
+///     path.file_stem().unwrap().to_string_lossy().into_owned(),
+///     if !path.ends_with("lib.rs")
+/// ];
+/// assert!(errors.is_empty());
+/// assert!(modules.contains(&"sources".to_owned()));
+/// # Ok::<(), glob::PatternError>(())
+/// ```
+#[cfg(feature = "glob")]
+pub fn glob<'a>(
+    pattern: &str,
+    policy: OnError<'a, glob::GlobError>,
+) -> Result<HandleErrors<'a, glob::Paths, glob::GlobError>, glob::PatternError> {
+    Ok(on_error(glob::glob(pattern)?, policy))
+}
+
+/// Iterates over the [`Captures`](regex::Captures) of every match of `re`
+/// in `text`. Requires the `regex` feature.
+///
+/// To destructure the capture groups right in the `for-in` clause, see
+/// [`groups`] and [`named_groups`].
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use regex::Regex;
+/// use rustcomp::sources::matches;
+///
+/// let re = Regex::new(r"(?<key>\w+)=(?<value>\d+)").unwrap();
+/// let v = rcomp![Vec<_>; for caps in matches(&re, "a=1 b=x c=3") => caps["value"].to_owned()];
+/// assert_eq!(v, vec!["1", "3"]);
+/// ```
+#[cfg(feature = "regex")]
+#[must_use = "iterators are lazy and do nothing unless consumed"]
+pub fn matches<'r, 'h>(re: &'r regex::Regex, text: &'h str) -> regex::CaptureMatches<'r, 'h> {
+    re.captures_iter(text)
+}
+
+/// Iterates over every match of `re` in `text`, yielding the first `N`
+/// capture groups (not counting the whole match) as an array, so they can
+/// be destructured. Groups that didn't take part in the match are empty
+/// strings. Requires the `regex` feature.
+///
+/// # Panics
+///
+/// Panics if `re` has fewer than `N` capture groups.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use regex::Regex;
+/// use rustcomp::sources::groups;
+///
+/// let log = "[INFO] started\n[WARN] disk almost full\n[INFO] done";
+/// let re = Regex::new(r"\[(\w+)\] (.*)").unwrap();
+/// let v = rcomp![Vec<_>; for [level, msg] in groups(&re, log) => msg, if level == "WARN"];
+/// assert_eq!(v, vec!["disk almost full"]);
+/// ```
+#[cfg(feature = "regex")]
+pub fn groups<'r, 'h, const N: usize>(
+    re: &'r regex::Regex,
+    text: &'h str,
+) -> impl Iterator<Item = [&'h str; N]> + use<'r, 'h, N> {
+    assert!(
+        re.captures_len() > N,
+        "the regex has fewer than {N} capture groups"
+    );
+    re.captures_iter(text)
+        .map(|caps| std::array::from_fn(|i| caps.get(i + 1).map_or("", |m| m.as_str())))
+}
+
+/// Like [`groups`], but picks the capture groups by name. The array has
+/// the groups in the same order as `names`. Requires the `regex` feature.
+///
+/// # Panics
+///
+/// Panics if `re` doesn't have a capture group for every name.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use regex::Regex;
+/// use rustcomp::sources::named_groups;
+///
+/// let re = Regex::new(r"(?<user>\w+)@(?<host>[\w.]+)").unwrap();
+/// let text = "mail alice@example.com or bob@test.org";
+/// let v = rcomp![Vec<_>; for [host, user] in named_groups(&re, text, ["host", "user"]) => format!("{host}/{user}")];
+/// assert_eq!(v, vec!["example.com/alice", "test.org/bob"]);
+/// ```
+#[cfg(feature = "regex")]
+pub fn named_groups<'r, 'h, const N: usize>(
+    re: &'r regex::Regex,
+    text: &'h str,
+    names: [&str; N],
+) -> impl Iterator<Item = [&'h str; N]> + use<'r, 'h, N> {
+    let indices = names.map(|name| {
+        re.capture_names()
+            .position(|n| n == Some(name))
+            .unwrap_or_else(|| panic!("the regex has no capture group named `{name}`"))
+    });
+    re.captures_iter(text)
+        .map(move |caps| std::array::from_fn(|i| caps.get(indices[i]).map_or("", |m| m.as_str())))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rcomp;
+
+    #[test]
+    fn test_descend_forest_with_guard() {
+        let children = |&n: &u32| {
+            if n < 10 {
+                vec![n * 10 + 1, n * 10 + 2]
+            } else {
+                vec![]
+            }
+        };
+        let v = rcomp![Vec<_>; for n in [1, 2] descend children => n];
+        assert_eq!(v, vec![1, 11, 12, 2, 21, 22]);
+        let v = rcomp![Vec<_>; for n in [1, 2] descend children => n * 2, if n % 2 == 1];
+        assert_eq!(v, vec![2, 22, 42]);
+    }
+
+    #[test]
+    fn test_worklist_is_breadth_first() {
+        // every number below 20 reachable by doubling or adding 3, from 1
+        let v = rcomp![Vec<_>; for (n, queue) in super::worklist([1]) => {
+            if n * 2 < 20 { queue.push(n * 2); }
+            if n % 2 == 1 && n + 3 < 20 { queue.push(n + 3); }
+            n
+        }];
+        assert_eq!(v, vec![1, 2, 4, 4, 8, 8, 16, 16]);
+    }
+
+    #[test]
+    fn test_lines_error_policies() {
+        use super::lines;
+        use crate::adapters::OnError;
+
+        // invalid UTF-8 on the second line is an I/O error
+        let input: &[u8] = b"1\n\xff\n2\n";
+        let v = rcomp![Vec<_>; for l in lines(input, OnError::Skip) => l];
+        assert_eq!(v, vec!["1", "2"]);
+        let mut errors = Vec::new();
+        let v = rcomp![Vec<_>; for l in lines(input, OnError::Collect(&mut errors)) => l];
+        assert_eq!(v, vec!["1", "2"]);
+        assert_eq!(errors.len(), 1);
+        let mut error = None;
+        let v = rcomp![Vec<_>; for l in lines(input, OnError::Abort(&mut error)) => l];
+        assert_eq!(v, vec!["1"]);
+        assert_eq!(
+            error.map(|e| e.kind()),
+            Some(std::io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn test_zip_with() {
+        use super::zip_with;
+
+        let xs = vec![1.5, 2.0, -1.0];
+        let ys = [2.0, 0.5, 3.0, 100.0];
+        let v = rcomp![Vec<_>; for z in zip_with(|x, y| x * y, &xs, ys) => z];
+        assert_eq!(v, [3.0, 1.0, -3.0]);
+        let zipped = zip_with(|x: &f64, y| x + y, &xs, ys);
+        assert_eq!(zipped.len(), 3);
+        assert_eq!(zipped.rev().collect::<Vec<_>>(), [2.0, 2.5, 3.5]);
+    }
+
+    #[test]
+    fn test_outer_join() {
+        use std::collections::{BTreeMap, HashMap};
+
+        let old = BTreeMap::from([(1, "a"), (2, "b"), (3, "c")]);
+        let new = BTreeMap::from([(2, 'b'), (3, 'x'), (4, 'd')]);
+        let changes = rcomp![Vec<_>; for (k, before, after) in &old outer join &new =>
+            (*k, before.copied(), after.copied()), if before.map(|s| s.chars().next()) != Some(after.copied())
+        ];
+        assert_eq!(
+            changes,
+            [
+                (1, Some("a"), None),
+                (3, Some("c"), Some('x')),
+                (4, None, Some('d'))
+            ]
+        );
+        let empty = HashMap::<u8, u8>::new();
+        assert_eq!(
+            super::outer_join(&empty, &BTreeMap::<u8, String>::new()).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_triangles() {
+        use super::{pairs_of, upper_triangle};
+
+        for n in 0..6 {
+            let expected = rcomp![Vec<_>; move; for i in 0..n, j in i + 1..n => (i, j)];
+            let mut it = upper_triangle(n);
+            assert_eq!(it.len(), expected.len(), "n = {n}");
+            if it.next().is_some() {
+                assert_eq!(it.len(), expected.len() - 1, "n = {n}");
+            }
+            assert_eq!(rcomp![Vec<_>; for p in upper_triangle(n) => p], expected);
+        }
+        let words = ["a", "b", "a"];
+        let same = rcomp![Vec<_>; for (x, y) in pairs_of(&words) => (*x, *y), if x == y];
+        assert_eq!(same, [("a", "a")]);
+        assert_eq!(pairs_of(&words).len(), 3);
+    }
+
+    #[test]
+    fn test_grid() {
+        use super::grid;
+
+        let (w, h) = (3, 2);
+        let v = rcomp![Vec<_>; for (y, x) in grid(0..h, 0..w) => y * w + x];
+        assert_eq!(v, [0, 1, 2, 3, 4, 5]);
+        let mut it = grid(0..2, ['a', 'b']);
+        assert_eq!(it.size_hint(), (4, Some(4)));
+        it.next();
+        assert_eq!(it.size_hint(), (3, Some(3)));
+        assert_eq!(it.collect::<Vec<_>>(), [(0, 'b'), (1, 'a'), (1, 'b')]);
+        assert_eq!(grid(0..3, 0..0).count(), 0);
+        assert_eq!(grid(0..0, 0..3).size_hint(), (0, Some(0)));
+    }
+
+    #[test]
+    fn test_transpose() -> Result<(), super::RaggedError> {
+        use super::transpose;
+
+        let m: &[Vec<char>] = &[vec!['a', 'b'], vec!['c', 'd'], vec!['e', 'f']];
+        let v = rcomp![Vec<String>; for col in transpose(m)? => col.into_iter().collect()];
+        assert_eq!(v, ["ace", "bdf"]);
+        let it = transpose(m)?;
+        assert_eq!(it.len(), 2);
+        assert_eq!(transpose(Vec::<Vec<u8>>::new())?.count(), 0);
+        assert_eq!(transpose([Vec::<u8>::new(), vec![]])?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_transpose_ragged() {
+        use super::transpose;
+
+        let err = transpose(vec![vec![1, 2], vec![3]]).unwrap_err();
+        assert_eq!(
+            err,
+            super::RaggedError {
+                row: 1,
+                len: 1,
+                expected: 2
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "can't transpose ragged rows: row 1 has 1 items, but row 0 has 2"
+        );
+        let ragged = || -> Result<Vec<Vec<i32>>, super::RaggedError> {
+            Ok(rcomp![Vec<_>; for col in transpose([vec![1], vec![2, 3]])? => col])
+        };
+        assert!(ragged().is_err());
+    }
+
+    #[test]
+    fn test_cycle() {
+        let workers = ["w0", "w1", "w2"];
+        let tasks = ["a", "b", "c", "d"];
+        let v = rcomp![Vec<_>; for w in cycle workers.iter() take tasks.len() => *w];
+        assert_eq!(v, ["w0", "w1", "w2", "w0"]);
+        let v = rcomp![Vec<_>; for x in cycle [1, 2] take 0 => x];
+        assert!(v.is_empty());
+        let v = rcomp![Vec<_>; for x in cycle Vec::<u8>::new() take 3 => x];
+        assert!(v.is_empty());
+    }
+
+    #[test]
+    fn test_peeking() {
+        let text = "aab ccc";
+        let runs = rcomp![Vec<_>; for ((i, c), next) in peeking text.char_indices() => (i, c), if next.map(|(_, n)| n) != Some(c)];
+        assert_eq!(runs, [(1, 'a'), (2, 'b'), (3, ' '), (6, 'c')]);
+        let it = super::peeking(Vec::<u8>::new());
+        assert_eq!(it.len(), 0);
+        assert_eq!(super::peeking([1]).collect::<Vec<_>>(), [(1, None)]);
+    }
+
+    #[test]
+    fn test_zip_longest() {
+        let v = rcomp![Vec<_>; for (a, b) in 0..2 zip longest "xyz".chars() => (a, b)];
+        assert_eq!(
+            v,
+            vec![
+                (Some(0), Some('x')),
+                (Some(1), Some('y')),
+                (None, Some('z'))
+            ]
+        );
+        let v = rcomp![Vec<_>; for row in [vec![1, 2], vec![3]], (a, b) in row zip longest [0; 2] => (a, b)];
+        assert_eq!(
+            v,
+            vec![
+                (Some(1), Some(0)),
+                (Some(2), Some(0)),
+                (Some(3), Some(0)),
+                (None, Some(0))
+            ]
+        );
+        let it = super::zip_longest(0..5, 0..2);
+        assert_eq!(it.size_hint(), (5, Some(5)));
+        assert_eq!(it.count(), 5);
+    }
+
+    #[test]
+    fn test_combinations_and_permutations_counts() {
+        use super::{combinations, permutations};
+
+        assert_eq!(combinations::<_, 3>(0..6).count(), 20);
+        assert_eq!(combinations::<_, 0>(0..6).count(), 1);
+        assert_eq!(combinations::<_, 7>(0..6).count(), 0);
+        assert_eq!(permutations::<_, 3>(0..6).count(), 120);
+        assert_eq!(permutations::<_, 6>(0..6).count(), 720);
+        assert_eq!(permutations::<_, 0>(0..6).count(), 1);
+        assert_eq!(permutations::<_, 7>(0..6).count(), 0);
+
+        let v = rcomp![Vec<_>; for [a, b] in permutations 2 of [1, 2, 3] => (a, b)];
+        assert_eq!(v, vec![(1, 2), (1, 3), (2, 1), (2, 3), (3, 1), (3, 2)]);
+        let v = rcomp![Vec<_>; for [a, b] in combinations 2 of 1..=4 => a * b, if a + b == 5];
+        assert_eq!(v, vec![4, 6]);
+    }
+
+    #[test]
+    fn test_powerset() {
+        let v = rcomp![Vec<_>; for s in powerset 1..=3 => s];
+        let expected: Vec<Vec<i32>> = vec![
+            vec![],
+            vec![1],
+            vec![2],
+            vec![3],
+            vec![1, 2],
+            vec![1, 3],
+            vec![2, 3],
+            vec![1, 2, 3],
+        ];
+        assert_eq!(v, expected);
+        assert_eq!(
+            rcomp![for s in powerset 2 of 0..10 => s].count(),
+            1 + 10 + 45
+        );
+        assert_eq!(rcomp![for s in powerset 0..0 => s].count(), 1);
+    }
+
+    #[test]
+    fn test_multi_cross_matches_chained_clauses() {
+        use super::multi_cross;
+
+        let expected = rcomp![move; Vec<_>; for a in 0..3, b in 0..2, c in 0..4 => vec![a, b, c]];
+        let actual = rcomp![Vec<_>; for combo in multi_cross([0..3, 0..2, 0..4]) => combo];
+        assert_eq!(expected, actual);
+        assert_eq!(multi_cross([vec![1], vec![], vec![2]]).count(), 0);
+        assert_eq!(
+            multi_cross(Vec::<Vec<u8>>::new()).collect::<Vec<_>>(),
+            vec![vec![]]
+        );
+    }
+
+    #[test]
+    fn test_merge_sorted() {
+        use super::merge_sorted;
+
+        let sources = vec![
+            vec![(1, 'a'), (4, 'a')],
+            vec![],
+            vec![(1, 'c'), (2, 'c'), (9, 'c')],
+        ];
+        let v = rcomp![Vec<_>; for p in merge_sorted(sources.clone()) => p];
+        let mut expected = sources.into_iter().flatten().collect::<Vec<_>>();
+        expected.sort_unstable();
+        assert_eq!(v, expected);
+
+        let it = merge_sorted([0..3, 5..6, 2..4]);
+        assert_eq!(it.size_hint(), (6, Some(6)));
+        assert_eq!(it.collect::<Vec<_>>(), vec![0, 1, 2, 2, 3, 5]);
+        let v = rcomp![Vec<_>; for x in merge sorted(["b", "d"], vec!["a", "c", "e"]) => x, if x != "c"];
+        assert_eq!(v, vec!["a", "b", "d", "e"]);
+    }
+
+    #[test]
+    fn test_every() {
+        use super::every;
+
+        let v = rcomp![Vec<_>; for x in every(0.0..1.0, 0.25) => x];
+        assert_eq!(v, vec![0.0, 0.25, 0.5, 0.75]);
+        assert_eq!(every(3..3, 1).count(), 0);
+        assert_eq!(every(3..10, 0).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(every(3..10, -1).collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn test_repeat() {
+        use super::{repeat, repeat_with};
+
+        let v = rcomp![Vec<_>; for row in 0..3, x in repeat(row, row) => x];
+        assert_eq!(v, [1, 2, 2]);
+        let mut calls = 0;
+        let v =
+            rcomp![Vec<_>; for x in repeat_with(|| { calls += 1; calls }, 4) => x, if x % 2 == 0];
+        assert_eq!(v, [2, 4]);
+        assert_eq!(calls, 4);
+        assert_eq!(rcomp![for x in repeat('x', 0) => x].count(), 0);
+    }
+
+    #[test]
+    fn test_generate() {
+        let powers = rcomp![Vec<_>; for x in from 1 then |x| x * 2 while x < 1_000_000 => x];
+        assert_eq!(powers.len(), 20);
+        assert_eq!(powers.last(), Some(&524_288));
+        let same = rcomp![Vec<_>; for x in from 1 then |x| x * 2 while |x| *x < 1_000_000 => x];
+        assert_eq!(powers, same);
+        let words = rcomp![Vec<_>;
+            for s in from String::from("a") then |s| format!("{s}a") while |s| s.len() < 4 => s
+        ];
+        assert_eq!(words, ["a", "aa", "aaa"]);
+        let v = rcomp![for s in from String::from("a") then |s| format!("{s}b") => s.len()].take(3);
+        assert_eq!(v.collect::<Vec<_>>(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_linspace() {
+        use super::linspace;
+
+        let v = rcomp![Vec<_>; for x in linspace(-1.0, 0.5, 4) => x];
+        assert_eq!(v, [-1.0, -0.5, 0.0, 0.5]);
+        assert_eq!(linspace(0.1, 0.7, 7).last(), Some(0.7));
+        assert_eq!(
+            linspace(2.0, 1.0, 3).rev().collect::<Vec<_>>(),
+            [1.0, 1.5, 2.0]
+        );
+        assert_eq!(linspace(3.0, 9.0, 1).collect::<Vec<_>>(), [3.0]);
+        assert_eq!(linspace(3.0, 9.0, 0).len(), 0);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_days_across_month_end() {
+        use super::{days, every};
+        use chrono::{NaiveDate, TimeDelta};
+
+        let start = NaiveDate::from_ymd_opt(2024, 2, 27).unwrap();
+        let end = NaiveDate::from_ymd_opt(2024, 3, 2).unwrap();
+        let v = rcomp![Vec<_>; for day in days(start..end) => day.to_string()];
+        assert_eq!(
+            v,
+            vec!["2024-02-27", "2024-02-28", "2024-02-29", "2024-03-01"]
+        );
+        assert_eq!(every(start..end, TimeDelta::days(2)).count(), 2);
+        assert_eq!(days(end..start).count(), 0);
+    }
+
+    #[cfg(feature = "walkdir")]
+    #[test]
+    fn test_walk_this_crate() {
+        use super::{walk, WalkEntry};
+        use crate::adapters::OnError;
+
+        let root = env!("CARGO_MANIFEST_DIR");
+        let mut error = None;
+        let sources = rcomp![Vec<_>; for WalkEntry { path, depth, .. } in walk(root, OnError::Abort(&mut error)) =>
+            path.file_name().unwrap().to_owned(),
+            if depth == 2 && path.parent().unwrap().ends_with("src")
+        ];
+        assert!(error.is_none());
+        assert!(sources.iter().any(|name| name == "sources.rs"));
+
+        let mut errors = Vec::new();
+        let missing =
+            rcomp![Vec<_>; for e in walk("/does/not/exist", OnError::Collect(&mut errors)) => e];
+        assert!(missing.is_empty());
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[cfg(feature = "glob")]
+    #[test]
+    fn test_glob_this_crate() {
+        use super::glob;
+        use crate::adapters::OnError;
+
+        let pattern = concat!(env!("CARGO_MANIFEST_DIR"), "/src/**/*.rs");
+        let names = rcomp![Vec<_>; for path in glob(pattern, OnError::Skip).unwrap() =>
+            path.file_name().unwrap().to_owned()
+        ];
+        assert!(names.iter().any(|name| name == "sources.rs"));
+        assert!(names
+            .iter()
+            .all(|name| name.to_string_lossy().ends_with(".rs")));
+        assert!(glob("src/***", OnError::Skip).is_err());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex_groups() {
+        use super::{groups, named_groups};
+        use regex::Regex;
+
+        let re = Regex::new(r"(\d+)-(\d+)?").unwrap();
+        let v = rcomp![Vec<_>; for [a, b] in groups(&re, "1-2 3- 4-5") => (a, b)];
+        assert_eq!(v, vec![("1", "2"), ("3", ""), ("4", "5")]);
+
+        let re = Regex::new(r"(?<k>\w)=(?<v>\w)").unwrap();
+        let v = rcomp![Vec<_>; for [v] in named_groups(&re, "a=1,b=2", ["v"]) => v];
+        assert_eq!(v, vec!["1", "2"]);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    #[should_panic(expected = "no capture group named `nope`")]
+    fn test_regex_named_groups_missing_name() {
+        let re = regex::Regex::new(r"(?<k>\w)").unwrap();
+        let _ = super::named_groups(&re, "", ["nope"]);
+    }
+
+    #[test]
+    fn test_descend_deep_tree() {
+        // a linked list deep enough to blow the stack if this recursed
+        let v = rcomp![Vec<_>; for n in [0_u32] descend |&n| (n < 100_000).then_some(n + 1) => n];
+        assert_eq!(v.len(), 100_001);
+        assert_eq!(v.last(), Some(&100_000));
+    }
+}
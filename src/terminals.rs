@@ -0,0 +1,1626 @@
+/*!
+Terminal operations used by the [`rcomp!`](crate::rcomp) macro. These are
+the functions that a comprehension ends in when it's given a terminal
+instead of a collection type, e.g. `rcomp![shuffled; for x in v => x]`.
+
+They're public so they can be used on ordinary iterators too, but the
+macro is the intended way to reach them.
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Collections that a comprehension can be collected into. This is
+/// implemented for every type that implements [`FromIterator`], and only
+/// exists so that a comprehension with the wrong collection type gets an
+/// error about the comprehension instead of pages of iterator adapters.
+///
+/// ```compile_fail
+/// # use rustcomp::rcomp;
+/// // error: a comprehension of `u8` can't be collected into `String`
+/// let s = rcomp![String; for b in [104u8, 105] => b];
+/// ```
+#[diagnostic::on_unimplemented(
+    message = "a comprehension of `{T}` can't be collected into `{Self}`",
+    label = "this comprehension yields `{T}`",
+    note = "the collection type has to implement `FromIterator<{T}>`"
+)]
+pub trait FromComprehension<T>: Sized {
+    /// Collects the items of the comprehension.
+    fn from_comprehension<I: Iterator<Item = T>>(iter: I) -> Self;
+}
+
+impl<C: FromIterator<T>, T> FromComprehension<T> for C {
+    fn from_comprehension<I: Iterator<Item = T>>(iter: I) -> Self {
+        iter.collect()
+    }
+}
+
+/// Collects the iterator into `C`, just like [`Iterator::collect`].
+///
+/// This is what `rcomp![C; ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// use rustcomp::terminals::collect;
+///
+/// let v = collect::<Vec<_>, _>((1..4).map(|x| x * x));
+/// assert_eq!(v, vec![1, 4, 9]);
+/// ```
+pub fn collect<C, I>(iter: I) -> C
+where
+    I: IntoIterator,
+    C: FromComprehension<I::Item>,
+{
+    C::from_comprehension(iter.into_iter())
+}
+
+/// Terminals that are defined outside of this crate. A value that
+/// implements this trait can end a comprehension with
+/// `rcomp![end <terminal>; ...]`, which hands it the iterator and returns
+/// whatever it returns. This is how downstream crates can add their own
+/// reducers, like a metrics sink or a sketch, without a new keyword in the
+/// macro.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::terminals::CompTerminal;
+///
+/// /// Records how many items there were and their total.
+/// struct Metrics<'a> {
+///     name: &'a str,
+/// }
+///
+/// impl CompTerminal<u64> for Metrics<'_> {
+///     type Output = String;
+///
+///     fn finish<I: Iterator<Item = u64>>(self, iter: I) -> String {
+///         let (count, total) = iter.fold((0, 0), |(n, sum), x| (n + 1, sum + x));
+///         format!("{}: count={count} total={total}", self.name)
+///     }
+/// }
+///
+/// let line = rcomp![end Metrics { name: "latency_ms" }; for ms in [12, 30, 7] => ms];
+/// assert_eq!(line, "latency_ms: count=3 total=49");
+/// ```
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't end a comprehension of `{T}`",
+    label = "this isn't a terminal for `{T}`",
+    note = "a terminal has to implement `CompTerminal<{T}>`"
+)]
+pub trait CompTerminal<T> {
+    /// What the comprehension returns.
+    type Output;
+
+    /// Consumes the items of the comprehension.
+    fn finish<I: Iterator<Item = T>>(self, iter: I) -> Self::Output;
+}
+
+/// Tuples of collections that a comprehension of tuples can be unzipped
+/// into, one collection per field. This is [`Iterator::unzip`] for 2 to 4
+/// fields, and it's implemented for tuples of collections that implement
+/// `Default` and `Extend`.
+pub trait Unzip<T>: Sized {
+    /// Unzips the items into one collection per field, in a single pass.
+    fn unzip<I: Iterator<Item = T>>(iter: I) -> Self;
+}
+
+macro_rules! impl_unzip {
+    ($(($($item:ident $from:ident $idx:tt),+))+) => {$(
+        impl<$($item, $from: Default + Extend<$item>),+> Unzip<($($item,)+)> for ($($from,)+) {
+            fn unzip<I: Iterator<Item = ($($item,)+)>>(iter: I) -> Self {
+                iter.fold(Self::default(), |mut unzipped, item| {
+                    $(unzipped.$idx.extend(Some(item.$idx));)+
+                    unzipped
+                })
+            }
+        }
+    )+};
+}
+
+impl_unzip! {
+    (A FromA 0, B FromB 1)
+    (A FromA 0, B FromB 1, C FromC 2)
+    (A FromA 0, B FromB 1, C FromC 2, D FromD 3)
+}
+
+/// Unzips an iterator of tuples into a tuple of collections, one per field.
+///
+/// This is what `rcomp![(C1, C2, ...); ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use std::collections::HashSet;
+///
+/// let rows = [("a", 1, 'x'), ("b", 2, 'x'), ("c", 3, 'y')];
+/// let (names, nums, tags) = rcomp![(Vec<_>, Vec<_>, HashSet<_>); for (n, i, t) in rows => (n, i * 10, t)];
+/// assert_eq!(names, ["a", "b", "c"]);
+/// assert_eq!(nums, [10, 20, 30]);
+/// assert_eq!(tags, HashSet::from(['x', 'y']));
+/// ```
+pub fn unzip<C, I, T>(iter: I) -> C
+where
+    I: IntoIterator<Item = T>,
+    C: Unzip<T>,
+{
+    C::unzip(iter.into_iter())
+}
+
+/// Collects the iterator into a `bumpalo` `Vec` that lives in `bump`, so
+/// that a comprehension in a hot loop doesn't go through the global
+/// allocator. Requires the `bumpalo` feature.
+///
+/// This is what `rcomp![in <arena>, Vec<_>; ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use bumpalo::Bump;
+///
+/// let mut arena = Bump::new();
+/// for n in 1..4 {
+///     let squares = rcomp![in &arena, Vec<_>; for x in 0..n => x * x];
+///     assert_eq!(squares.len(), n);
+///     drop(squares);
+///     arena.reset();
+/// }
+/// ```
+#[cfg(feature = "bumpalo")]
+pub fn collect_in<T, I: IntoIterator<Item = T>>(
+    iter: I,
+    bump: &bumpalo::Bump,
+) -> bumpalo::collections::Vec<'_, T> {
+    let iter = iter.into_iter();
+    let mut v = bumpalo::collections::Vec::with_capacity_in(iter.size_hint().0, bump);
+    v.extend(iter);
+    v
+}
+
+/// Adds the items to the end of `collection` and returns it, like the
+/// unstable [`Iterator::collect_into`]. Clearing a buffer and collecting
+/// into it again keeps its allocation, so a comprehension in a hot loop
+/// that rebuilds an output of the same size doesn't reallocate.
+///
+/// This is what `rcomp![into <&mut collection>; ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let mut buf = Vec::with_capacity(8);
+/// for n in 1..4 {
+///     buf.clear();
+///     let squares = rcomp![into &mut buf; for x in 0..n => x * x];
+///     assert_eq!(squares.len(), n);
+/// }
+/// assert_eq!(buf, [0, 1, 4]);
+/// assert_eq!(buf.capacity(), 8);
+/// ```
+pub fn collect_into<C, I>(iter: I, collection: &mut C) -> &mut C
+where
+    C: Extend<I::Item> + ?Sized,
+    I: IntoIterator,
+{
+    collection.extend(iter);
+    collection
+}
+
+/// Applies `f` to the collected result of a comprehension.
+///
+/// This is what a trailing `then <f>` clause expands to. It only exists so
+/// that the types of a closure's arguments are known.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let first = rcomp![Vec<_>; for x in 0..5 => x * x, if x > 2, then |v| v.first().copied()];
+/// assert_eq!(first, Some(9));
+/// ```
+pub fn then<C, T>(collected: C, f: impl FnOnce(C) -> T) -> T {
+    f(collected)
+}
+
+/// Collects the iterator into a `Vec` and converts it into `T` with
+/// [`From`], for wrapper types that are built from a `Vec`.
+///
+/// This is what `rcomp![as T; ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// struct SortedIds(Vec<u32>);
+///
+/// impl From<Vec<u32>> for SortedIds {
+///     fn from(mut ids: Vec<u32>) -> Self {
+///         ids.sort_unstable();
+///         Self(ids)
+///     }
+/// }
+///
+/// let ids = rcomp![as SortedIds; for id in [7, 3, 5] => id];
+/// assert_eq!(ids.0, [3, 5, 7]);
+/// ```
+pub fn collect_as<T, I>(iter: I) -> T
+where
+    I: IntoIterator,
+    T: From<Vec<I::Item>>,
+{
+    T::from(iter.into_iter().collect())
+}
+
+/// Collects the iterator into a `Vec` and converts it into `T` with
+/// [`TryFrom`], returning the conversion's error if it fails.
+///
+/// This is what `rcomp![try as T; ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let rgb = rcomp![try as [u8; 3]; for c in "ff8000".as_bytes().chunks(2) =>
+///     u8::from_str_radix(std::str::from_utf8(c).unwrap(), 16).unwrap()
+/// ];
+/// assert_eq!(rgb, Ok([255, 128, 0]));
+/// ```
+///
+/// # Errors
+///
+/// Returns the error from `T::try_from` if the items can't be converted.
+pub fn try_collect_as<T, I>(iter: I) -> Result<T, T::Error>
+where
+    I: IntoIterator,
+    T: TryFrom<Vec<I::Item>>,
+{
+    T::try_from(iter.into_iter().collect())
+}
+
+/// Fixed-capacity collections that a comprehension can be collected into
+/// with the `bounded` terminal. Unlike `FromIterator`, adding an item can
+/// fail, in which case the item is handed back.
+///
+/// With the `heapless` feature, this is implemented for `heapless::Vec`,
+/// `Deque`, `String`, and `IndexSet`.
+pub trait Bounded<T>: Default {
+    /// Adds `item` to the collection, or returns it if there's no room.
+    ///
+    /// # Errors
+    ///
+    /// Returns `item` if the collection is full.
+    fn try_push(&mut self, item: T) -> Result<(), T>;
+}
+
+#[cfg(feature = "heapless")]
+impl<T, const N: usize> Bounded<T> for heapless::Vec<T, N> {
+    fn try_push(&mut self, item: T) -> Result<(), T> {
+        self.push(item)
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<T, const N: usize> Bounded<T> for heapless::Deque<T, N> {
+    fn try_push(&mut self, item: T) -> Result<(), T> {
+        self.push_back(item)
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<const N: usize> Bounded<char> for heapless::String<N> {
+    fn try_push(&mut self, item: char) -> Result<(), char> {
+        self.push(item).map_err(|()| item)
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<T, S, const N: usize> Bounded<T> for heapless::IndexSet<T, S, N>
+where
+    T: Eq + Hash,
+    S: std::hash::BuildHasher + Default,
+{
+    fn try_push(&mut self, item: T) -> Result<(), T> {
+        self.insert(item).map(|_| ())
+    }
+}
+
+/// Collects the iterator into a fixed-capacity collection, stopping at the
+/// first item that doesn't fit. Nothing here allocates, although the
+/// comprehension itself still needs `std`.
+///
+/// This is what `rcomp![bounded <collect_ty>; ...]` expands to.
+///
+/// # Errors
+///
+/// Returns the first item that didn't fit. The rest of the iterator isn't
+/// consumed.
+///
+/// # Example
+///
+/// ```rust
+/// # #[cfg(feature = "heapless")] {
+/// # use rustcomp::rcomp;
+/// let small = rcomp![bounded heapless::Vec<_, 4>; for x in 0..10 => x * x, if x % 3 == 0];
+/// assert_eq!(small.as_deref(), Ok(&[0, 9, 36, 81][..]));
+/// let full = rcomp![bounded heapless::Vec<_, 2>; for x in 0..10 => x * x, if x % 3 == 0];
+/// assert_eq!(full, Err(36));
+/// # }
+/// ```
+pub fn collect_bounded<C, I>(iter: I) -> Result<C, I::Item>
+where
+    I: IntoIterator,
+    C: Bounded<I::Item>,
+{
+    let mut collection = C::default();
+    for item in iter {
+        collection.try_push(item)?;
+    }
+    Ok(collection)
+}
+
+/// Items that can be null in an Arrow array. `Option`s are null when
+/// they're `None`, and plain values never are. Requires the `arrow`
+/// feature.
+#[cfg(feature = "arrow")]
+pub trait Nullable {
+    /// The type of the value when it isn't null.
+    type Value;
+
+    /// Returns the value, or `None` if it's null.
+    fn into_option(self) -> Option<Self::Value>;
+}
+
+#[cfg(feature = "arrow")]
+impl<T> Nullable for Option<T> {
+    type Value = T;
+
+    fn into_option(self) -> Option<T> {
+        self
+    }
+}
+
+#[cfg(feature = "arrow")]
+macro_rules! impl_nullable {
+    ($($ty:ty),+) => {$(
+        impl Nullable for $ty {
+            type Value = $ty;
+
+            fn into_option(self) -> Option<$ty> {
+                Some(self)
+            }
+        }
+    )+};
+}
+
+#[cfg(feature = "arrow")]
+impl_nullable!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64, bool, String);
+
+#[cfg(feature = "arrow")]
+impl<'a> Nullable for &'a str {
+    type Value = &'a str;
+
+    fn into_option(self) -> Option<&'a str> {
+        Some(self)
+    }
+}
+
+/// Arrow arrays that [`collect_arrow`] can build. This is implemented for
+/// every `PrimitiveArray`, like `Int64Array` or `Float64Array`, and for
+/// `StringArray`, `LargeStringArray`, and `BooleanArray`. Requires the
+/// `arrow` feature.
+#[cfg(feature = "arrow")]
+pub trait ArrowArray<T>: Sized {
+    /// Builds the array, with a null for every `None`.
+    fn from_options<I: Iterator<Item = Option<T>>>(iter: I) -> Self;
+}
+
+#[cfg(feature = "arrow")]
+impl<P: arrow_array::ArrowPrimitiveType> ArrowArray<P::Native> for arrow_array::PrimitiveArray<P> {
+    fn from_options<I: Iterator<Item = Option<P::Native>>>(iter: I) -> Self {
+        let mut builder =
+            arrow_array::builder::PrimitiveBuilder::<P>::with_capacity(iter.size_hint().0);
+        builder.extend(iter);
+        builder.finish()
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl<O, S> ArrowArray<S> for arrow_array::GenericStringArray<O>
+where
+    O: arrow_array::OffsetSizeTrait,
+    S: AsRef<str>,
+{
+    fn from_options<I: Iterator<Item = Option<S>>>(iter: I) -> Self {
+        let mut builder = arrow_array::builder::GenericStringBuilder::<O>::new();
+        builder.extend(iter);
+        builder.finish()
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl ArrowArray<bool> for arrow_array::BooleanArray {
+    fn from_options<I: Iterator<Item = Option<bool>>>(iter: I) -> Self {
+        let mut builder = arrow_array::builder::BooleanBuilder::with_capacity(iter.size_hint().0);
+        builder.extend(iter);
+        builder.finish()
+    }
+}
+
+/// Builds an Arrow array from the items, which can be plain values or
+/// `Option`s, with `None` becoming a null. The array is built in one pass
+/// with Arrow's builders, without an intermediate `Vec`. Requires the
+/// `arrow` feature.
+///
+/// This is what `rcomp![arrow <array_ty>; ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use arrow_array::{Array, Int64Array, StringArray};
+///
+/// let ages = ["31", "", "27"];
+/// let parsed = rcomp![arrow Int64Array; for age in ages => age.parse().ok()];
+/// assert_eq!(parsed.null_count(), 1);
+/// assert_eq!(parsed.value(2), 27);
+/// let names = rcomp![arrow StringArray; for name in ["alice", "bob"] => name];
+/// assert_eq!(names.value(1), "bob");
+/// ```
+#[cfg(feature = "arrow")]
+pub fn collect_arrow<A, I>(iter: I) -> A
+where
+    I: IntoIterator,
+    I::Item: Nullable,
+    A: ArrowArray<<I::Item as Nullable>::Value>,
+{
+    A::from_options(iter.into_iter().map(Nullable::into_option))
+}
+
+/// Collects the iterator into a `Vec` and shuffles it with the given RNG.
+///
+/// This is what `rcomp![shuffled with rng; ...]` expands to. For the
+/// thread-local RNG, see [`shuffled`].
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rand::{rngs::StdRng, SeedableRng};
+///
+/// let mut rng = StdRng::seed_from_u64(42);
+/// let mut v = rcomp![shuffled with &mut rng; for x in 0..10 => x];
+/// v.sort_unstable();
+/// assert_eq!(v, (0..10).collect::<Vec<_>>());
+/// ```
+#[cfg(feature = "rand")]
+pub fn shuffled_with<I, R>(iter: I, rng: &mut R) -> Vec<I::Item>
+where
+    I: IntoIterator,
+    R: rand::Rng + ?Sized,
+{
+    use rand::seq::SliceRandom;
+
+    let mut v = iter.into_iter().collect::<Vec<_>>();
+    v.shuffle(rng);
+    v
+}
+
+/// Collects the iterator into a `Vec` and shuffles it with the thread-local
+/// RNG.
+///
+/// This is what `rcomp![shuffled; ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let mut v = rcomp![shuffled; for x in 0..10 => x * 2];
+/// v.sort_unstable();
+/// assert_eq!(v, (0..10).map(|x| x * 2).collect::<Vec<_>>());
+/// ```
+#[cfg(feature = "rand")]
+pub fn shuffled<I>(iter: I) -> Vec<I::Item>
+where
+    I: IntoIterator,
+{
+    shuffled_with(iter, &mut rand::rng())
+}
+
+/// A [HyperLogLog](https://en.wikipedia.org/wiki/HyperLogLog) sketch,
+/// which estimates how many distinct items it's been given in a fixed
+/// amount of memory, no matter how many there are. Requires the `hll`
+/// feature.
+///
+/// A sketch with precision `p` has `2^p` one-byte registers and a typical
+/// error of about `1.04 / sqrt(2^p)`, so the default precision of 14 uses
+/// 16 KiB and is usually within a few percent of the real count. Sketches with the
+/// same precision can be merged, e.g. to combine the counts of several
+/// partitions of a stream.
+#[cfg(feature = "hll")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    precision: u32,
+    registers: Vec<u8>,
+}
+
+#[cfg(feature = "hll")]
+impl HyperLogLog {
+    /// The precision used by [`HyperLogLog::default`] and the
+    /// `approx_count_distinct` terminal.
+    pub const DEFAULT_PRECISION: u32 = 14;
+
+    /// Creates an empty sketch with `2^precision` registers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `precision` isn't between 4 and 18, inclusive.
+    #[must_use]
+    pub fn new(precision: u32) -> Self {
+        assert!(
+            (4..=18).contains(&precision),
+            "a HyperLogLog's precision has to be between 4 and 18, not {precision}"
+        );
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    /// Adds an item to the sketch. Adding the same item again doesn't
+    /// change the estimate.
+    pub fn insert<T: Hash + ?Sized>(&mut self, item: &T) {
+        use std::hash::{BuildHasher, BuildHasherDefault, DefaultHasher};
+
+        // the hasher has to be the same across sketches for them to merge
+        let hash = BuildHasherDefault::<DefaultHasher>::default().hash_one(item);
+        let index = usize::try_from(hash >> (64 - self.precision)).unwrap_or_default();
+        // the position of the first set bit after the index bits. the
+        // sentinel bit caps it for hashes that are all zeros.
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = u8::try_from(rest.leading_zeros() + 1).unwrap_or(u8::MAX);
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    /// Merges another sketch into this one, so it estimates the distinct
+    /// items given to either of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the sketches have different precisions.
+    pub fn merge(&mut self, other: &Self) {
+        assert_eq!(
+            self.precision, other.precision,
+            "can't merge HyperLogLogs with different precisions"
+        );
+        for (mine, theirs) in self.registers.iter_mut().zip(&other.registers) {
+            *mine = (*mine).max(*theirs);
+        }
+    }
+
+    /// Estimates the number of distinct items given to the sketch.
+    #[must_use]
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        // the approximation only holds from 128 registers up, so the
+        // smaller sketches use the exact constants
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let mut sum = 0.0;
+        let mut zeros = 0;
+        for &r in &self.registers {
+            sum += (-f64::from(r)).exp2();
+            zeros += usize::from(r == 0);
+        }
+        let raw = alpha * m * m / sum;
+        // linear counting is more accurate while most registers are empty
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+}
+
+#[cfg(feature = "hll")]
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_PRECISION)
+    }
+}
+
+#[cfg(feature = "hll")]
+impl<T: Hash> Extend<T> for HyperLogLog {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for item in iter {
+            self.insert(&item);
+        }
+    }
+}
+
+#[cfg(feature = "hll")]
+impl<T: Hash> FromIterator<T> for HyperLogLog {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut hll = Self::default();
+        hll.extend(iter);
+        hll
+    }
+}
+
+/// Adds the items to a sketch that's kept elsewhere and returns the new
+/// estimate, e.g. `rcomp![end &mut sketch; ...]`.
+#[cfg(feature = "hll")]
+impl<T: Hash> CompTerminal<T> for &mut HyperLogLog {
+    type Output = u64;
+
+    fn finish<I: Iterator<Item = T>>(self, iter: I) -> u64 {
+        self.extend(iter);
+        self.estimate()
+    }
+}
+
+/// Estimates the number of distinct items with a [`HyperLogLog`] sketch,
+/// without keeping the items around like a `HashSet` would. Requires the
+/// `hll` feature.
+///
+/// This is what `rcomp![approx_count_distinct; ...]` expands to. To keep
+/// the sketch, e.g. to merge it with others later, collect into a
+/// `HyperLogLog` instead.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let events = (0..100_000).map(|i| (i % 2_500, i));
+/// let users = rcomp![approx_count_distinct; for (user_id, _) in events => user_id];
+/// assert!(users.abs_diff(2_500) < 2_500 / 50);
+/// ```
+#[cfg(feature = "hll")]
+pub fn approx_count_distinct<I>(iter: I) -> u64
+where
+    I: IntoIterator,
+    I::Item: Hash,
+{
+    iter.into_iter().collect::<HyperLogLog>().estimate()
+}
+
+/// The result of the [`minmax`] terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MinMax<T> {
+    /// The iterator was empty.
+    NoElements,
+    /// The iterator had exactly one element, so it's both the minimum and
+    /// the maximum.
+    OneElement(T),
+    /// The minimum and maximum, in that order.
+    MinMax(T, T),
+}
+
+impl<T: Clone> MinMax<T> {
+    /// Converts this into an `Option` of `(min, max)`, cloning the element
+    /// if there was only one.
+    pub fn into_option(self) -> Option<(T, T)> {
+        match self {
+            Self::NoElements => None,
+            Self::OneElement(x) => Some((x.clone(), x)),
+            Self::MinMax(min, max) => Some((min, max)),
+        }
+    }
+}
+
+/// Finds the minimum and maximum of the iterator in a single pass.
+///
+/// Like [`Iterator::min`] and [`Iterator::max`], the _first_ minimum and
+/// the _last_ maximum are returned if there are several equal ones.
+///
+/// This is what `rcomp![minmax; ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::terminals::MinMax;
+///
+/// let v = vec![3, 1, 4, 1, 5, 9, 2, 6];
+/// assert_eq!(rcomp![minmax; for x in &v => x * 10], MinMax::MinMax(10, 90));
+/// assert_eq!(rcomp![minmax; for x in &v => x, if *x > 8], MinMax::OneElement(&9));
+/// assert_eq!(rcomp![minmax; for x in &v => x, if *x > 9], MinMax::NoElements);
+/// ```
+pub fn minmax<I>(iter: I) -> MinMax<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Ord,
+{
+    minmax_by(iter, Ord::cmp)
+}
+
+/// Like [`minmax`], but compares the items with `compare`. This is handy
+/// for types that aren't `Ord`, like floats.
+///
+/// This is what `rcomp![fminmax; ...]` expands to, which skips NaNs and
+/// compares the rest with `total_cmp`, like C's `fmin` and `fmax`. Since
+/// `total_cmp` puts `-0.0` before `0.0`, that's also how they're treated
+/// here.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use rustcomp::terminals::MinMax;
+///
+/// let readings = [2.5, f64::NAN, -1.0, 7.25];
+/// assert_eq!(rcomp![fminmax; for x in readings => x], MinMax::MinMax(-1.0, 7.25));
+/// assert_eq!(rcomp![fminmax; for x in [f32::NAN] => x], MinMax::NoElements);
+/// ```
+pub fn minmax_by<I, F>(iter: I, mut compare: F) -> MinMax<I::Item>
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item, &I::Item) -> std::cmp::Ordering,
+{
+    use std::cmp::Ordering;
+
+    let mut iter = iter.into_iter();
+    let Some(first) = iter.next() else {
+        return MinMax::NoElements;
+    };
+    let Some(second) = iter.next() else {
+        return MinMax::OneElement(first);
+    };
+    let (min, max) = if compare(&second, &first) == Ordering::Less {
+        (second, first)
+    } else {
+        (first, second)
+    };
+    let (min, max) = iter.fold((min, max), |(min, max), x| {
+        if compare(&x, &min) == Ordering::Less {
+            (x, max)
+        } else if compare(&x, &max) != Ordering::Less {
+            (min, x)
+        } else {
+            (min, max)
+        }
+    });
+    MinMax::MinMax(min, max)
+}
+
+/// Collects the iterator into a `Vec`, sorts it, and removes the
+/// duplicates, leaving every distinct item once, in ascending order.
+///
+/// This is what `rcomp![sorted unique; ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let tags = [vec!["b", "a"], vec!["c", "a", "b"]];
+/// let v = rcomp![sorted unique; for row in &tags, tag in row => *tag];
+/// assert_eq!(v, ["a", "b", "c"]);
+/// ```
+pub fn sorted_unique<I>(iter: I) -> Vec<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Ord,
+{
+    let mut v = iter.into_iter().collect::<Vec<_>>();
+    // equal items are removed anyway, so their order doesn't matter
+    v.sort_unstable();
+    v.dedup();
+    v
+}
+
+/// Collects the running totals of the iterator into a `Vec`, so the `i`th
+/// element is the sum of the first `i + 1` items.
+///
+/// This is what `rcomp![cumsum; ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let weights = [("a", 2), ("b", 5), ("c", 1)];
+/// let v = rcomp![cumsum; for (_, w) in weights => w];
+/// assert_eq!(v, vec![2, 7, 8]);
+/// ```
+pub fn cumsum<I>(iter: I) -> Vec<I::Item>
+where
+    I: IntoIterator,
+    I::Item: Copy + std::ops::Add<Output = I::Item>,
+{
+    let iter = iter.into_iter();
+    let mut totals = Vec::with_capacity(iter.size_hint().0);
+    for item in iter {
+        let total = match totals.last() {
+            Some(&last) => last + item,
+            None => item,
+        };
+        totals.push(total);
+    }
+    totals
+}
+
+/// Counts how many items fall into each bucket, where `bucket` gives the
+/// bucket of an item. The buckets are kept in a `BTreeMap`, so they come
+/// out in order and empty buckets are left out.
+///
+/// This is what `rcomp![histogram by <bucket>; ...]` expands to, and
+/// `rcomp![histogram; ...]` counts each distinct item instead.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use std::collections::BTreeMap;
+///
+/// let latencies = [12, 45, 17, 3, 48, 41, 19];
+/// let h = rcomp![histogram by |ms| ms / 10 * 10; for ms in latencies => ms];
+/// assert_eq!(h, BTreeMap::from([(0, 1), (10, 3), (40, 3)]));
+/// ```
+pub fn histogram_by<I, F, K>(iter: I, mut bucket: F) -> BTreeMap<K, usize>
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item) -> K,
+    K: Ord,
+{
+    let mut counts = BTreeMap::new();
+    for item in iter {
+        *counts.entry(bucket(&item)).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Counts how many times each distinct item appears and returns them sorted
+/// by how often they appeared, most common first. Items that appeared
+/// equally often stay in the order they were first seen.
+///
+/// This is what `rcomp![most_common; ...]` expands to. With a number, like
+/// `rcomp![most_common 10; ...]`, only the first `n` are kept.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let text = "the cat and the dog and the bird";
+/// let top = rcomp![most_common 2; for w in text.split(' ') => w];
+/// assert_eq!(top, vec![("the", 3), ("and", 2)]);
+/// ```
+pub fn most_common<I>(iter: I) -> Vec<(I::Item, usize)>
+where
+    I: IntoIterator,
+    I::Item: Hash + Eq,
+{
+    // each item keeps the position it was first seen at to break ties
+    let mut counts = HashMap::<_, (usize, usize)>::new();
+    for (i, item) in iter.into_iter().enumerate() {
+        counts.entry(item).or_insert((i, 0)).1 += 1;
+    }
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_unstable_by(|(_, (a_first, a_count)), (_, (b_first, b_count))| {
+        b_count.cmp(a_count).then(a_first.cmp(b_first))
+    });
+    counts
+        .into_iter()
+        .map(|(item, (_, count))| (item, count))
+        .collect()
+}
+
+/// Splits an iterator of pairs of `Option`s into two collections, in a
+/// single pass. Each half that's `Some` is added to its collection, and
+/// each `None` is skipped, so the two collections can be mapped and
+/// filtered separately.
+///
+/// As with [`group_by`], the `FromIterator` bounds only tie each
+/// collection's item type to its half's, so that a `Vec<_>` can be inferred.
+///
+/// This is what `rcomp![tee (<collect_ty>, <collect_ty>); ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use std::collections::HashSet;
+///
+/// let (evens, digits) = rcomp![tee (Vec<_>, HashSet<_>);
+///     for x in [12, 7, 40, 3] => ((x % 2 == 0).then_some(x), Some(x % 10))
+/// ];
+/// assert_eq!(evens, [12, 40]);
+/// assert_eq!(digits, HashSet::from([2, 7, 0, 3]));
+/// ```
+pub fn tee<I, L, R, A, B>(iter: I) -> (A, B)
+where
+    I: IntoIterator<Item = (Option<L>, Option<R>)>,
+    A: Default + Extend<L> + FromIterator<L>,
+    B: Default + Extend<R> + FromIterator<R>,
+{
+    let mut left = A::default();
+    let mut right = B::default();
+    for (l, r) in iter {
+        left.extend(l);
+        right.extend(r);
+    }
+    (left, right)
+}
+
+/// Splits an iterator of [`Either`](either::Either)s into two collections,
+/// one for the `Left` values and one for the `Right` values, in a single
+/// pass. Requires the `either` feature.
+///
+/// This is what `rcomp![partition (<collect_ty>, <collect_ty>); ...]`
+/// expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use either::Either::{Left, Right};
+/// use std::collections::HashSet;
+///
+/// let (numbers, words) = rcomp![partition (Vec<_>, HashSet<_>);
+///     for s in ["1", "a", "2", "a"] => s.parse::<u32>().map_or(Right(s), Left)
+/// ];
+/// assert_eq!(numbers, [1, 2]);
+/// assert_eq!(words, HashSet::from(["a"]));
+/// ```
+#[cfg(feature = "either")]
+pub fn partition_either<I, L, R, A, B>(iter: I) -> (A, B)
+where
+    I: IntoIterator<Item = either::Either<L, R>>,
+    A: Default + Extend<L>,
+    B: Default + Extend<R>,
+{
+    let mut left = A::default();
+    let mut right = B::default();
+    for item in iter {
+        match item {
+            either::Either::Left(l) => left.extend(std::iter::once(l)),
+            either::Either::Right(r) => right.extend(std::iter::once(r)),
+        }
+    }
+    (left, right)
+}
+
+/// Runs the whole iterator of `Result`s and returns every `Ok` value if
+/// there were no errors, or _every_ error otherwise. Unlike collecting into
+/// a `Result<Vec<_>, _>`, this doesn't stop at the first error, which is
+/// what form-validation style code wants.
+///
+/// This is what `rcomp![validate; ...]` expands to.
+///
+/// # Errors
+///
+/// Returns all of the errors, in order, if there were any.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let check = |field: &str| if field.is_empty() { Err("missing field") } else { Ok(field.len()) };
+/// assert_eq!(rcomp![validate; for f in ["ab", "c"] => check(f)], Ok(vec![2, 1]));
+/// assert_eq!(rcomp![validate; for f in ["", "c", ""] => check(f)].unwrap_err().len(), 2);
+/// ```
+pub fn validate<I, T, E>(iter: I) -> Result<Vec<T>, Vec<E>>
+where
+    I: IntoIterator<Item = Result<T, E>>,
+{
+    let mut values = Vec::new();
+    let mut errors = Vec::new();
+    for item in iter {
+        match item {
+            Ok(value) if errors.is_empty() => values.push(value),
+            // the values won't be returned anymore, so don't keep them
+            Ok(_) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+    if errors.is_empty() {
+        Ok(values)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Maps that [`group_by`] can group into. This is implemented for
+/// `HashMap`, `BTreeMap`, and, with the `indexmap` feature, `IndexMap`.
+pub trait GroupMap<K, V> {
+    /// Returns the group for `key`, inserting an empty one if there isn't
+    /// one yet.
+    fn group(&mut self, key: K) -> &mut V;
+}
+
+impl<K: Hash + Eq, V: Default, S: std::hash::BuildHasher> GroupMap<K, V> for HashMap<K, V, S> {
+    fn group(&mut self, key: K) -> &mut V {
+        self.entry(key).or_default()
+    }
+}
+
+impl<K: Ord, V: Default> GroupMap<K, V> for BTreeMap<K, V> {
+    fn group(&mut self, key: K) -> &mut V {
+        self.entry(key).or_default()
+    }
+}
+
+#[cfg(feature = "indexmap")]
+impl<K: Hash + Eq, V: Default, S: std::hash::BuildHasher> GroupMap<K, V>
+    for indexmap::IndexMap<K, V, S>
+{
+    fn group(&mut self, key: K) -> &mut V {
+        self.entry(key).or_default()
+    }
+}
+
+/// Groups the items by the key returned by `key_fn`, which is given a
+/// reference to the item. Each group is a collection of its items, in the
+/// order they came in.
+///
+/// The `FromIterator` bound isn't used, but it ties the group's item type
+/// to the iterator's. Without it, a `Vec<_>` of references couldn't be
+/// inferred, since a `Vec<T>` can also be extended with `&T`.
+///
+/// This is what `rcomp![group by <key_fn>; ...]` expands to, with `M` being
+/// a `HashMap<_, Vec<_>>`. Any other [`GroupMap`] and group collection can
+/// be picked with `rcomp![group (<map_ty>) by <key_fn>; ...]`, e.g. a
+/// `BTreeMap` for sorted keys, or an `IndexMap` (with the `indexmap`
+/// feature) to keep the keys in the order they were first seen.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use std::collections::{BTreeMap, BTreeSet};
+///
+/// let words = ["bee", "ant", "cat", "aphid", "bat", "ant"];
+/// let by_letter = rcomp![group (BTreeMap<_, BTreeSet<_>>) by |w| w.chars().next(); for w in words => w];
+/// assert_eq!(
+///     by_letter.into_iter().collect::<Vec<_>>(),
+///     [
+///         (Some('a'), BTreeSet::from(["ant", "aphid"])),
+///         (Some('b'), BTreeSet::from(["bat", "bee"])),
+///         (Some('c'), BTreeSet::from(["cat"])),
+///     ]
+/// );
+/// ```
+pub fn group_by<M, I, F, K, V>(iter: I, mut key_fn: F) -> M
+where
+    I: IntoIterator,
+    F: FnMut(&I::Item) -> K,
+    M: Default + GroupMap<K, V>,
+    V: Extend<I::Item> + FromIterator<I::Item>,
+{
+    let mut groups = M::default();
+    for item in iter {
+        groups.group(key_fn(&item)).extend(std::iter::once(item));
+    }
+    groups
+}
+
+/// Maps that values can be folded into by key, for [`reduce_by_key`]. This
+/// is implemented for `HashMap` and `BTreeMap`.
+pub trait ReduceMap<K, V> {
+    /// Inserts `value` under `key`, or replaces the value already there with
+    /// `f(old, value)`.
+    fn reduce(&mut self, key: K, value: V, f: impl FnOnce(V, V) -> V);
+}
+
+impl<K: Hash + Eq, V, S: std::hash::BuildHasher> ReduceMap<K, V> for HashMap<K, V, S> {
+    fn reduce(&mut self, key: K, value: V, f: impl FnOnce(V, V) -> V) {
+        let value = match self.remove(&key) {
+            Some(old) => f(old, value),
+            None => value,
+        };
+        self.insert(key, value);
+    }
+}
+
+impl<K: Ord, V> ReduceMap<K, V> for BTreeMap<K, V> {
+    fn reduce(&mut self, key: K, value: V, f: impl FnOnce(V, V) -> V) {
+        let value = match self.remove(&key) {
+            Some(old) => f(old, value),
+            None => value,
+        };
+        self.insert(key, value);
+    }
+}
+
+/// Folds the values of `(key, value)` pairs per key with `f`, in one pass.
+/// The first value for a key is kept as is, and each later one is combined
+/// with the result so far as `f(so_far, value)`. Counting and summing by
+/// key are both special cases of this.
+///
+/// This is what `rcomp![reduce with <f>; ...]` expands to, with `M` being a
+/// `HashMap<_, _>`. Another [`ReduceMap`] can be picked with
+/// `rcomp![reduce (<map_ty>) with <f>; ...]`.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// use std::collections::BTreeMap;
+///
+/// let sales = [("north", 10), ("south", 4), ("north", 7)];
+/// let totals = rcomp![reduce (BTreeMap<_, _>) with |a, b| a + b; for (region, amount) in sales => (region, amount)];
+/// assert_eq!(totals, BTreeMap::from([("north", 17), ("south", 4)]));
+/// ```
+pub fn reduce_by_key<M, I, K, V, F>(iter: I, mut f: F) -> M
+where
+    I: IntoIterator<Item = (K, V)>,
+    M: Default + ReduceMap<K, V>,
+    F: FnMut(V, V) -> V,
+{
+    let mut map = M::default();
+    for (key, value) in iter {
+        map.reduce(key, value, &mut f);
+    }
+    map
+}
+
+/// Integers that can be added without overflowing silently, for
+/// [`try_sum`]. This is implemented for all of the primitive integers.
+pub trait CheckedAdd: Sized {
+    /// The sum of no items.
+    const ZERO: Self;
+
+    /// Adds `rhs`, returning `None` if the result overflows.
+    fn checked_add(self, rhs: Self) -> Option<Self>;
+}
+
+macro_rules! impl_checked_add {
+    ($($t:ty),+) => {$(
+        impl CheckedAdd for $t {
+            const ZERO: Self = 0;
+
+            fn checked_add(self, rhs: Self) -> Option<Self> {
+                <$t>::checked_add(self, rhs)
+            }
+        }
+    )+};
+}
+
+impl_checked_add!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+/// Sums the iterator, returning `None` as soon as the sum overflows instead
+/// of wrapping around in release builds (or panicking in debug builds).
+/// The sum of no items is `Some(0)`.
+///
+/// This is what `rcomp![try_sum; ...]` expands to.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// assert_eq!(rcomp![try_sum; for x in [100u8, 100, 50] => x], Some(250));
+/// assert_eq!(rcomp![try_sum; for x in [100u8, 100, 100] => x], None);
+/// ```
+pub fn try_sum<I>(iter: I) -> Option<I::Item>
+where
+    I: IntoIterator,
+    I::Item: CheckedAdd,
+{
+    iter.into_iter()
+        .try_fold(CheckedAdd::ZERO, CheckedAdd::checked_add)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MinMax;
+
+    #[test]
+    fn test_minmax_ties() {
+        // first minimum, last maximum, same as `Iterator::{min, max}`
+        let v = vec![2, 1, 1, 3, 3];
+        let MinMax::MinMax(min, max) = super::minmax(&v) else {
+            panic!("expected two elements");
+        };
+        let (first_min, last_max) = (&v[1], &v[4]);
+        assert!(std::ptr::eq(min, first_min));
+        assert!(std::ptr::eq(max, last_max));
+    }
+
+    #[test]
+    fn test_fminmax_skips_nans() {
+        use crate::rcomp;
+
+        let v = [f32::NAN, 0.0, -0.0, f32::INFINITY, f32::NAN];
+        let MinMax::MinMax(min, max) = rcomp![fminmax; for x in v => x] else {
+            panic!("expected two elements");
+        };
+        assert!(min.is_sign_negative() && min == 0.0);
+        assert!(max.is_infinite());
+        assert_eq!(
+            rcomp![fminmax; for x in [f64::NAN, 1.5] => x],
+            MinMax::OneElement(1.5)
+        );
+    }
+
+    #[test]
+    fn test_minmax_into_option() {
+        assert_eq!(super::minmax(Vec::<i32>::new()).into_option(), None);
+        assert_eq!(super::minmax([7]).into_option(), Some((7, 7)));
+        assert_eq!(super::minmax([7, 2, 9]).into_option(), Some((2, 9)));
+    }
+
+    #[test]
+    fn test_tee_single_pass() {
+        use crate::rcomp;
+        use std::cell::Cell;
+        use std::collections::HashSet;
+
+        let pulls = Cell::new(0);
+        let source = (0..6).inspect(|_| pulls.set(pulls.get() + 1));
+        let (squares, parities) = rcomp![tee (Vec<_>, HashSet<_>,);
+            for x in source => ((x > 1).then(|| x * x), Some(x % 2)), if x != 3
+        ];
+        assert_eq!(squares, vec![4, 16, 25]);
+        assert_eq!(parities, HashSet::from([0, 1]));
+        assert_eq!(pulls.get(), 6);
+
+        let (names, none) = rcomp![tee (Vec<_>, Vec<u8>); for s in ["a", "b"] => (Some(s), None)];
+        assert_eq!(names, ["a", "b"]);
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn test_demux_routes_to_first_matching_arm() {
+        use crate::rcomp;
+
+        let (fizz, buzz, rest) = rcomp![demux {
+            x if x % 3 == 0 => fizz: Vec<_> = x,
+            x if x % 5 == 0 => buzz: Vec<_> = x,
+            x => rest: String = char::from(b'a' + x),
+        }; for x in 1u8..=10 => x];
+        assert_eq!(fizz, vec![3, 6, 9]);
+        assert_eq!(buzz, vec![5, 10]);
+        assert_eq!(rest, "bcehi");
+        let (only,) = rcomp![demux { x => only: Vec<i32> = x }; for x in 0..0 => x];
+        assert!(only.is_empty());
+    }
+
+    #[test]
+    fn test_try_sum_stops_at_overflow() {
+        use crate::rcomp;
+        use std::cell::Cell;
+
+        let pulls = Cell::new(0);
+        let source = [i32::MAX, 1, 5, 6]
+            .into_iter()
+            .inspect(|_| pulls.set(pulls.get() + 1));
+        assert_eq!(rcomp![try_sum; for x in source => x], None);
+        assert_eq!(pulls.get(), 2);
+        assert_eq!(
+            rcomp![try_sum; for x in [i8::MIN, 1, -1] => x],
+            Some(i8::MIN)
+        );
+        assert_eq!(rcomp![try_sum; for x in 0..0u64 => x], Some(0));
+    }
+
+    #[test]
+    fn test_try_fold_stops_at_first_error() {
+        use crate::rcomp;
+        use std::cell::Cell;
+
+        let seen = Cell::new(0);
+        let sum = rcomp![try_fold acc = 0u8, |acc, x| acc.checked_add(x).ok_or(x);
+            for x in [50, 100, 120, 1] => { seen.set(seen.get() + 1); x }
+        ];
+        assert_eq!(sum, Err(120));
+        assert_eq!(seen.get(), 3);
+        let sum = rcomp![try_fold 0u8, u8::checked_add; for x in 0..10 => x];
+        assert_eq!(sum, Some(45));
+        let start = 1;
+        let product = rcomp![try_fold start, |acc: i32, x| acc.checked_mul(x); for x in 1..=5 => x];
+        assert_eq!(product, Some(120));
+    }
+
+    #[test]
+    fn test_custom_terminal() {
+        use super::CompTerminal;
+        use crate::rcomp;
+
+        struct Last;
+
+        impl<T> CompTerminal<T> for Last {
+            type Output = Option<T>;
+
+            fn finish<I: Iterator<Item = T>>(self, iter: I) -> Option<T> {
+                iter.last()
+            }
+        }
+
+        let v = vec![vec![1, 2], vec![3]];
+        assert_eq!(
+            rcomp![end Last; for row in &v, x in row => *x, if x % 2 == 0],
+            Some(2)
+        );
+        assert_eq!(rcomp![end Last; for x in 0..0 => x], None);
+    }
+
+    #[test]
+    fn test_validate_collects_every_error() {
+        use crate::rcomp;
+
+        let v = rcomp![validate; for s in ["1", "a", "2", "b"] => s.parse::<u8>().map_err(|_| s)];
+        assert_eq!(v, Err(vec!["a", "b"]));
+        let v = rcomp![validate; for s in ["1", "2"] => s.parse::<u8>().map_err(|_| s)];
+        assert_eq!(v, Ok(vec![1, 2]));
+        let v = rcomp![validate; for s in [""; 0] => s.parse::<u8>()];
+        assert_eq!(v, Ok(vec![]));
+    }
+
+    #[test]
+    fn test_group_by_map_types() {
+        use crate::rcomp;
+        use std::collections::{BTreeMap, HashMap};
+
+        let v = [3, 14, 15, 92, 65, 35];
+        let groups = rcomp![group by |x| x % 2; for x in v => x];
+        assert_eq!(
+            groups,
+            HashMap::from([(0, vec![14, 92]), (1, vec![3, 15, 65, 35])])
+        );
+        // groups of references into the default `Vec<_>`
+        let groups = rcomp![group by |x| *x % 2; for x in &v => x];
+        assert_eq!(groups[&0], [&14, &92]);
+        assert_eq!(groups[&1], [&3, &15, &65, &35]);
+        let groups = rcomp![group (BTreeMap<_, String>) by |c: &char| c.is_uppercase(); for c in "aBcD".chars() => c];
+        assert_eq!(
+            groups.into_iter().collect::<Vec<_>>(),
+            [(false, "ac".to_owned()), (true, "BD".to_owned())]
+        );
+
+        #[cfg(feature = "indexmap")]
+        {
+            let groups =
+                rcomp![group (indexmap::IndexMap<_, Vec<_>>) by |x| x % 10; for x in v => x];
+            assert_eq!(groups.keys().copied().collect::<Vec<_>>(), [3, 4, 5, 2]);
+            assert_eq!(groups[&5], [15, 65, 35]);
+        }
+    }
+
+    #[test]
+    fn test_unzip_tuples() {
+        use crate::rcomp;
+        use std::collections::BTreeSet;
+
+        let (codes, chars) = rcomp![(Vec<_>, String); for c in "abc".chars() => (c as u32, c)];
+        assert_eq!((codes, chars.as_str()), (vec![97, 98, 99], "abc"));
+        let (nums, squares, parities, dashes) = rcomp![(Vec<_>, Vec<_>, BTreeSet<_>, String,); for i in 0..4 => (i, i * i, i % 2, '-'), if i != 2];
+        assert_eq!(nums, [0, 1, 3]);
+        assert_eq!(squares, [0, 1, 9]);
+        assert_eq!(parities, BTreeSet::from([0, 1]));
+        assert_eq!(dashes, "---");
+    }
+
+    #[test]
+    fn test_collect_as() {
+        use crate::rcomp;
+
+        let boxed = rcomp![as Box<[_]>; for x in 0..3 => x * 2];
+        assert_eq!(&*boxed, [0, 2, 4]);
+        let pair: Result<[i32; 2], _> = rcomp![try as [_; 2]; for x in 0..3 => x];
+        assert_eq!(pair, Err(vec![0, 1, 2]));
+        let pair: Result<[i32; 2], _> = rcomp![try as [_; 2]; for x in 0..3 => x, if x > 0];
+        assert_eq!(pair, Ok([1, 2]));
+    }
+
+    #[test]
+    fn test_reduce_by_key() {
+        use crate::rcomp;
+        use std::collections::BTreeMap;
+
+        let words = "the cat and the hat and the bat".split(' ');
+        let counts = rcomp![reduce with |a, b| a + b; for w in words => (w, 1)];
+        assert_eq!(counts.len(), 5);
+        assert_eq!(counts["the"], 3);
+        assert_eq!(counts["and"], 2);
+        let longest = rcomp![reduce (BTreeMap<_, _>) with |a: &str, b: &str| if b.len() > a.len() { b } else { a };
+            for w in ["ant", "bee", "aphid", "bat", "beetle"] => (&w[..1], w)
+        ];
+        assert_eq!(longest, BTreeMap::from([("a", "aphid"), ("b", "beetle")]));
+    }
+
+    #[test]
+    fn test_collect_into_appends() {
+        use crate::rcomp;
+        use std::collections::BTreeSet;
+
+        let mut set = BTreeSet::from([0]);
+        let len = rcomp![into &mut set; for x in 0..6 => x / 2].len();
+        assert_eq!(len, 3);
+        let mut s = String::from("ab");
+        rcomp![into &mut s; for c in 'c'..='e' => c].push('!');
+        assert_eq!(s, "abcde!");
+    }
+
+    #[cfg(feature = "bumpalo")]
+    #[test]
+    fn test_collect_in_arena() {
+        use crate::rcomp;
+
+        let arena = bumpalo::Bump::new();
+        let words =
+            rcomp![in &arena, Vec<&str>; for w in "a bb ccc".split(' ') => w, if w.len() > 1];
+        assert_eq!(words.as_slice(), ["bb", "ccc"]);
+        let empty = rcomp![in &arena, Vec<_>; for x in 0..0 => x];
+        assert!(empty.is_empty());
+        assert!(arena.allocated_bytes() > 0);
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn test_collect_bounded() {
+        use crate::rcomp;
+
+        let s = rcomp![bounded heapless::String<3>; for c in "a-b-c".chars() => c, if c != '-'];
+        assert_eq!(s.as_deref(), Ok("abc"));
+        let s =
+            rcomp![bounded heapless::String<2>; for c in "abc".chars() => c.to_ascii_uppercase()];
+        assert_eq!(s, Err('C'));
+        let set = rcomp![bounded heapless::FnvIndexSet<_, 4>; for x in [3, 1, 3, 3, 2, 1] => x];
+        assert_eq!(
+            set.map(|s| s.into_iter().copied().collect::<Vec<_>>()),
+            Ok(vec![3, 1, 2])
+        );
+        let deque = rcomp![bounded heapless::Deque<_, 8>; for x in 0..0 => x];
+        assert!(deque.is_ok_and(|d| d.is_empty()));
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_collect_arrow() {
+        use crate::rcomp;
+        use arrow_array::{Array, BooleanArray, Float64Array, LargeStringArray};
+
+        let halves = rcomp![arrow Float64Array; for x in 0..4 => f64::from(x) / 2.0, if x != 2];
+        assert_eq!(halves.values(), &[0.0, 0.5, 1.5]);
+        let words = rcomp![arrow LargeStringArray; for w in ["a", "", "c"] => (!w.is_empty()).then(|| w.repeat(2))];
+        assert_eq!(words.len(), 3);
+        assert!(words.is_null(1));
+        assert_eq!(words.value(2), "cc");
+        let flags = rcomp![arrow BooleanArray; for x in 0..0 => x > 0];
+        assert!(flags.is_empty());
+    }
+
+    #[test]
+    fn test_cumsum() {
+        use crate::rcomp;
+
+        let v = rcomp![cumsum; for x in [1.5, 2.0, -0.5] => x * 2.0];
+        assert_eq!(v, vec![3.0, 7.0, 6.0]);
+        let v = rcomp![cumsum; for row in [vec![1, 2], vec![], vec![3]], x in row => x];
+        assert_eq!(v, vec![1, 3, 6]);
+        assert!(super::cumsum(Vec::<u8>::new()).is_empty());
+    }
+
+    #[test]
+    fn test_sorted_unique() {
+        use crate::rcomp;
+
+        let v = rcomp![sorted unique; for x in [5, 1, 5, 3, 1, 1] => x * 2, if x != 3];
+        assert_eq!(v, [2, 10]);
+        let empty = rcomp![sorted unique; for x in 0..0 => x];
+        assert!(empty.is_empty());
+    }
+
+    #[cfg(feature = "hll")]
+    #[test]
+    fn test_approx_count_distinct() {
+        use super::HyperLogLog;
+        use crate::rcomp;
+
+        assert_eq!(rcomp![approx_count_distinct; for x in 0..0 => x], 0);
+        // small counts are exact in practice, thanks to linear counting
+        assert_eq!(
+            rcomp![approx_count_distinct; for x in 0..1000 => x % 10],
+            10
+        );
+        for n in [1_000u64, 50_000, 500_000] {
+            let estimate = rcomp![approx_count_distinct; for x in 0..n => x];
+            assert!(estimate.abs_diff(n) < n / 40, "{estimate} for {n}");
+        }
+        let mut evens = rcomp![HyperLogLog; for x in 0..20_000 => x * 2];
+        let odds = rcomp![HyperLogLog; for x in 0..20_000 => x * 2 + 1];
+        evens.merge(&odds);
+        assert_eq!(
+            rcomp![end &mut evens; for x in 0..10 => x * 2],
+            evens.estimate()
+        );
+        // 40k is right where the estimator switches methods, which is where
+        // it's least accurate
+        assert!(evens.estimate().abs_diff(40_000) < 40_000 / 20);
+    }
+
+    #[cfg(feature = "hll")]
+    #[test]
+    fn test_hll_small_precision() {
+        use super::HyperLogLog;
+
+        // one sketch of 16 registers is only good to about 26%, so this
+        // averages 32 of them over disjoint items
+        let n = 2_000u64;
+        let total = (0..32u64)
+            .map(|round| {
+                let mut hll = HyperLogLog::new(4);
+                hll.extend((0..n).map(|x| round * n + x));
+                hll.estimate()
+            })
+            .sum::<u64>();
+        let mean = total / 32;
+        assert!(mean.abs_diff(n) < n / 10, "{mean} for {n}");
+        // linear counting still takes over for the first few items
+        let mut hll = HyperLogLog::new(4);
+        hll.extend(0..5);
+        assert!(hll.estimate().abs_diff(5) <= 1, "{}", hll.estimate());
+    }
+
+    #[cfg(feature = "hll")]
+    #[test]
+    #[should_panic(expected = "different precisions")]
+    fn test_hll_merge_precision_mismatch() {
+        super::HyperLogLog::new(10).merge(&super::HyperLogLog::new(12));
+    }
+
+    #[test]
+    fn test_histogram() {
+        use crate::rcomp;
+        use std::collections::BTreeMap;
+
+        let words = ["a", "bb", "cc", "a", "ddd"];
+        let h = rcomp![histogram; for w in words => w];
+        assert_eq!(
+            h,
+            BTreeMap::from([("a", 2), ("bb", 1), ("cc", 1), ("ddd", 1)])
+        );
+        let h = rcomp![histogram by |w| w.len(); for w in words => w, if w != "a"];
+        assert_eq!(h, BTreeMap::from([(2, 2), (3, 1)]));
+        assert!(rcomp![histogram; for x in 0..0 => x].is_empty());
+    }
+
+    #[test]
+    fn test_most_common_ties_keep_first_seen_order() {
+        use crate::rcomp;
+
+        let v = rcomp![most_common; for c in "abcbcad".chars() => c];
+        assert_eq!(v, vec![('a', 2), ('b', 2), ('c', 2), ('d', 1)]);
+        let v = rcomp![most_common 1; for c in "abcbcdd".chars() => c, if c != 'b'];
+        assert_eq!(v, vec![('c', 2)]);
+        assert!(rcomp![most_common 5; for c in "".chars() => c].is_empty());
+    }
+
+    #[cfg(feature = "either")]
+    #[test]
+    fn test_partition_either() {
+        use crate::rcomp;
+        use either::Either;
+
+        let (small, big): (Vec<_>, String) = rcomp![partition (_, _);
+            for x in 0..6 => if x < 3 { Either::Left(x) } else { Either::Right('x') }, if x != 1
+        ];
+        assert_eq!(small, vec![0, 2]);
+        assert_eq!(big, "xxx");
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_shuffled_with_seeded_rng_is_deterministic() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let a = super::shuffled_with(0..100, &mut StdRng::seed_from_u64(7));
+        let b = super::shuffled_with(0..100, &mut StdRng::seed_from_u64(7));
+        assert_eq!(a, b);
+        let mut sorted = a.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,96 @@
+/*!
+Dependency-free parallelism for the [`rcomp!`](crate::rcomp) macro, used
+by the `threads n` modifier.
+
+The outermost source is split into `n` chunks, and each chunk runs the rest
+of the comprehension on its own [scoped thread](std::thread::scope). The
+results are stitched back together in the original order, so the output is
+the same as the sequential comprehension. This is nowhere near as clever as
+a work-stealing pool like `rayon`, but it doesn't need any dependencies.
+*/
+
+use std::thread;
+
+/// Splits `iter` into `n` chunks of (nearly) equal size and runs `f` on each
+/// one in its own scoped thread. The outputs are yielded in the same order
+/// as the chunks.
+///
+/// The whole source is collected before any threads are spawned, so this
+/// won't work with infinite iterators.
+///
+/// This is what `rcomp![threads n; ...]` expands to, where `f` is the
+/// rest of the comprehension.
+///
+/// # Panics
+///
+/// Panics if `n` is zero, or if `f` panics on any of the threads.
+///
+/// # Example
+///
+/// ```rust
+/// # use rustcomp::rcomp;
+/// let matrix = vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]];
+/// let v = rcomp![threads 2; Vec<_>; for row in &matrix, x in row => x * 2, if x % 2 == 1];
+/// assert_eq!(v, vec![2, 6, 10, 14, 18]);
+/// ```
+pub fn scoped<I, F, U>(n: usize, iter: I, f: F) -> impl Iterator<Item = U>
+where
+    I: IntoIterator,
+    I::Item: Send,
+    F: Fn(Vec<I::Item>) -> Vec<U> + Sync,
+    U: Send,
+{
+    assert!(n > 0, "a comprehension needs at least one thread");
+    let items = iter.into_iter().collect::<Vec<_>>();
+    let chunk_size = items.len().div_ceil(n).max(1);
+    let mut items = items.into_iter();
+    let chunks = (0..n)
+        .map(|_| items.by_ref().take(chunk_size).collect::<Vec<_>>())
+        .take_while(|chunk| !chunk.is_empty())
+        .collect::<Vec<_>>();
+    let f = &f;
+    let results = thread::scope(|s| {
+        let handles = chunks
+            .into_iter()
+            .map(|chunk| s.spawn(move || f(chunk)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e))
+            })
+            .collect::<Vec<_>>()
+    });
+    results.into_iter().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rcomp;
+
+    #[test]
+    fn test_threads_preserves_order() {
+        let expected = rcomp![Vec<_>; for x in 0..1000 => x * 3, if x % 7 != 0];
+        for n in [1, 2, 3, 8, 2000] {
+            let actual = rcomp![threads n; Vec<_>; for x in 0..1000 => x * 3, if x % 7 != 0];
+            assert_eq!(expected, actual, "{n} threads");
+        }
+    }
+
+    #[test]
+    fn test_threads_with_clauses_and_empty_source() {
+        let v = vec![vec![1, 2, 2], vec![], vec![3, 1, 4]];
+        let actual = rcomp![threads 2; Vec<_>; for row in &v, x in row => *x, unique by |x| *x];
+        assert_eq!(actual, vec![1, 2, 3, 4]);
+        let empty: Vec<i32> = rcomp![threads 4; Vec<_>; for x in Vec::<i32>::new() => x];
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one thread")]
+    fn test_zero_threads_panics() {
+        let _ = rcomp![threads 0; for x in 0..10 => x].count();
+    }
+}
@@ -0,0 +1,119 @@
+/*!
+[`tracing`] instrumentation for the [`rcomp!`](crate::rcomp) macro, used by
+the `trace "name"` modifier. Requires the `tracing` feature.
+
+A traced comprehension runs inside an `rcomp` span with the given name.
+Once it's done, the span's `scanned`, `filtered`, and `yielded` fields are
+recorded and a `DEBUG` event is emitted with the same counts:
+
+- `scanned` is the number of items that reached the guard, i.e. the items
+  produced by the innermost `for-in` clause.
+- `filtered` is the number of those items that the guard rejected.
+- `yielded` is the number of items that came out the other end, after any
+  trailing clauses.
+
+Because the counts are only known once the iterator has been consumed, a
+traced comprehension needs a collection type or a terminal; the bare
+iterator form doesn't compile.
+*/
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tracing::{field, span, Span};
+
+use crate::report::Probe;
+
+/// Keeps the span and counters for a traced comprehension. This is created
+/// by `rcomp![trace "name"; ...]` and shouldn't usually be used directly.
+#[derive(Debug)]
+pub struct Tracer {
+    span: Span,
+    scanned: AtomicUsize,
+    rejected: AtomicUsize,
+    yielded: AtomicUsize,
+}
+
+impl Tracer {
+    /// Creates a new `INFO` level `rcomp` span with the given name.
+    pub fn new(name: &str) -> Self {
+        let span = tracing::info_span!(
+            "rcomp",
+            name,
+            scanned = field::Empty,
+            filtered = field::Empty,
+            yielded = field::Empty,
+        );
+        Self {
+            span,
+            scanned: AtomicUsize::new(0),
+            rejected: AtomicUsize::new(0),
+            yielded: AtomicUsize::new(0),
+        }
+    }
+
+    /// Enters the span, exiting it again when the guard is dropped.
+    pub fn enter(&self) -> span::Entered<'_> {
+        self.span.enter()
+    }
+
+    /// Returns the `(scanned, filtered, yielded)` counts so far.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        (
+            self.scanned.load(Ordering::Relaxed),
+            self.rejected.load(Ordering::Relaxed),
+            self.yielded.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Records the counts on the span and emits a `DEBUG` event with them.
+    pub fn finish(&self) {
+        let (scanned, filtered, yielded) = self.counts();
+        self.span.record("scanned", scanned);
+        self.span.record("filtered", filtered);
+        self.span.record("yielded", yielded);
+        tracing::debug!(
+            parent: &self.span,
+            scanned,
+            filtered,
+            yielded,
+            "comprehension finished"
+        );
+    }
+}
+
+impl Probe for Tracer {
+    fn scanned(&self) {
+        self.scanned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn yielded(&self) {
+        self.yielded.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tracer;
+    use crate::rcomp;
+
+    #[test]
+    fn test_trace_counts() {
+        let tracer = Tracer::new("test");
+        let v = vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]];
+        let actual = rcomp![Vec<_>; @probe [&tracer] for row in &v, x in row => x % 4, if x % 2 == 1, unique by |x| *x];
+        assert_eq!(actual, vec![1, 3]);
+        assert_eq!(tracer.counts(), (9, 4, 2));
+    }
+
+    #[test]
+    fn test_trace_with_report_and_threads() {
+        let (v, report) = rcomp![trace "threaded"; report; threads 3; Vec<_>; for x in 0..100 => x, if x % 2 == 0];
+        assert_eq!(v, (0..100).step_by(2).collect::<Vec<_>>());
+        assert_eq!(report.produced, vec![100]);
+        assert_eq!(report.rejected, 50);
+    }
+}